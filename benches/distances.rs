@@ -0,0 +1,279 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rayon::ThreadPoolBuilder;
+use std::hint::black_box;
+
+use distle::indexed::IndexedFasta;
+use distle::processing::{
+    calculate_distance, compute_distances, read_and_parse_fasta, DistanceOptions, OutputMode,
+    ParseOptions,
+};
+use distle::types::{HomogeneousMatrix, InputFormat, InputMatrix, MinHashSketch, SupportedTypeVec};
+
+// A small deterministic generator so the benchmarks don't need a rand dependency
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize
+    }
+
+    fn value(&mut self, input_format: InputFormat) -> String {
+        match input_format {
+            InputFormat::Fasta
+            | InputFormat::FastaAll
+            | InputFormat::FastaSketch
+            | InputFormat::Phylip => (b"ACGT"[self.next() % 4] as char).to_string(),
+            InputFormat::Protein => (b"ACDEFGHIKLMNPQRSTVWY"[self.next() % 20] as char).to_string(),
+            InputFormat::Cgmlst => (self.next() % 50 + 1).to_string(),
+            InputFormat::CgmlstHash => format!("{:040x}", self.next() % 50 + 1),
+            InputFormat::Binary => (self.next() % 2).to_string(),
+            InputFormat::Distances => unreachable!("distances are not computed"),
+        }
+    }
+}
+
+fn random_row(input_format: InputFormat, length: usize, rng: &mut Lcg) -> Vec<String> {
+    (0..length).map(|_| rng.value(input_format)).collect()
+}
+
+/// Copies the row and changes roughly one in `every` positions
+fn mutate(row: &[String], input_format: InputFormat, every: usize, rng: &mut Lcg) -> Vec<String> {
+    row.iter()
+        .map(|v| {
+            if rng.next().is_multiple_of(every) {
+                rng.value(input_format)
+            } else {
+                v.clone()
+            }
+        })
+        .collect()
+}
+
+fn to_row(input_format: InputFormat, values: &[String]) -> SupportedTypeVec {
+    match input_format {
+        InputFormat::Fasta | InputFormat::Phylip => {
+            SupportedTypeVec::Nucleotide(values.iter().map(|v| v.as_bytes()[0].into()).collect())
+        }
+        InputFormat::FastaAll => {
+            SupportedTypeVec::NucleotideAll(values.iter().map(|v| v.as_bytes()[0].into()).collect())
+        }
+        InputFormat::FastaSketch => {
+            let seq: Vec<u8> = values.iter().map(|v| v.as_bytes()[0]).collect();
+            SupportedTypeVec::Sketch(MinHashSketch::from_sequence(&seq, 21, 1000, 0))
+        }
+        InputFormat::Protein => {
+            SupportedTypeVec::AminoAcid(values.iter().map(|v| v.as_bytes()[0].into()).collect())
+        }
+        InputFormat::Cgmlst => {
+            SupportedTypeVec::Cgmlst(values.iter().map(|v| v.parse().unwrap()).collect())
+        }
+        InputFormat::CgmlstHash => {
+            SupportedTypeVec::SHA1Hash(values.iter().map(|v| v.parse().unwrap()).collect())
+        }
+        InputFormat::Binary => {
+            SupportedTypeVec::Binary(values.iter().map(|v| v.parse().unwrap()).collect())
+        }
+        InputFormat::Distances => unreachable!("distances are not computed"),
+    }
+}
+
+fn bench_pairwise(c: &mut Criterion) {
+    let mut rng = Lcg(42);
+    let options = DistanceOptions::default();
+    let mut group = c.benchmark_group("pairwise");
+    for (name, input_format, length) in [
+        ("nucleotide", InputFormat::Fasta, 1_000_000),
+        ("nucleotide_all", InputFormat::FastaAll, 1_000_000),
+        ("protein", InputFormat::Protein, 300_000),
+        ("sketch", InputFormat::FastaSketch, 1_000_000),
+        ("cgmlst", InputFormat::Cgmlst, 3_000),
+        ("cgmlst_hash", InputFormat::CgmlstHash, 3_000),
+        ("binary", InputFormat::Binary, 10_000),
+    ] {
+        let values = random_row(input_format, length, &mut rng);
+        let row1 = to_row(input_format, &values);
+        let row2 = to_row(input_format, &mutate(&values, input_format, 100, &mut rng));
+        group.bench_function(name, |b| {
+            b.iter(|| calculate_distance(black_box(&row1), black_box(&row2), &options))
+        });
+    }
+    group.finish();
+}
+
+fn bench_matrix(c: &mut Criterion) {
+    let mut rng = Lcg(7);
+    let reference = random_row(InputFormat::Cgmlst, 3_000, &mut rng);
+    let data_map: InputMatrix = (0..1000)
+        .map(|i| {
+            let values = mutate(&reference, InputFormat::Cgmlst, 20, &mut rng);
+            (format!("sample{}", i), to_row(InputFormat::Cgmlst, &values))
+        })
+        .collect();
+    let data_map = HomogeneousMatrix::try_from(data_map).unwrap();
+    let options = DistanceOptions::default();
+
+    let mut group = c.benchmark_group("matrix");
+    group.sample_size(10);
+    group.bench_function("cgmlst_1000x3000", |b| {
+        b.iter(|| {
+            compute_distances(
+                black_box(&data_map),
+                &options,
+                OutputMode::LowerTriangle,
+                None,
+                None,
+            )
+            .count()
+        })
+    });
+    group.finish();
+}
+
+/// Computes a cgmlst-hash matrix with the default comparison, which uses SIMD with the simd
+/// feature, and with the scalar comparison that a minimum of 0 shared loci falls back to. Run it
+/// with `--features simd` to see the speedup.
+fn bench_hash_matrix(c: &mut Criterion) {
+    let mut rng = Lcg(5);
+    let reference = random_row(InputFormat::CgmlstHash, 3_000, &mut rng);
+    let data_map: InputMatrix = (0..300)
+        .map(|i| {
+            let values = mutate(&reference, InputFormat::CgmlstHash, 20, &mut rng);
+            (
+                format!("sample{}", i),
+                to_row(InputFormat::CgmlstHash, &values),
+            )
+        })
+        .collect();
+    let data_map = HomogeneousMatrix::try_from(data_map).unwrap();
+
+    let mut group = c.benchmark_group("cgmlst_hash_300x3000");
+    group.sample_size(10);
+    for (name, min_shared) in [("default", None), ("scalar", Some(0))] {
+        let options = DistanceOptions {
+            min_shared,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compute_distances(
+                    black_box(&data_map),
+                    &options,
+                    OutputMode::LowerTriangle,
+                    None,
+                    None,
+                )
+                .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Computes a nucleotide alignment matrix with the bit-packed comparison of the default options,
+/// and with the unpacked comparison of the nucleotides one by one that a minimum of 0 shared
+/// positions falls back to. The matrix is scaled down from 5000 x 50 kb to 200 x 50 kb, which
+/// has 628 times fewer pairs, so a run takes seconds instead of hours.
+fn bench_nucleotide_matrix(c: &mut Criterion) {
+    let mut rng = Lcg(3);
+    let reference = random_row(InputFormat::Fasta, 50_000, &mut rng);
+    let data_map: InputMatrix = (0..200)
+        .map(|i| {
+            let values = mutate(&reference, InputFormat::Fasta, 20, &mut rng);
+            (format!("sample{}", i), to_row(InputFormat::Fasta, &values))
+        })
+        .collect();
+    let data_map = HomogeneousMatrix::try_from(data_map).unwrap();
+
+    let mut group = c.benchmark_group("nucleotide_200x50000");
+    group.sample_size(10);
+    for (name, min_shared) in [("packed", None), ("unpacked", Some(0))] {
+        let options = DistanceOptions {
+            min_shared,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compute_distances(
+                    black_box(&data_map),
+                    &options,
+                    OutputMode::LowerTriangle,
+                    None,
+                    None,
+                )
+                .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Computes the same matrix with growing thread pools, in memory and in the low memory mode,
+/// to show how the work is spread over the threads. Pools with more threads than the available
+/// cores measure the overhead of the extra threads instead of a speedup.
+fn bench_threads(c: &mut Criterion) {
+    let mut rng = Lcg(11);
+    let reference = random_row(InputFormat::Fasta, 10_000, &mut rng);
+    let fasta: String = (0..200)
+        .map(|i| {
+            let values = mutate(&reference, InputFormat::Fasta, 20, &mut rng);
+            format!(">sample{}\n{}\n", i, values.concat())
+        })
+        .collect();
+    let path = std::env::temp_dir().join("distle_bench_threads.fasta");
+    std::fs::write(&path, &fasta).unwrap();
+
+    let parse_options = ParseOptions::default();
+    let data_map =
+        read_and_parse_fasta(fasta.as_bytes(), InputFormat::Fasta, &parse_options).unwrap();
+    let indexed =
+        IndexedFasta::open(path.to_str().unwrap(), InputFormat::Fasta, &parse_options).unwrap();
+    let options = DistanceOptions::default();
+
+    let mut group = c.benchmark_group("threads");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8, 16, 32, 64] {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        group.bench_function(BenchmarkId::new("in_memory", threads), |b| {
+            b.iter(|| {
+                pool.install(|| {
+                    compute_distances(
+                        black_box(&data_map),
+                        &options,
+                        OutputMode::LowerTriangle,
+                        None,
+                        None,
+                    )
+                    .count()
+                })
+            })
+        });
+        group.bench_function(BenchmarkId::new("low_memory", threads), |b| {
+            b.iter(|| {
+                pool.install(|| {
+                    black_box(&indexed)
+                        .compute_distances(&options, OutputMode::LowerTriangle, None, None)
+                        .count()
+                })
+            })
+        });
+    }
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(
+    benches,
+    bench_pairwise,
+    bench_matrix,
+    bench_hash_matrix,
+    bench_nucleotide_matrix,
+    bench_threads
+);
+criterion_main!(benches);
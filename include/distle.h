@@ -0,0 +1,46 @@
+/* The C interface of distle, available when it is built with the ffi feature. See src/ffi.rs for
+ * the documentation of the functions. */
+#ifndef DISTLE_H
+#define DISTLE_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DISTLE_OK 0
+#define DISTLE_ERROR_NULL_POINTER 1
+#define DISTLE_ERROR_INVALID_ARGUMENT 2
+#define DISTLE_ERROR_INPUT 3
+#define DISTLE_ERROR_BUFFER_TOO_SMALL 4
+#define DISTLE_ERROR_PANIC 5
+
+#define DISTLE_FORMAT_CGMLST 0
+#define DISTLE_FORMAT_CGMLST_HASH 1
+#define DISTLE_FORMAT_FASTA 2
+#define DISTLE_FORMAT_FASTA_ALL 3
+#define DISTLE_FORMAT_PROTEIN 4
+#define DISTLE_FORMAT_FASTA_SKETCH 5
+#define DISTLE_FORMAT_PHYLIP 6
+#define DISTLE_FORMAT_BINARY 7
+
+typedef struct DistleMatrix DistleMatrix;
+
+const char *distle_last_error(void);
+
+int distle_matrix_load(const char *path, int input_format, DistleMatrix **out);
+void distle_matrix_free(DistleMatrix *matrix);
+size_t distle_matrix_len(const DistleMatrix *matrix);
+const char *distle_matrix_sample_id(const DistleMatrix *matrix, size_t index);
+
+/* Writes the n x n distances in row-major order, with NaN for undefined distances. A maxdist of
+ * 0 computes all distances exactly. */
+int distle_compute_distances(const DistleMatrix *matrix, size_t maxdist, double *buffer,
+                             size_t buffer_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,269 @@
+//! A C-compatible interface to load an input and compute its distance matrix. Every function
+//! returns one of the `DISTLE_*` codes, and the message of the last error of the calling thread
+//! is available from [`distle_last_error`]. Panics are caught and returned as
+//! [`DISTLE_ERROR_PANIC`], so they never unwind into the caller.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::processing::{
+    compute_distances, decompress, read_and_parse_fasta, read_and_parse_phylip,
+    read_and_parse_tabular, DistanceOptions, OutputMode, ParseOptions,
+};
+use crate::types::{HomogeneousMatrix, InputFormat};
+
+pub const DISTLE_OK: c_int = 0;
+/// A pointer argument was null
+pub const DISTLE_ERROR_NULL_POINTER: c_int = 1;
+/// An argument was out of range, like an unknown input format or a path that is not UTF-8
+pub const DISTLE_ERROR_INVALID_ARGUMENT: c_int = 2;
+/// The input could not be read or parsed
+pub const DISTLE_ERROR_INPUT: c_int = 3;
+/// The buffer is smaller than the number of samples squared
+pub const DISTLE_ERROR_BUFFER_TOO_SMALL: c_int = 4;
+/// distle panicked, which is a bug
+pub const DISTLE_ERROR_PANIC: c_int = 5;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A parsed input with the sample IDs as C strings
+pub struct DistleMatrix {
+    data_map: HomogeneousMatrix,
+    ids: Vec<CString>,
+}
+
+/// Stores the message as the last error and returns the code
+fn fail(code: c_int, message: impl Into<String>) -> c_int {
+    let message = CString::new(message.into().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+/// Runs the function and turns a panic into an error code
+fn guard(f: impl FnOnce() -> c_int) -> c_int {
+    catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| fail(DISTLE_ERROR_PANIC, "distle panicked"))
+}
+
+/// The input formats in the order of [`InputFormat`], starting at 0 for cgmlst
+fn input_format(value: c_int) -> Option<InputFormat> {
+    match value {
+        0 => Some(InputFormat::Cgmlst),
+        1 => Some(InputFormat::CgmlstHash),
+        2 => Some(InputFormat::Fasta),
+        3 => Some(InputFormat::FastaAll),
+        4 => Some(InputFormat::Protein),
+        5 => Some(InputFormat::FastaSketch),
+        6 => Some(InputFormat::Phylip),
+        7 => Some(InputFormat::Binary),
+        _ => None,
+    }
+}
+
+fn load(path: &str, input_format: InputFormat) -> Result<DistleMatrix, Box<dyn Error>> {
+    let reader = decompress(BufReader::new(File::open(path)?))?;
+    let options = ParseOptions::default();
+    let data_map = match input_format {
+        InputFormat::Cgmlst | InputFormat::CgmlstHash | InputFormat::Binary => {
+            read_and_parse_tabular(reader, input_format, &options)?
+        }
+        InputFormat::Phylip => read_and_parse_phylip(reader, &options)?,
+        _ => read_and_parse_fasta(reader, input_format, &options)?,
+    };
+    let ids = data_map
+        .iter()
+        .map(|(id, _)| CString::new(id.as_str()))
+        .collect::<Result<_, _>>()?;
+    Ok(DistleMatrix { data_map, ids })
+}
+
+/// Returns the message of the last error of this thread, or null if there was none. The string
+/// stays valid until the next call that fails on this thread.
+#[no_mangle]
+pub extern "C" fn distle_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Loads the input file with the default parse options and stores the matrix in `out`. The
+/// input format is the index of the format in the order cgmlst, cgmlst-hash, fasta, fasta-all,
+/// protein, fasta-sketch, phylip and binary. The matrix must be freed with
+/// [`distle_matrix_free`].
+///
+/// # Safety
+///
+/// `path` must be null or a valid NUL-terminated string, and `out` must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn distle_matrix_load(
+    path: *const c_char,
+    input_format: c_int,
+    out: *mut *mut DistleMatrix,
+) -> c_int {
+    guard(|| {
+        if path.is_null() || out.is_null() {
+            return fail(
+                DISTLE_ERROR_NULL_POINTER,
+                "The path and output must not be null",
+            );
+        }
+        let Ok(path) = CStr::from_ptr(path).to_str() else {
+            return fail(DISTLE_ERROR_INVALID_ARGUMENT, "The path is not valid UTF-8");
+        };
+        let Some(format) = self::input_format(input_format) else {
+            return fail(
+                DISTLE_ERROR_INVALID_ARGUMENT,
+                format!("Unknown input format {}", input_format),
+            );
+        };
+        match load(path, format) {
+            Ok(matrix) => {
+                *out = Box::into_raw(Box::new(matrix));
+                DISTLE_OK
+            }
+            Err(e) => fail(DISTLE_ERROR_INPUT, e.to_string()),
+        }
+    })
+}
+
+/// Frees a matrix from [`distle_matrix_load`]. Null is ignored.
+///
+/// # Safety
+///
+/// `matrix` must be null or a matrix from [`distle_matrix_load`] that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn distle_matrix_free(matrix: *mut DistleMatrix) {
+    if !matrix.is_null() {
+        drop(Box::from_raw(matrix));
+    }
+}
+
+/// Returns the number of samples of the matrix, or 0 for null
+///
+/// # Safety
+///
+/// `matrix` must be null or a valid matrix from [`distle_matrix_load`].
+#[no_mangle]
+pub unsafe extern "C" fn distle_matrix_len(matrix: *const DistleMatrix) -> usize {
+    matrix.as_ref().map_or(0, |matrix| matrix.ids.len())
+}
+
+/// Returns the ID of the sample at the index in input order, or null if the index is out of
+/// range. The string is owned by the matrix and valid until it is freed.
+///
+/// # Safety
+///
+/// `matrix` must be null or a valid matrix from [`distle_matrix_load`].
+#[no_mangle]
+pub unsafe extern "C" fn distle_matrix_sample_id(
+    matrix: *const DistleMatrix,
+    index: usize,
+) -> *const c_char {
+    matrix
+        .as_ref()
+        .and_then(|matrix| matrix.ids.get(index))
+        .map_or(ptr::null(), |id| id.as_ptr())
+}
+
+/// Computes the full distance matrix into `buffer` in row-major order, so the distance between
+/// samples `i` and `j` is at `i * n + j`. Undefined distances are NaN. A `maxdist` above 0 stops
+/// counting at that distance like the maxdist flag, so those distances are written as `maxdist`.
+///
+/// # Safety
+///
+/// `matrix` must be null or a valid matrix from [`distle_matrix_load`], and `buffer` must be null
+/// or valid for writes of `buffer_len` doubles.
+#[no_mangle]
+pub unsafe extern "C" fn distle_compute_distances(
+    matrix: *const DistleMatrix,
+    maxdist: usize,
+    buffer: *mut f64,
+    buffer_len: usize,
+) -> c_int {
+    guard(|| {
+        let Some(matrix) = matrix.as_ref() else {
+            return fail(DISTLE_ERROR_NULL_POINTER, "The matrix must not be null");
+        };
+        if buffer.is_null() {
+            return fail(DISTLE_ERROR_NULL_POINTER, "The buffer must not be null");
+        }
+        let n = matrix.ids.len();
+        let needed = match n.checked_mul(n) {
+            Some(needed) if needed <= buffer_len => needed,
+            _ => {
+                return fail(
+                    DISTLE_ERROR_BUFFER_TOO_SMALL,
+                    format!("The buffer must hold {} x {} distances", n, n),
+                )
+            }
+        };
+        let buffer = std::slice::from_raw_parts_mut(buffer, needed);
+        let options = DistanceOptions {
+            maxdist: (maxdist > 0).then_some(maxdist),
+            ..Default::default()
+        };
+        let distances = compute_distances(&matrix.data_map, &options, OutputMode::Full, None, None);
+        for (slot, (_, _, dist)) in buffer.iter_mut().zip(distances) {
+            *slot = dist.as_f64().unwrap_or(f64::NAN);
+        }
+        DISTLE_OK
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_distances() {
+        let path = std::env::temp_dir().join("distle_test_ffi.fasta");
+        std::fs::write(&path, b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n").unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut matrix = ptr::null_mut();
+            assert_eq!(
+                distle_matrix_load(c_path.as_ptr(), 2, &mut matrix),
+                DISTLE_OK
+            );
+            assert_eq!(distle_matrix_len(matrix), 3);
+            let id = CStr::from_ptr(distle_matrix_sample_id(matrix, 1));
+            assert_eq!(id.to_str().unwrap(), "S2");
+            assert!(distle_matrix_sample_id(matrix, 3).is_null());
+
+            let mut buffer = [0.0; 9];
+            assert_eq!(
+                distle_compute_distances(matrix, 0, buffer.as_mut_ptr(), 8),
+                DISTLE_ERROR_BUFFER_TOO_SMALL
+            );
+            assert!(!distle_last_error().is_null());
+            assert_eq!(
+                distle_compute_distances(matrix, 0, buffer.as_mut_ptr(), buffer.len()),
+                DISTLE_OK
+            );
+            assert_eq!(buffer, [0.0, 1.0, 2.0, 1.0, 0.0, 1.0, 2.0, 1.0, 0.0]);
+            assert_eq!(
+                distle_compute_distances(ptr::null(), 0, buffer.as_mut_ptr(), buffer.len()),
+                DISTLE_ERROR_NULL_POINTER
+            );
+            distle_matrix_free(matrix);
+
+            assert_eq!(
+                distle_matrix_load(c_path.as_ptr(), 8, &mut matrix),
+                DISTLE_ERROR_INVALID_ARGUMENT
+            );
+            let missing = CString::new("/nonexistent/distle.fasta").unwrap();
+            assert_eq!(
+                distle_matrix_load(missing.as_ptr(), 2, &mut matrix),
+                DISTLE_ERROR_INPUT
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::ops::Range;
+
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::processing::{
+    calculate_distance, check_duplicate_ids, check_fasta_start, header_id, is_excluded,
+    normalize_ids, precomputed_distance, Distance, DistanceOptions, OutputMode, ParseOptions,
+    SequenceParser,
+};
+use crate::types::{InputFormat, SupportedTypeVec};
+
+/// The minimum number of pairs that are computed together in the low memory mode
+pub const CHUNK_PAIRS: usize = 1 << 14;
+
+/// The ID of a record and the byte range of its sequence
+type Record = (String, Range<usize>);
+
+/// A memory-mapped FASTA file with the offsets of its records. Sequences are parsed when a
+/// comparison needs them, so only the rows that are being compared are kept in memory.
+pub struct IndexedFasta {
+    mmap: Mmap,
+    records: Vec<Record>,
+    parser: SequenceParser,
+}
+
+impl IndexedFasta {
+    pub fn open(
+        path: &str,
+        input_format: InputFormat,
+        options: &ParseOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        if matches!(
+            input_format,
+            InputFormat::Cgmlst
+                | InputFormat::CgmlstHash
+                | InputFormat::Binary
+                | InputFormat::Phylip
+        ) {
+            return Err("The low memory mode only supports FASTA input".into());
+        }
+        let parser = SequenceParser::new(input_format, options)?;
+        let file = File::open(path)?;
+        // Safety: the file must not be changed while distle runs
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut records = index_records(&mmap)?;
+        for (id, _) in &mut records {
+            *id = header_id(id, options)?;
+        }
+        normalize_ids(records.iter_mut().map(|(id, _)| id), options.normalize_ids);
+
+        let indexed = IndexedFasta {
+            mmap,
+            records,
+            parser,
+        };
+        if !options.allow_duplicate_ids {
+            check_duplicate_ids(&indexed.sample_ids())?;
+        }
+        Ok(indexed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the sample IDs in input order
+    pub fn sample_ids(&self) -> Vec<&str> {
+        self.records.iter().map(|(id, _)| id.as_str()).collect()
+    }
+
+    /// Parses the sequence of the record, leaving out the line breaks
+    pub fn row(&self, index: usize) -> SupportedTypeVec {
+        let seq: Vec<u8> = self.mmap[self.records[index].1.clone()]
+            .iter()
+            .copied()
+            .filter(|&b| b != b'\n' && b != b'\r')
+            .collect();
+        self.parser.parse(&seq)
+    }
+
+    /// Computes the distances in the same order as [`crate::processing::compute_distances`].
+    /// The rows are computed in chunks with at least [`CHUNK_PAIRS`] pairs, and all pairs of a
+    /// chunk in parallel, so the threads stay busy even for the short rows at the top of the lower
+    /// triangle. Only the distances of a single chunk are kept in memory.
+    pub fn compute_distances<'a>(
+        &'a self,
+        options: &'a DistanceOptions,
+        output_mode: OutputMode,
+        already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
+        excluded: Option<&'a HashSet<(&'a str, &'a str)>>,
+    ) -> impl Iterator<Item = (&'a str, &'a str, Distance)> + 'a {
+        let len = self.len();
+        let row_len = move |i| output_mode.row_len(i, len);
+
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            if start >= len {
+                return None;
+            }
+            let mut end = start;
+            let mut chunk_pairs = 0;
+            while end < len && (end == start || chunk_pairs < CHUNK_PAIRS) {
+                chunk_pairs += row_len(end);
+                end += 1;
+            }
+
+            let chunk: Vec<_> = (start..end)
+                .into_par_iter()
+                .flat_map(|i| {
+                    let id1 = self.records[i].0.as_str();
+                    let row1 = self.row(i);
+                    (0..row_len(i)).into_par_iter().filter_map(move |j| {
+                        let id2 = self.records[j].0.as_str();
+                        if is_excluded(excluded, id1, id2) {
+                            return None;
+                        }
+                        let dist = already_computed
+                            .and_then(|distances| precomputed_distance(distances, id1, id2))
+                            .map(Distance::Exact)
+                            .unwrap_or_else(|| calculate_distance(&row1, &self.row(j), options));
+                        Some((id1, id2, dist))
+                    })
+                })
+                .collect();
+            start = end;
+            Some(chunk)
+        })
+        .flatten()
+    }
+}
+
+/// Finds the ID and the byte range of the sequence of every record
+fn index_records(data: &[u8]) -> Result<Vec<Record>, Box<dyn Error>> {
+    check_fasta_start(data)?;
+    let mut records: Vec<Record> = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i);
+
+        if data[pos] == b'>' {
+            if let Some((_, range)) = records.last_mut() {
+                range.end = pos;
+            }
+            let header = std::str::from_utf8(&data[pos + 1..line_end])?;
+            let id = header.split_whitespace().next().unwrap_or("");
+            let seq_start = (line_end + 1).min(data.len());
+            records.push((id.to_string(), seq_start..data.len()));
+        } else if records.is_empty() && !data[pos..line_end].trim_ascii().is_empty() {
+            return Err("Expected a FASTA record starting with '>'".into());
+        }
+        pos = line_end + 1;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::{compute_distances, read_and_parse_fasta};
+
+    #[test]
+    fn test_index_records() {
+        let data = b">S1 description\nAC\nGT\n>S2\r\nACGA\r\n>S3\n";
+        let records = index_records(data).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                ("S1".to_string(), 16..22),
+                ("S2".to_string(), 27..33),
+                ("S3".to_string(), 37..37),
+            ]
+        );
+        assert!(index_records(b"ACGT\n>S1\nACGT\n").is_err());
+    }
+
+    #[test]
+    fn test_indexed_distances() {
+        let path = std::env::temp_dir().join("distle_test_indexed_distances.fasta");
+        let data = b">S1\nACGT\nACGT\n>S2\nACGA\nACGT\n>S3\nTCGA\nAC-A\n";
+        std::fs::write(&path, data).unwrap();
+
+        for input_format in [InputFormat::Fasta, InputFormat::FastaAll] {
+            let options = ParseOptions::default();
+            let indexed =
+                IndexedFasta::open(path.to_str().unwrap(), input_format, &options).unwrap();
+            let data_map = read_and_parse_fasta(&data[..], input_format, &options).unwrap();
+            let distance_options = DistanceOptions::default();
+
+            for output_mode in [
+                OutputMode::LowerTriangle,
+                OutputMode::Full,
+                OutputMode::LowerTriangleDiag,
+            ] {
+                let expected: Vec<_> =
+                    compute_distances(&data_map, &distance_options, output_mode, None, None)
+                        .collect();
+                let result: Vec<_> = indexed
+                    .compute_distances(&distance_options, output_mode, None, None)
+                    .collect();
+                assert_eq!(result, expected);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,2 +1,8 @@
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod indexed;
 pub mod processing;
+pub mod selftest;
+#[cfg(feature = "simd")]
+pub mod simd;
 pub mod types;
@@ -1,29 +1,53 @@
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
-use std::time::Instant;
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use env_logger::Env;
-use log::{debug, info};
+use log::{debug, info, warn};
+use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 
-mod processing;
-mod types;
-
-use processing::{
-    compute_distances, read_and_parse_fasta, read_and_parse_tabular,
-    read_and_parse_tabular_distances, write_distances_to_file, OutputFormat, OutputMode,
+use distle::indexed::IndexedFasta;
+use distle::processing::{
+    append_distances_to_cache, base_frequencies, complete_deletion_mask, compute_distances,
+    compute_distances_in_chunks, compute_nearest_neighbors, compute_pair_distances, decompress_as,
+    differing_positions, distance_histogram, json_string, missing_statistics, nearest_neighbors,
+    parse_separator, read_and_parse_fasta, read_and_parse_matrix_distances, read_and_parse_phylip,
+    read_and_parse_tabular, read_and_parse_tabular_distances, read_binary_distances,
+    read_cost_matrix, read_excluded_pairs, read_loci, read_locus_names, read_pairs,
+    read_sample_order, read_weights, read_written_pairs, reference_pairs, remove_identical_columns,
+    reorder_samples, sample_ids, select_columns, validate_rows, write_binned_distances,
+    write_distances_to_file, write_distances_with_compared, write_histogram, write_parsed_fasta,
+    write_sample_order, ComparedColumns, Distance, DistanceOptions, DistanceTable, IdNormalization,
+    IdPairs, InputCompression, InputSeparator, MeanDistances, Model, OutputFormat, OutputMode,
+    OutputOptions, ParseOptions, PrecomputedFormat,
+};
+use distle::selftest::self_test;
+use distle::types::{
+    ComparisonPolicy, Deletion, HomogeneousMatrix, InferredPolicy, InputFormat, InputMatrix,
+    MissingPolicy, SupportedTypeVec,
 };
-use types::InputFormat;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true
+)]
 struct Cli {
-    /// The input file or '-' for stdin.
-    input: String,
+    /// The input files or '-' for stdin. Multiple files are combined into one matrix and must share the same input format.
+    #[arg(required = true, num_args = 1..)]
+    input: Vec<String>,
 
     /// The output file or '-' for stdout.
+    // The default only fills in the field for a subcommand, since the argument is required otherwise
+    #[arg(required = true, default_value = "-", hide_default_value = true)]
     output: String,
 
     /// The format of the input file.
@@ -34,54 +58,1551 @@ struct Cli {
     #[arg(value_enum, short = 'o', long, default_value = "tabular")]
     output_format: OutputFormat,
 
-    /// A file with precomputed distances that don't have to be calculated again. The file should be in tabular long format and have the separator as specified by the output-sep flag.
+    /// A file with precomputed distances that don't have to be calculated again. The file should be in the layout given by the precomputed-format flag and have the separator as specified by the precomputed-sep flag.
     #[arg(long)]
     precomputed_distances: Option<String>,
 
-    /// The separator character for the input file. Relevant for tabular input files.
-    #[arg(long, default_value = "\t")]
-    input_sep: char,
+    /// Return an error instead of a warning when the precomputed distances file contains the same pair with different distances.
+    #[arg(long)]
+    symmetric_check: bool,
+
+    /// Append the newly computed distances to the precomputed distances file. Only for the tabular precomputed format.
+    #[arg(long, requires = "precomputed_distances")]
+    update_cache: bool,
+
+    /// The layout of the precomputed distances file.
+    #[arg(value_enum, long, default_value = "tabular")]
+    precomputed_format: PrecomputedFormat,
+
+    /// The separator character for the precomputed distances file. Defaults to the output separator.
+    #[arg(long)]
+    precomputed_sep: Option<char>,
 
-    /// The separator character for the output file.
+    /// The size in KB of the read buffer of every input file and the write buffer of the output files. Larger buffers read and write fewer, larger blocks, which helps on storage with a high latency like network mounts.
+    #[arg(long, value_name = "KB", default_value_t = 8)]
+    buffer_size: usize,
+
+    /// Compression of the input: auto (detect from magic bytes), none, gzip, bzip2, xz or zstd
+    #[arg(long, value_enum, default_value_t = InputCompression::Auto)]
+    input_compression: InputCompression,
+
+    /// The separator character for the input file, or 'auto' to detect it from the first data line of every file as the one of tab, comma and semicolon that gives the most fields. The weights, cost matrix and excluded pairs files are detected the same way. Relevant for tabular input files.
     #[arg(long, default_value = "\t")]
-    output_sep: char,
+    input_sep: InputSeparator,
+
+    /// The k-mer length of the sketches. Relevant for the fasta-sketch input format.
+    #[arg(long, default_value = "21")]
+    sketch_k: usize,
+
+    /// The number of hashes in the sketches. Relevant for the fasta-sketch input format.
+    #[arg(long, default_value = "1000")]
+    sketch_size: usize,
+
+    /// The seed of the k-mer hashes of the sketches. Sketches only depend on the input and the seed, not on the machine or the number of threads, and only sketches with the same seed can be compared. Relevant for the fasta-sketch input format.
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Parse tabular input as CSV, so fields may be quoted and contain the input separator.
+    #[arg(long)]
+    csv: bool,
+
+    /// The separator for the output file. It can have more than one character, and '\t' and '\\' are decoded as a tab and a backslash.
+    #[arg(long, default_value = "\t", value_parser = parse_separator)]
+    output_sep: String,
+
+    /// Write a header line as the first line of the output. Relevant for tabular output. For matrix output the first header name is used as the top-left cell.
+    #[arg(long)]
+    output_header: bool,
+
+    /// The column names of the header line, separated by commas.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        default_value = "id1,id2,distance"
+    )]
+    header_names: Vec<String>,
+
+    /// Truncate or pad sample names to 10 characters as required by strict Phylip. By default relaxed Phylip with full names is written. For Phylip input the names are read from the first 10 characters of the line.
+    #[arg(long)]
+    phylip_strict: bool,
 
     /// The output mode.
     #[arg(value_enum, short = 'm', long, default_value = "lower-triangle")]
     output_mode: OutputMode,
 
-    /// If set, distance calculations will be stopped when this distance is reached. Useful for large datasets.
+    /// If set, distance calculations will be stopped when this distance is reached. Useful for large datasets. Distances that reach it are written as '>=MAXDIST' in tabular and matrix output, so use one more than the largest distance you need exactly. A warning is logged for the matrix output formats, since trees built from capped distances are wrong.
     #[arg(short = 'd', long, default_value = None)]
     maxdist: Option<usize>,
 
+    /// A file with pairs of sample IDs that are not compared and left out of the output, one pair per line separated by the input separator. The order of the IDs in a pair does not matter. Only for tabular output.
+    #[arg(long)]
+    exclude_pairs: Option<String>,
+
+    /// A file with the pairs of sample IDs to compare, one pair per line separated by the input separator. Only these pairs are computed and written, in the order of the file. Only for tabular output, and not with excluded pairs, nearest neighbors, resume, low-memory or a cache update.
+    #[arg(long, value_name = "PATH")]
+    pairs: Option<String>,
+
+    /// Only compute the distances of this sample to all other samples, written in input order with the reference as the first ID. This is far cheaper than the whole matrix. Only for tabular output, and not with excluded pairs, nearest neighbors, resume, low-memory or a cache update.
+    #[arg(long, value_name = "ID", conflicts_with = "pairs")]
+    reference: Option<String>,
+
+    /// List the positions where the two samples differ, with the values of both samples, instead of computing distances. Not for the fasta-sketch and distances input formats, low-memory, complete deletion or drop-invariant.
+    #[arg(long, num_args = 2, value_names = ["ID1", "ID2"], conflicts_with_all = ["pairs", "reference"])]
+    explain: Option<Vec<String>>,
+
+    /// Only write the K nearest neighbors of every sample, and the neighbors that tie with the K-th nearest one, sorted by distance. Self-comparisons and undefined distances are left out. Only for tabular output.
+    #[arg(long, value_name = "K")]
+    knn: Option<usize>,
+
+    /// Sort the written pairs by the first sample ID and then by the second, so outputs can be compared across runs and tools. All distances are kept in memory until they are sorted. Only for tabular output, and not with resume or low-memory.
+    #[arg(long)]
+    sort_output: bool,
+
+    /// Only write the pairs with a distance at or below this cutoff. Relevant for tabular output. Unlike maxdist the distances are still calculated exactly; when both are set, keep maxdist above the cutoff, otherwise the distances that reach maxdist are written as maxdist.
+    #[arg(long)]
+    max_output_dist: Option<f64>,
+
+    /// Write the number of pairs at every distance instead of the distances, one distance and its count per line. The capped distances of maxdist and the undefined distances are counted on separate lines. Only for distances that are counts in the tabular output format.
+    #[arg(long, visible_alias = "count-only")]
+    histogram: bool,
+
+    /// Write the mean distance of every sample to the other samples to this file, one sample and its mean per line in input order. Self-comparisons and undefined distances are left out, and distances that reach maxdist count as maxdist. The means are taken over all computed pairs, also with knn or a cutoff.
+    #[arg(long, value_name = "FILE")]
+    with_means: Option<String>,
+
+    /// Write the tabular output to one file per distance bin instead, named PREFIX followed by 'upto' and the upper bound of the bin, 'above' and the last bound, or 'undefined' for the undefined distances. The output file then lists the bin files and their number of pairs. Requires the bins flag.
+    #[arg(long, value_name = "PREFIX", requires = "bins")]
+    bin_output: Option<String>,
+
+    /// The upper bounds of the distance bins, separated by commas and in increasing order. A distance goes to the first bin whose bound it does not exceed, so '0,5,20' gives the bins 0, up to 5, up to 20 and above 20. Relevant for bin-output. Distances that reach maxdist are binned as maxdist.
+    #[arg(long, value_delimiter = ',', requires = "bin_output")]
+    bins: Vec<f64>,
+
+    /// Characters that are treated as gaps instead of being compared literally. Relevant for the fasta-all input format.
+    #[arg(long, default_value = "")]
+    gap_chars: String,
+
+    /// Characters that are treated as missing instead of being compared literally. Relevant for the fasta-all input format.
+    #[arg(long, default_value = "")]
+    missing_chars: String,
+
+    /// Compare bases case sensitively, so soft-masked (lowercase) bases differ from uppercase ones. The gap and missing characters are then also matched exactly. By default case is ignored. Relevant for the fasta-all input format.
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// How positions that are missing in one of the two samples are compared.
+    #[arg(value_enum, long, default_value = "skip")]
+    missing_policy: MissingPolicy,
+
+    /// Count a missing allele as a difference with a called allele, so missing only matches missing. For hash input this counts loci that are present in one sample and absent in the other. Shorthand for '--missing-policy count'. Relevant for the cgmlst input formats.
+    #[arg(long, visible_alias = "count-presence-absence")]
+    cgmlst_strict: bool,
+
+    /// How inferred alleles, written as 'INF-' followed by the allele number, are compared. Relevant for the cgmlst input format.
+    #[arg(value_enum, long, default_value = "match")]
+    inferred_policy: InferredPolicy,
+
+    /// Count gaps as differences with concrete bases, while still matching other gaps and N. Relevant for the fasta and phylip input formats. For the protein input format gaps and stops are counted as differences with residues, while still matching themselves and X.
+    #[arg(long)]
+    gaps_as_diff: bool,
+
+    /// Leave out the leading and trailing positions where either sample of a pair has a gap, so ragged ends padded with gaps are not counted, even with gaps-as-diff. Internal gaps are compared as usual. Relevant for the fasta and phylip input formats.
+    #[arg(long)]
+    ignore_terminal_gaps: bool,
+
+    /// Only count differences where both positions are one of A, C, G and T, and treat ambiguous bases and gaps as missing, also for min-shared. This gives a conservative SNP distance. Relevant for the fasta and phylip input formats.
+    #[arg(long, conflicts_with = "gaps_as_diff")]
+    strict_acgt: bool,
+
+    /// Count the SNPs, the positions where both samples have one of A, C, G and T and they differ. Shorthand for '--strict-acgt' with the default missing policy. Relevant for the fasta and phylip input formats.
+    #[arg(long, conflicts_with_all = ["gaps_as_diff", "missing_policy", "cgmlst_strict"])]
+    snp_distance: bool,
+
+    /// Which positions are compared, those that are non-missing in both samples of a pair or in all samples. Complete deletion is not supported for the fasta-sketch and binary input formats or in the low memory mode.
+    #[arg(long, value_enum, default_value_t = Deletion::Pairwise)]
+    deletion: Deletion,
+
+    /// Remove positions where all samples are identical or missing before comparing
+    #[arg(long)]
+    drop_invariant: bool,
+
+    /// The minimum number of positions that must be non-missing in both samples. If fewer positions are shared, the distance is undefined.
+    #[arg(long)]
+    min_shared: Option<usize>,
+
+    /// A file with the loci to compare, one per line. A locus is given by its header name, which requires the header line of the input, or by its index starting at 0 for the first column after the ID. Relevant for the cgmlst and binary input formats.
+    #[arg(long)]
+    loci: Option<String>,
+
+    /// A file with a locus and its weight on every line. Loci that are not listed have a weight of 1. Relevant for the cgmlst and binary input formats.
+    #[arg(long)]
+    weights: Option<String>,
+
+    /// A file with a locus, two allele numbers and the cost of a difference between them on every line. Other differences cost 1. Relevant for the cgmlst input format.
+    #[arg(long, value_name = "FILE", conflicts_with = "weights")]
+    cost_matrix: Option<String>,
+
+    /// The number of decimals of distances that are not counts, like model estimates and weighted distances. By default they are written in the shortest form, which is an integer for whole numbers.
+    #[arg(long)]
+    precision: Option<usize>,
+
+    /// The value written instead of the distance when it is undefined.
+    #[arg(long, default_value = "NA")]
+    undefined_value: String,
+
+    /// Take the sample ID from this field of the FASTA header, starting at 0, for structured headers like '>locus|strain|date'. The header is cut at the first whitespace before it is split. Relevant for the FASTA input formats.
+    #[arg(long, value_name = "N")]
+    id_field: Option<usize>,
+
+    /// The delimiter of the header fields of id-field.
+    #[arg(long, default_value = "|", requires = "id_field")]
+    id_delimiter: char,
+
+    /// The model used to compute the distances.
+    #[arg(value_enum, long, visible_alias = "metric", default_value = "hamming")]
+    model: Model,
+
+    /// Leave out the comparisons of a sample with itself in the full and lower-triangle-diag output modes. Relevant for tabular output, the other formats always have a diagonal.
+    #[arg(long)]
+    no_self: bool,
+
+    /// Also write the distance of every sample with itself in the lower-triangle output mode, which is the same as the lower-triangle-diag output mode. Relevant for tabular output.
+    #[arg(long, conflicts_with = "no_self")]
+    include_diagonal: bool,
+
     /// Number of threads to use. If not set, all available threads will be used.
     #[arg(short = 't', long, default_value = None)]
     threads: Option<usize>,
 
+    /// Number of threads that read and decompress the input files, separate from the threads that compute the distances. Each input file is read by one thread, so this only helps with multiple input files.
+    #[arg(long, default_value = "2")]
+    threads_io: usize,
+
+    /// Allow rows of tabular input with a different number of loci than the first row. Only the loci that both samples of a pair have are compared.
+    #[arg(long)]
+    allow_ragged: bool,
+
+    /// Return an error if a row of tabular input does not have this number of loci, counted after the loci flag. Checked for every input file, so tables of different scheme versions are not compared misaligned.
+    #[arg(long, value_name = "N")]
+    expect_loci: Option<usize>,
+
+    /// Pad rows with fewer loci than expect-loci with missing alleles at the end instead of returning an error. Rows with more loci are still an error. Requires expect-loci. Not for the binary input format, which has no missing values.
+    #[arg(long, requires = "expect_loci")]
+    pad_missing: bool,
+
+    /// Trim the whitespace around the sample IDs when they are read. This applies to the IDs of the input, the precomputed distances and the excluded pairs alike, so they match each other and the output.
+    #[arg(long)]
+    normalize_ids: bool,
+
+    /// Lowercase the sample IDs when they are read, after trimming them like normalize-ids.
+    #[arg(long)]
+    lowercase_ids: bool,
+
+    /// Allow the same sample ID to occur more than once, within one input file or across several.
+    #[arg(long)]
+    allow_duplicate_ids: bool,
+
+    /// Write an empty output when the input has no samples instead of returning an error. The tabular, matrix and condensed output are then empty, and the Phylip, NEXUS and binary output only have their headers with 0 samples.
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Read tabular input with the loci as rows and the samples as columns. The header line holds the sample IDs. The whole table is kept in memory as text before it is parsed.
+    #[arg(long)]
+    transpose_input: bool,
+
     /// Skip the header line of the input file. Relevant for tabular input files.
     #[arg(short = 's', long)]
     skip_header: bool,
 
+    /// Parse the sequences on demand from the memory-mapped input instead of keeping all of them in memory. Trades computation time for memory. Only for a single FASTA input file.
+    #[arg(long)]
+    low_memory: bool,
+
+    /// The maximum memory in MB. If the memory estimated from the input files and precomputed distances is above it, the low memory mode is used when possible, and otherwise distle stops with an error.
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<u64>,
+
+    /// Write the metadata of the run to this file as JSON: the distle version, the command line, the input format, the input files with an FNV-1a hash of their bytes, the number of samples and positions, the number of threads, maxdist and the elapsed time. The input files are read again for the hash, and stdin has no hash.
+    #[arg(long, value_name = "PATH")]
+    metadata: Option<String>,
+
+    /// Write the sample IDs in input order, one per line, to this file. This is the order of the rows and columns of the output, which is the order of the order flag when it is given.
+    #[arg(long)]
+    write_order: Option<String>,
+
+    /// A file with the sample IDs in the order of the output, one per line like the file of write-order. Missing and unknown IDs are an error unless order-tolerant is given. Not for the distances input format or low-memory.
+    #[arg(long, value_name = "FILE")]
+    order: Option<String>,
+
+    /// Put the samples that are not in the order file last in input order, and skip the IDs of the order file that are not in the input.
+    #[arg(long, requires = "order")]
+    order_tolerant: bool,
+
+    /// Write the parsed sequences back as FASTA to this file, one line per sequence, to check how the input was read. Nucleotides are written as their IUPAC codes, so ambiguous bases read as N appear as N, and characters that are missing in the fasta-all input format as '?'. Only for the sequence input formats, and not with low-memory.
+    #[arg(long)]
+    emit_parsed: Option<String>,
+
+    /// Write tabular output in chunks that are flushed as soon as they are computed. If the output file already exists, the pairs in it are kept and only the missing pairs are computed and appended, so an interrupted run can be continued. Use it for the first run as well.
+    #[arg(long)]
+    resume: bool,
+
+    /// Append to the output file instead of overwriting it (tabular and NDJSON only)
+    #[arg(long)]
+    append: bool,
+
+    /// Swap the two sample ID columns in tabular and NDJSON output
+    #[arg(long)]
+    transpose_output: bool,
+
+    /// Write the number of compared positions as an extra column
+    #[arg(long)]
+    with_compared: bool,
+
+    /// Write the distance as a fraction of the compared positions as an extra column
+    #[arg(long)]
+    with_normalized: bool,
+
+    /// Keep parsing after a line of tabular input or a FASTA record that cannot be parsed or a row with another number of loci, log every such error with its line number, and exit with an error at the end if there were any. Not for transposed tabular input or low-memory.
+    #[arg(long)]
+    collect_errors: bool,
+
+    /// Check that the input parses, that there are no duplicate sample IDs and that all samples have the same number of positions, without computing any distances. All problems are listed, and the exit code is nonzero if there are any.
+    #[arg(long)]
+    validate_only: bool,
+
+    /// Parse the input and report the number of samples, positions and comparisons without computing any distances.
+    #[arg(long)]
+    dry_run: bool,
+
     /// Enable verbose mode. Outputs debug messages and calculation times.
     #[arg(short = 'v', long)]
     verbose: bool,
+
+    /// Only log errors, so nothing but real problems is written to stderr.
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let opts: Cli = Cli::parse();
-    if opts.verbose {
-        env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compute the distances of a small random input, write them in every output format and check that the formats that can be read back give the same distances. Useful to check a build on a new platform.
+    Selftest,
+}
+
+/// Reads the input files in parallel in a separate pool of I/O threads, so the reading of one file
+/// can wait on slow storage while another is parsed. The results are in input order.
+fn read_inputs(
+    opts: &Cli,
+    parse_options: &ParseOptions,
+) -> Result<Vec<Result<HomogeneousMatrix, String>>, Box<dyn Error>> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(opts.threads_io)
+        .thread_name(|i| format!("distle-io-{}", i))
+        .build()?;
+    Ok(pool.install(|| {
+        opts.input
+            .par_iter()
+            .map(|input| read_input(input, opts, parse_options).map_err(|e| e.to_string()))
+            .collect()
+    }))
+}
+
+/// Reads the names of the loci from the header line of the first input file, so the weights and
+/// the cost matrix can refer to loci by name. Input from stdin cannot be read a second time, so
+/// its loci can only be given by index.
+fn read_first_locus_names(
+    opts: &Cli,
+    parse_options: &ParseOptions,
+) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let Some(input) = opts.input.first().filter(|input| *input != "-") else {
+        return Ok(None);
+    };
+    let mut reader = decompress_as(
+        BufReader::with_capacity(buffer_capacity(opts), std::fs::File::open(input)?),
+        opts.input_compression,
+    )?;
+    let parse_options = &ParseOptions {
+        separator: opts.input_sep.resolve(&mut reader, opts.skip_header)?,
+        ..parse_options.clone()
+    };
+    read_locus_names(reader, parse_options)
+}
+
+fn read_input(
+    input: &str,
+    opts: &Cli,
+    parse_options: &ParseOptions,
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let reader: Box<dyn Read> = if input == "-" {
+        Box::new(stdin())
     } else {
-        env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+        Box::new(std::fs::File::open(input)?)
+    };
+
+    let mut reader = decompress_as(
+        BufReader::with_capacity(buffer_capacity(opts), reader),
+        opts.input_compression,
+    )?;
+    let parse_options = &ParseOptions {
+        separator: opts.input_sep.resolve(&mut reader, opts.skip_header)?,
+        ..parse_options.clone()
+    };
+
+    match opts.input_format {
+        InputFormat::Fasta
+        | InputFormat::FastaAll
+        | InputFormat::Protein
+        | InputFormat::FastaSketch => {
+            read_and_parse_fasta(reader, opts.input_format, parse_options)
+        }
+        InputFormat::Cgmlst | InputFormat::CgmlstHash | InputFormat::Binary => {
+            read_and_parse_tabular(reader, opts.input_format, parse_options)
+        }
+        InputFormat::Phylip => read_and_parse_phylip(reader, parse_options),
+        InputFormat::Distances => Err("The distances input format has no samples to parse".into()),
     }
+}
 
-    let reader: Box<dyn Read> = if opts.input == "-" {
-        Box::new(stdin())
+/// The number of lines the output will have. For tabular output filtered by a cutoff and for the
+/// histogram this is an upper bound, and for nearest neighbors ties can add more lines.
+fn estimated_output_lines(samples: usize, comparisons: usize, opts: &Cli) -> usize {
+    match opts.output_format {
+        // Every distance below maxdist, the capped and the undefined distances
+        OutputFormat::Tabular if opts.histogram => {
+            comparisons.min(opts.maxdist.map_or(usize::MAX, |maxdist| maxdist + 2))
+                + usize::from(opts.output_header)
+        }
+        OutputFormat::Tabular | OutputFormat::Ndjson => {
+            let lines = match opts.knn {
+                Some(k) => samples * k.min(samples.saturating_sub(1)),
+                None => comparisons,
+            };
+            let header = opts.output_header && opts.output_format == OutputFormat::Tabular;
+            lines + usize::from(header)
+        }
+        OutputFormat::Phylip | OutputFormat::Matrix => samples + 1,
+        // The taxa and distances blocks around the labels and the matrix rows
+        OutputFormat::Nexus => 2 * samples + 14,
+        OutputFormat::Condensed => comparisons,
+        // The binary output has no lines
+        OutputFormat::Binary => 0,
+    }
+}
+
+/// Whether the distances are numbers of differences, and not model estimates, weighted sums or
+/// sketch estimates
+fn distances_are_counts(opts: &Cli) -> bool {
+    opts.model == Model::Hamming
+        && opts.weights.is_none()
+        && opts.cost_matrix.is_none()
+        && opts.input_format != InputFormat::FastaSketch
+}
+
+/// The distance to stop counting at. Pairs above the output cutoff are not written, so their
+/// differences only have to be counted until they exceed the cutoff. This is skipped when all
+/// distances are used, for means or the cache, and for distances that are not counts.
+fn effective_maxdist(opts: &Cli) -> Option<usize> {
+    match opts.max_output_dist {
+        Some(cutoff)
+            if distances_are_counts(opts)
+                && cutoff >= 0.0
+                && opts.with_means.is_none()
+                && !opts.update_cache =>
+        {
+            let bound = cutoff.floor() as usize + 1;
+            Some(opts.maxdist.map_or(bound, |maxdist| maxdist.min(bound)))
+        }
+        _ => opts.maxdist,
+    }
+}
+
+/// Whether the nearest neighbors can be found with the distance of the k-th nearest neighbor so
+/// far as a bound. This needs distances that are counts, and the means and the cache need all
+/// distances.
+fn bounds_neighbors(opts: &Cli) -> bool {
+    distances_are_counts(opts) && opts.with_means.is_none() && !opts.update_cache
+}
+
+/// The separator of the precomputed distances file, which defaults to the output separator
+fn precomputed_sep(opts: &Cli) -> Result<char, Box<dyn Error>> {
+    if let Some(sep) = opts.precomputed_sep {
+        return Ok(sep);
+    }
+    let mut chars = opts.output_sep.chars();
+    match (chars.next(), chars.next()) {
+        (Some(sep), None) => Ok(sep),
+        _ => Err(
+            "Set the precomputed separator when the output separator has more than one character"
+                .into(),
+        ),
+    }
+}
+
+/// Reads all input files and lists the problems of the input instead of stopping at the first one
+fn run_validate(opts: &Cli, parse_options: &ParseOptions) -> Result<(), Box<dyn Error>> {
+    // Duplicates and ragged rows are reported together with the other problems
+    let parse_options = ParseOptions {
+        allow_duplicate_ids: true,
+        allow_ragged: true,
+        ..parse_options.clone()
+    };
+    let mut problems = Vec::new();
+    let mut data_map = InputMatrix::new();
+    for (input, file_data) in opts.input.iter().zip(read_inputs(opts, &parse_options)?) {
+        match file_data {
+            Ok(file_data) => data_map.extend(file_data.into_inner()),
+            Err(err) => problems.push(format!("{}: {}", input, err)),
+        }
+    }
+    problems.extend(validate_rows(&data_map, opts.allow_duplicate_ids));
+
+    if problems.is_empty() {
+        let positions = data_map.first().map_or(0, |(_, row)| row.len());
+        println!(
+            "The input is valid: {} samples with {} positions",
+            data_map.len(),
+            positions
+        );
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    Err(format!("Found {} problems in the input", problems.len()).into())
+}
+
+/// Returns an error for an input without samples, unless an empty output is allowed
+fn check_not_empty(samples: usize, opts: &Cli) -> Result<(), Box<dyn Error>> {
+    if samples > 0 {
+        return Ok(());
+    }
+    if !opts.allow_empty {
+        return Err("The input has no samples. Use allow-empty to write an empty output".into());
+    }
+    warn!("The input has no samples, writing an empty output");
+    Ok(())
+}
+
+/// The number of pairs that are computed before they are written in a resumable run
+const CHECKPOINT_PAIRS: usize = 1 << 20;
+
+/// Appends the pairs that are not in the output file yet in chunks, after cutting off a line
+/// that was only partly written
+fn run_resume(
+    opts: &Cli,
+    data_map: &HomogeneousMatrix,
+    distance_options: &DistanceOptions,
+    output_mode: OutputMode,
+    precomputed_distances: &HashMap<(&str, &str), usize>,
+    mut excluded_pairs: IdPairs,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&opts.output)?;
+    let (written, complete_len) =
+        read_written_pairs(BufReader::new(&file), &opts.output_sep, opts.output_header)?;
+    file.set_len(complete_len)?;
+    file.seek(SeekFrom::End(0))?;
+    if !written.is_empty() {
+        info!("Resuming after {} pairs in {}", written.len(), &opts.output);
+    }
+    excluded_pairs.extend(written);
+    let excluded_pairs = pair_refs(&excluded_pairs);
+
+    let mut output_options = output_options(opts, output_mode);
+    let header = output_options.header.take().filter(|_| complete_len == 0);
+    let mut header = Some(header);
+    let ids = sample_ids(data_map);
+    let chunks = compute_distances_in_chunks(
+        data_map,
+        distance_options,
+        output_mode,
+        Some(precomputed_distances),
+        Some(&excluded_pairs),
+        CHECKPOINT_PAIRS,
+    );
+    for chunk in chunks {
+        let mut writer = BufWriter::with_capacity(buffer_capacity(opts), &mut file);
+        let chunk_options = OutputOptions {
+            header: header.take().flatten(),
+            ..output_options.clone()
+        };
+        write_distances_to_file(chunk.into_iter(), &mut writer, &ids, &chunk_options)?;
+        writer.flush()?;
+    }
+
+    debug!("Computing + Writing time: {:?}", start.elapsed());
+    info!("Done");
+    Ok(())
+}
+
+fn id_normalization(opts: &Cli) -> Option<IdNormalization> {
+    if opts.lowercase_ids {
+        Some(IdNormalization::Lowercase)
+    } else if opts.normalize_ids {
+        Some(IdNormalization::Trim)
     } else {
-        Box::new(std::fs::File::open(&opts.input)?)
+        None
+    }
+}
+
+/// Reads the pairs that should not be compared, if a file is given
+fn read_excluded(opts: &Cli) -> Result<Option<IdPairs>, Box<dyn Error>> {
+    match &opts.exclude_pairs {
+        Some(pairs_file) => {
+            let mut reader = BufReader::new(std::fs::File::open(pairs_file)?);
+            let separator = opts.input_sep.resolve(&mut reader, false)?;
+            Ok(Some(read_excluded_pairs(
+                reader,
+                separator,
+                id_normalization(opts),
+            )?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn pair_refs(pairs: &IdPairs) -> HashSet<(&str, &str)> {
+    pairs
+        .iter()
+        .map(|(id1, id2)| (id1.as_str(), id2.as_str()))
+        .collect()
+}
+
+/// Selects the distances that are written
+fn output_distances<'a: 'b, 'b>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)> + 'b,
+    samples: usize,
+    opts: &Cli,
+) -> Box<dyn Iterator<Item = (&'a str, &'a str, Distance)> + 'b> {
+    let distances: Box<dyn Iterator<Item = _>> = match opts.knn {
+        Some(k) => Box::new(nearest_neighbors(distances, samples, k)),
+        None => Box::new(distances),
     };
+    sorted_output(distances, opts)
+}
+
+/// Sorts the written distances by their sample IDs if the output is sorted
+fn sorted_output<'a: 'b, 'b>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)> + 'b,
+    opts: &Cli,
+) -> Box<dyn Iterator<Item = (&'a str, &'a str, Distance)> + 'b> {
+    if !opts.sort_output {
+        return Box::new(distances);
+    }
+    let mut distances: Vec<_> = distances.collect();
+    distances.sort_by(|(a1, a2, _), (b1, b2, _)| (a1, a2).cmp(&(b1, b2)));
+    Box::new(distances.into_iter())
+}
+
+fn output_options(opts: &Cli, output_mode: OutputMode) -> OutputOptions {
+    OutputOptions {
+        output_format: opts.output_format,
+        output_mode,
+        output_sep: opts.output_sep.clone(),
+        header: opts.output_header.then(|| opts.header_names.clone()),
+        undefined_value: opts.undefined_value.clone(),
+        phylip_strict: opts.phylip_strict,
+        max_output_dist: opts.max_output_dist,
+        precision: opts.precision,
+        no_self: opts.no_self,
+        transpose: opts.transpose_output,
+    }
+}
+
+/// The names of the files of the distance bins, in the order of the writers of
+/// [`write_binned_distances`]
+fn bin_file_names(prefix: &str, edges: &[f64]) -> Vec<String> {
+    let mut names: Vec<String> = edges
+        .iter()
+        .map(|edge| format!("{}upto{}", prefix, edge))
+        .collect();
+    if let Some(last) = edges.last() {
+        names.push(format!("{}above{}", prefix, last));
+    }
+    names.push(format!("{}undefined", prefix));
+    names
+}
+
+/// Writes the distances to the output file, and the mean distance of every sample to the means
+/// file if one is given
+fn write_output<'a>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    sample_ids: &[&str],
+    opts: &Cli,
+    output_mode: OutputMode,
+    compared: Option<Compared>,
+) -> Result<(), Box<dyn Error>> {
+    // The means are taken over all pairs, before only the nearest neighbors are kept
+    let mut means = MeanDistances::new(output_mode);
+    let distances = distances.inspect(|&(id1, id2, dist)| {
+        if opts.with_means.is_some() {
+            means.add(id1, id2, dist);
+        }
+    });
+    write_distances(
+        output_distances(distances, sample_ids.len(), opts),
+        sample_ids,
+        opts,
+        output_mode,
+        compared,
+    )?;
+    if let Some(means_file) = &opts.with_means {
+        means.write(
+            sample_ids,
+            BufWriter::new(std::fs::File::create(means_file)?),
+            &output_options(opts, output_mode),
+        )?;
+    }
+    Ok(())
+}
 
-    let reader = BufReader::new(reader);
+/// The rows and the comparison policy to count the compared positions of every pair with, and
+/// the columns to write from them
+type Compared<'a> = (&'a HomogeneousMatrix, &'a ComparisonPolicy, ComparedColumns);
+
+/// Writes the distances in the output format. With binned output the distances go to the bin
+/// files and the output file lists them with their number of pairs.
+fn write_distances<'a>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    sample_ids: &[&str],
+    opts: &Cli,
+    output_mode: OutputMode,
+    compared: Option<Compared>,
+) -> Result<(), Box<dyn Error>> {
+    let mut output_options = output_options(opts, output_mode);
+    let writer: Box<dyn Write> = if opts.output == "-" {
+        Box::new(stdout())
+    } else if opts.append {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&opts.output)?;
+        if file.metadata()?.len() > 0 {
+            output_options.header = None;
+        }
+        Box::new(file)
+    } else {
+        Box::new(std::fs::File::create(&opts.output)?)
+    };
+    let mut writer = BufWriter::with_capacity(buffer_capacity(opts), writer);
+
+    if opts.histogram {
+        let histogram = distance_histogram(distances, &output_options)?;
+        write_histogram(&histogram, &mut writer, &output_options)?;
+        writer.flush()?;
+        return Ok(());
+    }
+    let Some(prefix) = &opts.bin_output else {
+        if let Some((data_map, policy, columns)) = compared {
+            return write_distances_with_compared(
+                distances,
+                &mut writer,
+                data_map,
+                policy,
+                columns,
+                &output_options,
+            );
+        }
+        return write_distances_to_file(distances, &mut writer, sample_ids, &output_options);
+    };
+    let names = bin_file_names(prefix, &opts.bins);
+    let mut bin_writers = names
+        .iter()
+        .map(|name| {
+            let file = std::fs::File::create(name)?;
+            Ok(BufWriter::with_capacity(buffer_capacity(opts), file))
+        })
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+    let counts = write_binned_distances(distances, &mut bin_writers, &opts.bins, &output_options)?;
+    for bin_writer in &mut bin_writers {
+        bin_writer.flush()?;
+    }
+    for (name, count) in names.iter().zip(counts) {
+        writeln!(writer, "{}{}{}", name, opts.output_sep, count)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The capacity in bytes of the buffers of the input and output files
+fn buffer_capacity(opts: &Cli) -> usize {
+    opts.buffer_size.saturating_mul(1024)
+}
+
+/// Writes the positions where the two samples of the explain flag differ, with their values
+fn run_explain(
+    data_map: &HomogeneousMatrix,
+    ids: &[String],
+    opts: &Cli,
+    policy: &ComparisonPolicy,
+    parse_options: &ParseOptions,
+) -> Result<(), Box<dyn Error>> {
+    let ids: Vec<String> = ids
+        .iter()
+        .map(|id| id_normalization(opts).map_or_else(|| id.clone(), |n| n.apply(id)))
+        .collect();
+    let mut rows = Vec::new();
+    for id in &ids {
+        let row = data_map
+            .iter()
+            .find(|(sample, _)| sample == id)
+            .map(|(_, row)| row)
+            .ok_or_else(|| format!("The sample {} is not in the input", id))?;
+        rows.push(row);
+    }
+    let (row1, row2) = (rows[0], rows[1]);
+    let positions =
+        differing_positions(row1, row2, policy).ok_or("Sketches have no positions to explain")?;
+
+    let sequences = matches!(
+        opts.input_format,
+        InputFormat::Fasta | InputFormat::FastaAll | InputFormat::Protein | InputFormat::Phylip
+    );
+    let names = if sequences {
+        None
+    } else {
+        read_first_locus_names(opts, parse_options)?
+    };
+    let label = |index: usize| {
+        if sequences {
+            return (index + 1).to_string();
+        }
+        names
+            .as_ref()
+            .and_then(|names| names.get(index).cloned())
+            .unwrap_or_else(|| index.to_string())
+    };
+
+    let writer: Box<dyn Write> = if opts.output == "-" {
+        Box::new(stdout())
+    } else {
+        Box::new(std::fs::File::create(&opts.output)?)
+    };
+    let mut writer = BufWriter::with_capacity(buffer_capacity(opts), writer);
+    let sep = &opts.output_sep;
+    if opts.output_header {
+        let position = if sequences { "position" } else { "locus" };
+        writeln!(writer, "{}{}{}{}{}", position, sep, ids[0], sep, ids[1])?;
+    }
+    for &index in &positions {
+        let value = |row: &SupportedTypeVec| row.value_at(index).unwrap_or_else(|| "-".into());
+        writeln!(
+            writer,
+            "{}{}{}{}{}",
+            label(index),
+            sep,
+            value(row1),
+            sep,
+            value(row2)
+        )?;
+    }
+    writer.flush()?;
+    info!(
+        "Samples {} and {} differ at {} positions",
+        ids[0],
+        ids[1],
+        positions.len()
+    );
+    Ok(())
+}
+
+/// Reads the distances of the input files and writes them in the output format without
+/// computing any
+fn run_convert(
+    opts: &Cli,
+    parse_options: &ParseOptions,
+    output_mode: OutputMode,
+    run_start: Instant,
+) -> Result<(), Box<dyn Error>> {
+    let mut table = DistanceTable::default();
+    for input in &opts.input {
+        let reader: Box<dyn Read> = if input == "-" {
+            Box::new(stdin())
+        } else {
+            Box::new(std::fs::File::open(input)?)
+        };
+        let mut reader = decompress_as(
+            BufReader::with_capacity(buffer_capacity(opts), reader),
+            opts.input_compression,
+        )?;
+        let parse_options = &ParseOptions {
+            separator: opts.input_sep.resolve(&mut reader, opts.skip_header)?,
+            ..parse_options.clone()
+        };
+        table.read(
+            reader,
+            parse_options,
+            &opts.undefined_value,
+            opts.symmetric_check,
+        )?;
+    }
+    let sample_ids = table.sample_ids();
+    info!("Read the distances of {} samples", sample_ids.len());
+    check_not_empty(sample_ids.len(), opts)?;
+
+    write_output(
+        table.distances(output_mode),
+        &sample_ids,
+        opts,
+        output_mode,
+        None,
+    )?;
+    write_metadata(opts, sample_ids.len(), 0, run_start)?;
+    info!("Done");
+    Ok(())
+}
+
+/// The 64-bit FNV-1a hash of the bytes of the file
+fn fnv1a_file(path: &str) -> Result<u64, Box<dyn Error>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut hash: u64 = 0xcbf29ce484222325;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(hash);
+        }
+        for &byte in buffer {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+        let len = buffer.len();
+        reader.consume(len);
+    }
+}
+
+/// Logs the missing statistics of the positions over all pairs in verbose mode. Sketches have no
+/// positions, so nothing is logged for them.
+fn report_missing_statistics<R: Borrow<SupportedTypeVec>>(
+    rows: impl IntoIterator<Item = R>,
+    opts: &Cli,
+    policy: &ComparisonPolicy,
+) {
+    if !opts.verbose || opts.input_format == InputFormat::FastaSketch {
+        return;
+    }
+    let statistics = missing_statistics(rows, policy);
+    debug!("Total positions: {}", statistics.positions);
+    debug!(
+        "Average positions missing in either sample of a pair: {:.2}",
+        statistics.mean_missing
+    );
+    debug!(
+        "Average positions compared per pair: {:.2}",
+        statistics.mean_compared
+    );
+}
+
+/// Writes the metadata of the run as JSON to the metadata file, if there is one
+fn write_metadata(
+    opts: &Cli,
+    samples: usize,
+    positions: usize,
+    run_start: Instant,
+) -> Result<(), Box<dyn Error>> {
+    let Some(metadata_file) = &opts.metadata else {
+        return Ok(());
+    };
+    let command_line: Vec<String> = std::env::args().map(|arg| json_string(&arg)).collect();
+    let inputs = opts
+        .input
+        .iter()
+        .map(|input| {
+            let hash = match input.as_str() {
+                "-" => "null".to_string(),
+                _ => format!("\"{:016x}\"", fnv1a_file(input)?),
+            };
+            Ok(format!(
+                "{{\"path\": {}, \"fnv1a64\": {}}}",
+                json_string(input),
+                hash
+            ))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let input_format = opts.input_format.to_possible_value().map_or_else(
+        || format!("{:?}", opts.input_format),
+        |v| v.get_name().into(),
+    );
+
+    let mut writer = BufWriter::new(std::fs::File::create(metadata_file)?);
+    writeln!(writer, "{{")?;
+    writeln!(
+        writer,
+        "  \"version\": {},",
+        json_string(env!("CARGO_PKG_VERSION"))
+    )?;
+    writeln!(writer, "  \"command_line\": [{}],", command_line.join(", "))?;
+    writeln!(
+        writer,
+        "  \"input_format\": {},",
+        json_string(&input_format)
+    )?;
+    writeln!(writer, "  \"inputs\": [{}],", inputs.join(", "))?;
+    writeln!(writer, "  \"samples\": {},", samples)?;
+    writeln!(writer, "  \"positions\": {},", positions)?;
+    writeln!(writer, "  \"threads\": {},", rayon::current_num_threads())?;
+    match opts.maxdist {
+        Some(maxdist) => writeln!(writer, "  \"maxdist\": {},", maxdist)?,
+        None => writeln!(writer, "  \"maxdist\": null,")?,
+    }
+    writeln!(
+        writer,
+        "  \"elapsed_seconds\": {:.3}",
+        run_start.elapsed().as_secs_f64()
+    )?;
+    writeln!(writer, "}}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The approximate memory of a precomputed distance: the two IDs and the distance in the map that
+/// is read, and the borrowed copy of that map
+const PRECOMPUTED_PAIR_BYTES: u64 = 128;
+
+/// Counts the lines of the file without keeping them
+fn count_lines(path: &str) -> Result<u64, Box<dyn Error>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut lines = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(lines);
+        }
+        lines += buffer.iter().filter(|&&b| b == b'\n').count() as u64;
+        let len = buffer.len();
+        reader.consume(len);
+    }
+}
+
+/// Estimates the memory in bytes of the parsed input and the precomputed distances. The parsed
+/// rows are never larger than the text they are parsed from, so the size of the input files is
+/// an upper bound for them.
+fn estimated_memory(opts: &Cli) -> Result<u64, Box<dyn Error>> {
+    let mut bytes = 0;
+    for input in opts.input.iter().filter(|input| *input != "-") {
+        bytes += std::fs::metadata(input)?.len();
+    }
+    if let Some(precomputed_distances_file) = &opts.precomputed_distances {
+        let pairs = match opts.precomputed_format {
+            PrecomputedFormat::Tabular => count_lines(precomputed_distances_file)?,
+            // Both halves of the matrix are stored as one pair
+            PrecomputedFormat::Matrix => count_lines(precomputed_distances_file)?.pow(2) / 2,
+            PrecomputedFormat::Binary => std::fs::metadata(precomputed_distances_file)?.len() / 4,
+        };
+        bytes += pairs * PRECOMPUTED_PAIR_BYTES;
+    }
+    Ok(bytes)
+}
+
+/// Whether the low memory mode supports the input and options
+fn low_memory_supported(opts: &Cli) -> bool {
+    matches!(opts.input.as_slice(), [input] if input != "-")
+        && matches!(
+            opts.input_format,
+            InputFormat::Fasta
+                | InputFormat::FastaAll
+                | InputFormat::Protein
+                | InputFormat::FastaSketch
+        )
+        && opts.precomputed_distances.is_none()
+        && opts.emit_parsed.is_none()
+        && !opts.dry_run
+        && !opts.resume
+        && !opts.sort_output
+        && opts.deletion == Deletion::Pairwise
+        && !opts.drop_invariant
+        && opts.order.is_none()
+        && opts.explain.is_none()
+}
+
+/// Returns whether the low memory mode has to be used to stay below the maximum memory, or an
+/// error if the estimate is above the maximum and the low memory mode cannot be used
+fn check_max_memory(opts: &Cli) -> Result<bool, Box<dyn Error>> {
+    let Some(max_memory) = opts.max_memory else {
+        return Ok(false);
+    };
+    if opts.low_memory {
+        return Ok(false);
+    }
+    let estimate = estimated_memory(opts)?.div_ceil(1 << 20);
+    debug!("Estimated memory: {} MB", estimate);
+    if estimate <= max_memory {
+        return Ok(false);
+    }
+    if !low_memory_supported(opts) {
+        return Err(format!(
+            "The estimated memory of {} MB is above the maximum of {} MB, and the low memory mode does not support the input and options",
+            estimate, max_memory
+        )
+        .into());
+    }
+    warn!(
+        "The estimated memory of {} MB is above the maximum of {} MB, using the low memory mode",
+        estimate, max_memory
+    );
+    Ok(true)
+}
+
+/// Computes the distances from a memory-mapped input file that is parsed on demand
+fn run_low_memory(
+    opts: &Cli,
+    parse_options: &ParseOptions,
+    distance_options: &DistanceOptions,
+    output_mode: OutputMode,
+    run_start: Instant,
+) -> Result<(), Box<dyn Error>> {
+    let [input] = opts.input.as_slice() else {
+        return Err("The low memory mode needs exactly one input file".into());
+    };
+    if input == "-" {
+        return Err("The low memory mode cannot read from stdin".into());
+    }
+    if opts.precomputed_distances.is_some() || opts.dry_run {
+        return Err(
+            "The low memory mode cannot be combined with precomputed distances or a dry run".into(),
+        );
+    }
+    if opts.emit_parsed.is_some() {
+        return Err("The low memory mode cannot write the parsed sequences".into());
+    }
+
+    let start = Instant::now();
+    let indexed = IndexedFasta::open(input, opts.input_format, parse_options)?;
+    debug!("Indexing time: {:?}", start.elapsed());
+    check_not_empty(indexed.len(), opts)?;
+    let distance_options = &DistanceOptions {
+        base_frequencies: (opts.model == Model::Tn93)
+            .then(|| base_frequencies((0..indexed.len()).map(|i| indexed.row(i)))),
+        ..*distance_options
+    };
+    let start = Instant::now();
+
+    if let Some(order_file) = &opts.write_order {
+        write_sample_order(
+            &indexed.sample_ids(),
+            BufWriter::new(std::fs::File::create(order_file)?),
+        )?;
+    }
+
+    info!("Computing distances and writing to file: {}", &opts.output);
+    let excluded_pairs = read_excluded(opts)?;
+    let excluded_pairs = excluded_pairs.as_ref().map(pair_refs);
+    let distances =
+        indexed.compute_distances(distance_options, output_mode, None, excluded_pairs.as_ref());
+
+    write_output(distances, &indexed.sample_ids(), opts, output_mode, None)?;
+
+    debug!("Computing + Writing time: {:?}", start.elapsed());
+    report_missing_statistics(
+        (0..indexed.len()).map(|i| indexed.row(i)),
+        opts,
+        &distance_options.policy,
+    );
+    let positions = if indexed.is_empty() {
+        0
+    } else {
+        indexed.row(0).len()
+    };
+    write_metadata(opts, indexed.len(), positions, run_start)?;
+    info!("Done");
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let run_start = Instant::now();
+    let opts: Cli = Cli::parse();
+    let level = if opts.verbose {
+        "debug"
+    } else if opts.quiet {
+        "error"
+    } else {
+        "info"
+    };
+    env_logger::Builder::from_env(Env::default().default_filter_or(level)).init();
+
+    if let Some(Command::Selftest) = opts.command {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let passed = self_test(seed)?;
+        println!(
+            "The self-test passed for the output formats {}",
+            passed.join(", ")
+        );
+        return Ok(());
+    }
+
+    let nucleotides = matches!(opts.input_format, InputFormat::Fasta | InputFormat::Phylip);
+    match opts.model {
+        Model::K80 | Model::Tn93 if !nucleotides => {
+            return Err(
+                "Substitution models are only supported for the fasta and phylip input formats"
+                    .into(),
+            );
+        }
+        Model::Jaccard if opts.input_format != InputFormat::Binary => {
+            return Err(
+                "The Jaccard distance is only supported for the binary input format".into(),
+            );
+        }
+        Model::Jaccard if opts.weights.is_some() => {
+            return Err("The Jaccard distance cannot be combined with weights".into());
+        }
+        _ => {}
+    }
+
+    if opts.weights.is_some()
+        && !matches!(
+            opts.input_format,
+            InputFormat::Cgmlst | InputFormat::CgmlstHash | InputFormat::Binary
+        )
+    {
+        return Err("Weights are only supported for the cgmlst and binary input formats".into());
+    }
+
+    if opts.cost_matrix.is_some() && opts.input_format != InputFormat::Cgmlst {
+        return Err("The cost matrix is only supported for the cgmlst input format".into());
+    }
+
+    if opts.cgmlst_strict
+        && !matches!(
+            opts.input_format,
+            InputFormat::Cgmlst | InputFormat::CgmlstHash
+        )
+    {
+        return Err("The strict comparison is only supported for the cgmlst input formats".into());
+    }
+
+    if opts.input_format == InputFormat::Distances
+        && (opts.low_memory
+            || opts.resume
+            || opts.precomputed_distances.is_some()
+            || opts.validate_only
+            || opts.dry_run
+            || opts.maxdist.is_some())
+    {
+        return Err("The distances input format cannot be combined with low-memory, resume, precomputed distances, validate-only, dry-run or maxdist".into());
+    }
+
+    if opts.emit_parsed.is_some()
+        && matches!(
+            opts.input_format,
+            InputFormat::Cgmlst
+                | InputFormat::CgmlstHash
+                | InputFormat::Binary
+                | InputFormat::FastaSketch
+                | InputFormat::Distances
+        )
+    {
+        return Err("Only the sequence input formats can be written as parsed FASTA".into());
+    }
+
+    if opts.inferred_policy != InferredPolicy::Match && opts.input_format != InputFormat::Cgmlst {
+        return Err("The inferred policy is only supported for the cgmlst input format".into());
+    }
+
+    if opts.id_field.is_some()
+        && matches!(
+            opts.input_format,
+            InputFormat::Cgmlst
+                | InputFormat::CgmlstHash
+                | InputFormat::Binary
+                | InputFormat::Phylip
+        )
+    {
+        return Err("The ID field is only supported for the FASTA input formats".into());
+    }
+
+    if (opts.strict_acgt || opts.snp_distance) && !nucleotides {
+        return Err(
+            "The strict ACGT comparison is only supported for the fasta and phylip input formats"
+                .into(),
+        );
+    }
+
+    if opts.ignore_terminal_gaps && !nucleotides {
+        return Err(
+            "Ignoring terminal gaps is only supported for the fasta and phylip input formats"
+                .into(),
+        );
+    }
+
+    if opts.expect_loci.is_some()
+        && !matches!(
+            opts.input_format,
+            InputFormat::Cgmlst | InputFormat::CgmlstHash | InputFormat::Binary
+        )
+    {
+        return Err(
+            "The expected loci are only supported for the cgmlst and binary input formats".into(),
+        );
+    }
+
+    if opts.pad_missing && opts.input_format == InputFormat::Binary {
+        return Err("The binary input format has no missing values to pad rows with".into());
+    }
+
+    if opts.loci.is_some()
+        && !matches!(
+            opts.input_format,
+            InputFormat::Cgmlst | InputFormat::CgmlstHash | InputFormat::Binary
+        )
+    {
+        return Err(
+            "A loci subset is only supported for the cgmlst and binary input formats".into(),
+        );
+    }
+
+    if opts.resume {
+        if opts.output_format != OutputFormat::Tabular || opts.output == "-" {
+            return Err("Resuming is only supported for tabular output to a file".into());
+        }
+        if opts.knn.is_some() || opts.update_cache || opts.low_memory || opts.with_means.is_some() {
+            return Err(
+                "Resuming cannot be combined with nearest neighbors, a cache update, means or the low memory mode"
+                    .into(),
+            );
+        }
+    }
+
+    if opts.threads_io == 0 {
+        return Err("The number of I/O threads must be at least 1".into());
+    }
+
+    if opts.pairs.is_some() {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err("A pair list is only supported for the tabular output format".into());
+        }
+        if opts.exclude_pairs.is_some()
+            || opts.knn.is_some()
+            || opts.resume
+            || opts.low_memory
+            || opts.update_cache
+        {
+            return Err(
+                "A pair list cannot be combined with excluded pairs, nearest neighbors, resume, low-memory or a cache update"
+                    .into(),
+            );
+        }
+    }
+
+    if opts.reference.is_some() {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err("A reference is only supported for the tabular output format".into());
+        }
+        if opts.exclude_pairs.is_some()
+            || opts.knn.is_some()
+            || opts.resume
+            || opts.low_memory
+            || opts.update_cache
+        {
+            return Err(
+                "A reference cannot be combined with excluded pairs, nearest neighbors, resume, low-memory or a cache update"
+                    .into(),
+            );
+        }
+    }
+
+    if let Some(k) = opts.knn {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err(
+                "Nearest neighbors are only supported for the tabular output format".into(),
+            );
+        }
+        if k == 0 {
+            return Err("The number of nearest neighbors must be at least 1".into());
+        }
+    }
+
+    if opts.exclude_pairs.is_some() {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err("Excluded pairs are only supported for the tabular output format".into());
+        }
+        if opts.knn.is_some() {
+            return Err("Excluded pairs cannot be combined with nearest neighbors".into());
+        }
+    }
+
+    if opts.sort_output {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err(
+                "Sorting the output is only supported for the tabular output format".into(),
+            );
+        }
+        if opts.resume || opts.low_memory {
+            return Err("Sorting the output cannot be combined with resume or low-memory".into());
+        }
+        warn!("Sorting the output keeps all distances in memory");
+    }
+
+    if opts.max_output_dist.is_some() && opts.output_format != OutputFormat::Tabular {
+        return Err("The output cutoff is only supported for the tabular output format".into());
+    }
+
+    if opts.output_format == OutputFormat::Binary && !distances_are_counts(&opts) {
+        return Err("The binary output format only supports distances that are counts".into());
+    }
+
+    if let Some(maxdist) = opts.maxdist {
+        // The tabular and binary output formats mark the capped distances
+        if !matches!(
+            opts.output_format,
+            OutputFormat::Tabular | OutputFormat::Binary
+        ) {
+            let format = opts.output_format.to_possible_value().unwrap();
+            warn!(
+                "The distances that reach maxdist {} are capped in the {} output, so they are not \
+                the true distances and trees built from the matrix will be wrong. Leave out maxdist \
+                or set it above the largest distance to get the true distances",
+                maxdist,
+                format.get_name()
+            );
+        }
+    }
+
+    if opts.collect_errors && (opts.transpose_input || opts.low_memory) {
+        return Err(
+            "Collecting parse errors is not supported for transposed input or low-memory".into(),
+        );
+    }
+
+    if opts.deletion == Deletion::Complete
+        && (matches!(
+            opts.input_format,
+            InputFormat::FastaSketch | InputFormat::Binary | InputFormat::Distances
+        ) || opts.low_memory)
+    {
+        return Err("Complete deletion is not supported for the fasta-sketch, binary or distances input formats or low-memory".into());
+    }
+    // The weights and costs are given by the loci of the input, which complete deletion renumbers
+    if opts.deletion == Deletion::Complete && (opts.weights.is_some() || opts.cost_matrix.is_some())
+    {
+        return Err("Complete deletion cannot be combined with weights or a cost matrix".into());
+    }
+
+    if opts.explain.is_some()
+        && (matches!(
+            opts.input_format,
+            InputFormat::FastaSketch | InputFormat::Distances
+        ) || opts.low_memory
+            || opts.deletion == Deletion::Complete
+            || opts.drop_invariant)
+    {
+        return Err("Explaining the differences of a pair is not supported for the fasta-sketch or distances input formats, low-memory, complete deletion or drop-invariant".into());
+    }
+
+    if opts.order.is_some() && (opts.input_format == InputFormat::Distances || opts.low_memory) {
+        return Err(
+            "The sample order is not supported for the distances input format or low-memory".into(),
+        );
+    }
+
+    if opts.drop_invariant {
+        if matches!(
+            opts.input_format,
+            InputFormat::FastaSketch | InputFormat::Distances
+        ) || opts.low_memory
+            || opts.weights.is_some()
+            || opts.cost_matrix.is_some()
+        {
+            return Err("Dropping invariant positions is not supported for the fasta-sketch or distances input formats, low-memory, weights or a cost matrix".into());
+        }
+        if opts.min_shared.is_some()
+            || opts.model != Model::Hamming
+            || opts.with_compared
+            || opts.with_normalized
+            || opts.ignore_terminal_gaps
+        {
+            return Err("Dropping invariant positions changes the number of compared positions, so it cannot be combined with min-shared, another model than hamming, with-compared, with-normalized or ignore-terminal-gaps".into());
+        }
+    }
+
+    if opts.buffer_size == 0 {
+        return Err("The buffer size must be at least 1 KB".into());
+    }
+
+    if opts.transpose_output && opts.resume {
+        return Err("Transposing the output cannot be combined with resume".into());
+    }
+
+    if opts.with_normalized && opts.model != Model::Hamming {
+        return Err("The normalized distances are only supported for the hamming model".into());
+    }
+
+    if opts.with_compared || opts.with_normalized {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err("The compared positions are only supported for tabular output".into());
+        }
+        if matches!(
+            opts.input_format,
+            InputFormat::FastaSketch | InputFormat::Distances
+        ) || opts.low_memory
+            || opts.resume
+            || opts.histogram
+            || opts.bin_output.is_some()
+        {
+            return Err("The compared positions cannot be combined with the fasta-sketch or distances input formats, low-memory, resume, a histogram or binned output".into());
+        }
+    }
+
+    if opts.append {
+        if !matches!(
+            opts.output_format,
+            OutputFormat::Tabular | OutputFormat::Ndjson
+        ) || opts.output == "-"
+        {
+            return Err(
+                "Appending is only supported for tabular and NDJSON output to a file".into(),
+            );
+        }
+        if opts.resume || opts.histogram || opts.bin_output.is_some() {
+            return Err(
+                "Appending cannot be combined with resume, a histogram or binned output".into(),
+            );
+        }
+    }
+
+    if opts.histogram {
+        if opts.output_format != OutputFormat::Tabular {
+            return Err("The histogram is only supported for the tabular output format".into());
+        }
+        if opts.bin_output.is_some() || opts.resume {
+            return Err("The histogram cannot be combined with binned output or resuming".into());
+        }
+        if !distances_are_counts(&opts) {
+            return Err("The histogram only supports distances that are counts".into());
+        }
+    }
+
+    if opts.bin_output.is_some() {
+        if opts.output_format != OutputFormat::Tabular || opts.resume {
+            return Err(
+                "Binned output is only supported for tabular output without resuming".into(),
+            );
+        }
+        if opts.bins.iter().any(|edge| !edge.is_finite())
+            || opts.bins.windows(2).any(|pair| pair[0] >= pair[1])
+        {
+            return Err("The bins must be numbers in increasing order".into());
+        }
+    }
+
+    if opts.update_cache && opts.precomputed_format != PrecomputedFormat::Tabular {
+        return Err("The cache can only be updated in the tabular precomputed format".into());
+    }
+
+    if opts.header_names.len() != 3 {
+        return Err("Expected exactly three header names".into());
+    }
+
+    if opts.input.iter().filter(|input| *input == "-").count() > 1 {
+        return Err("stdin can only be used as input once".into());
+    }
 
     // print version info
     info!("Version: {}", env!("CARGO_PKG_VERSION"));
@@ -105,15 +1626,189 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let start = Instant::now();
 
-    let data_map = match opts.input_format {
-        InputFormat::Fasta | InputFormat::FastaAll => {
-            read_and_parse_fasta(reader, opts.input_format)?
+    let loci = match &opts.loci {
+        Some(loci_file) => Some(read_loci(BufReader::new(std::fs::File::open(loci_file)?))?),
+        None => None,
+    };
+    let parse_options = ParseOptions {
+        gap_chars: opts.gap_chars.clone().into_bytes(),
+        missing_chars: opts.missing_chars.clone().into_bytes(),
+        case_sensitive: opts.case_sensitive,
+        allow_ragged: opts.allow_ragged,
+        allow_duplicate_ids: opts.allow_duplicate_ids,
+        // Detected separators are resolved for every input file
+        separator: match opts.input_sep {
+            InputSeparator::Char(separator) => separator,
+            InputSeparator::Auto => '\t',
+        },
+        skip_header: opts.skip_header,
+        quoted: opts.csv,
+        transpose: opts.transpose_input,
+        sketch_k: opts.sketch_k,
+        sketch_size: opts.sketch_size,
+        sketch_seed: opts.seed,
+        loci,
+        phylip_strict: opts.phylip_strict,
+        normalize_ids: id_normalization(&opts),
+        inferred_policy: opts.inferred_policy,
+        expect_loci: opts.expect_loci,
+        id_field: opts.id_field,
+        id_delimiter: opts.id_delimiter,
+        collect_errors: opts.collect_errors,
+        pad_missing: opts.pad_missing,
+    };
+
+    let output_mode = if opts.output_format == OutputFormat::Matrix {
+        if opts.output_mode != OutputMode::Full {
+            info!("Matrix output requires all pairs, using the full output mode");
         }
-        InputFormat::Cgmlst | InputFormat::CgmlstHash => {
-            read_and_parse_tabular(reader, opts.input_format, opts.input_sep, opts.skip_header)?
+        OutputMode::Full
+    } else if matches!(
+        opts.output_format,
+        OutputFormat::Condensed | OutputFormat::Binary
+    ) {
+        if opts.output_mode != OutputMode::LowerTriangle {
+            info!(
+                "{} output holds every pair once, using the lower triangle output mode",
+                if opts.output_format == OutputFormat::Binary {
+                    "Binary"
+                } else {
+                    "Condensed"
+                }
+            );
         }
+        OutputMode::LowerTriangle
+    } else if opts.knn.is_some() {
+        // The neighbors of a sample are found among all other samples
+        OutputMode::Full
+    } else if opts.include_diagonal && opts.output_mode == OutputMode::LowerTriangle {
+        OutputMode::LowerTriangleDiag
+    } else {
+        opts.output_mode
+    };
+
+    let distance_options = DistanceOptions {
+        maxdist: effective_maxdist(&opts),
+        policy: ComparisonPolicy {
+            missing: if opts.cgmlst_strict {
+                MissingPolicy::Count
+            } else {
+                opts.missing_policy
+            },
+            gaps_as_diff: opts.gaps_as_diff,
+            ignore_terminal_gaps: opts.ignore_terminal_gaps,
+            strict_acgt: opts.strict_acgt || opts.snp_distance,
+        },
+        min_shared: opts.min_shared,
+        model: opts.model,
+        weights: None,
+        costs: None,
+        base_frequencies: None,
     };
+
+    if opts.input_format == InputFormat::Distances {
+        return run_convert(&opts, &parse_options, output_mode, run_start);
+    }
+
+    if opts.validate_only {
+        return run_validate(&opts, &parse_options);
+    }
+
+    if opts.low_memory || check_max_memory(&opts)? {
+        return run_low_memory(
+            &opts,
+            &parse_options,
+            &distance_options,
+            output_mode,
+            run_start,
+        );
+    }
+
+    let mut data_map = InputMatrix::new();
+    // The index of the input file that an ID was first read from, as the same file can be given twice
+    let mut seen_ids: HashMap<String, usize> = HashMap::new();
+    let inputs = opts.input.iter().zip(read_inputs(&opts, &parse_options)?);
+    for (index, (input, file_data)) in inputs.enumerate() {
+        let file_data = file_data?;
+        for (id, _) in file_data.iter() {
+            let previous = *seen_ids.entry(id.clone()).or_insert(index);
+            if !opts.allow_duplicate_ids && previous < index {
+                return Err(format!(
+                    "Duplicate sample ID {} in {} (already read from {})",
+                    id, input, opts.input[previous]
+                )
+                .into());
+            }
+        }
+        data_map.extend(file_data.into_inner());
+    }
+    let mut data_map = HomogeneousMatrix::try_from(data_map)?;
+    if let Some(order_file) = &opts.order {
+        let order = read_sample_order(
+            BufReader::new(std::fs::File::open(order_file)?),
+            id_normalization(&opts),
+        )?;
+        data_map = reorder_samples(data_map, &order, opts.order_tolerant)?;
+    }
+    if opts.deletion == Deletion::Complete {
+        let keep = complete_deletion_mask(&data_map);
+        info!(
+            "Complete deletion kept {} of {} positions",
+            keep.iter().filter(|&&keep| keep).count(),
+            keep.len()
+        );
+        data_map = select_columns(data_map, &keep)?;
+    }
+    if opts.drop_invariant {
+        let removed = remove_identical_columns(&mut data_map, &distance_options.policy)?;
+        info!("Dropped {} invariant positions", removed);
+    }
+    if opts.input.len() > 1 {
+        info!(
+            "Read {} samples from {} input files",
+            data_map.len(),
+            opts.input.len()
+        );
+    }
     debug!("Reading time: {:?}", start.elapsed());
+    check_not_empty(data_map.len(), &opts)?;
+
+    if let Some(ids) = &opts.explain {
+        return run_explain(
+            &data_map,
+            ids,
+            &opts,
+            &distance_options.policy,
+            &parse_options,
+        );
+    }
+
+    if let Some(parsed_file) = &opts.emit_parsed {
+        write_parsed_fasta(
+            &data_map,
+            BufWriter::new(std::fs::File::create(parsed_file)?),
+        )?;
+    }
+
+    if opts.dry_run {
+        let n = data_map.len();
+        let comparisons = output_mode.pairs(n);
+        let positions = data_map.first().map_or(0, |(_, row)| row.len());
+        println!("{} samples", n);
+        println!("{} positions", positions);
+        println!("{} comparisons", comparisons);
+        println!(
+            "{} output lines",
+            estimated_output_lines(n, comparisons, &opts)
+        );
+        return Ok(());
+    }
+    if let Some(order_file) = &opts.write_order {
+        write_sample_order(
+            &sample_ids(&data_map),
+            BufWriter::new(std::fs::File::create(order_file)?),
+        )?;
+    }
     let start = Instant::now();
 
     info!("Computing distances and writing to file: {}", &opts.output);
@@ -121,43 +1816,193 @@ fn main() -> Result<(), Box<dyn Error>> {
     let precomputed_distances =
         if let Some(precomputed_distances_file) = &opts.precomputed_distances {
             let reader: Box<dyn Read> = Box::new(std::fs::File::open(precomputed_distances_file)?);
-            let reader = BufReader::new(reader);
+            let reader = BufReader::with_capacity(buffer_capacity(&opts), reader);
 
-            read_and_parse_tabular_distances(reader, opts.output_sep)?
+            match opts.precomputed_format {
+                PrecomputedFormat::Tabular => read_and_parse_tabular_distances(
+                    reader,
+                    precomputed_sep(&opts)?,
+                    opts.symmetric_check,
+                    id_normalization(&opts),
+                )?,
+                PrecomputedFormat::Matrix => read_and_parse_matrix_distances(
+                    reader,
+                    precomputed_sep(&opts)?,
+                    opts.symmetric_check,
+                    id_normalization(&opts),
+                )?,
+                PrecomputedFormat::Binary => {
+                    read_binary_distances(reader, opts.symmetric_check, id_normalization(&opts))?
+                }
+            }
         } else {
             HashMap::new()
         };
 
     let mut actual_precomputed_distances = HashMap::new();
     for ((key1, key2), value) in precomputed_distances.iter() {
-        actual_precomputed_distances.insert((key1.as_str(), key2.as_str()), value.clone());
+        actual_precomputed_distances.insert((key1.as_str(), key2.as_str()), *value);
+    }
+
+    let locus_names = if opts.weights.is_some() || opts.cost_matrix.is_some() {
+        read_first_locus_names(&opts, &parse_options)?
+    } else {
+        None
+    };
+    let weights = match &opts.weights {
+        Some(weights_file) => {
+            let mut reader = BufReader::new(std::fs::File::open(weights_file)?);
+            let separator = opts.input_sep.resolve(&mut reader, false)?;
+            let loci = data_map.first().map_or(0, |(_, row)| row.len());
+            Some(read_weights(
+                reader,
+                separator,
+                loci,
+                locus_names.as_deref(),
+            )?)
+        }
+        None => None,
+    };
+    let costs = match &opts.cost_matrix {
+        Some(costs_file) => {
+            let mut reader = BufReader::new(std::fs::File::open(costs_file)?);
+            let separator = opts.input_sep.resolve(&mut reader, false)?;
+            let loci = data_map.first().map_or(0, |(_, row)| row.len());
+            Some(read_cost_matrix(
+                reader,
+                separator,
+                loci,
+                locus_names.as_deref(),
+            )?)
+        }
+        None => None,
+    };
+    let distance_options = DistanceOptions {
+        weights: weights.as_deref(),
+        costs: costs.as_ref(),
+        base_frequencies: (opts.model == Model::Tn93)
+            .then(|| base_frequencies(data_map.iter().map(|(_, row)| row))),
+        ..distance_options
+    };
+
+    let columns = ComparedColumns {
+        compared: opts.with_compared,
+        normalized: opts.with_normalized,
+    };
+    let compared = (columns != ComparedColumns::default()).then_some((
+        &data_map,
+        &distance_options.policy,
+        columns,
+    ));
+    let positions = data_map.first().map_or(0, |(_, row)| row.len());
+    report_missing_statistics(
+        data_map.iter().map(|(_, row)| row),
+        &opts,
+        &distance_options.policy,
+    );
+    let pairs = if let Some(pairs_file) = &opts.pairs {
+        let mut reader = BufReader::new(std::fs::File::open(pairs_file)?);
+        let separator = opts.input_sep.resolve(&mut reader, false)?;
+        Some(read_pairs(reader, separator, id_normalization(&opts))?)
+    } else if let Some(reference) = &opts.reference {
+        Some(reference_pairs(
+            &data_map,
+            reference,
+            id_normalization(&opts),
+        )?)
+    } else {
+        None
+    };
+    if let Some(pairs) = pairs {
+        let distances = compute_pair_distances(
+            &data_map,
+            &distance_options,
+            &pairs,
+            Some(&actual_precomputed_distances),
+        )?;
+        write_output(
+            distances.into_iter(),
+            &sample_ids(&data_map),
+            &opts,
+            output_mode,
+            compared,
+        )?;
+        write_metadata(&opts, data_map.len(), positions, run_start)?;
+        info!("Computed the distances of {} pairs", pairs.len());
+        info!("Done");
+        return Ok(());
+    }
+
+    let excluded_pairs = read_excluded(&opts)?;
+    if opts.resume {
+        run_resume(
+            &opts,
+            &data_map,
+            &distance_options,
+            output_mode,
+            &actual_precomputed_distances,
+            excluded_pairs.unwrap_or_default(),
+        )?;
+        return write_metadata(&opts, data_map.len(), positions, run_start);
+    }
+    let excluded_pairs = excluded_pairs.as_ref().map(pair_refs);
+
+    if let Some(k) = opts.knn.filter(|_| bounds_neighbors(&opts)) {
+        let neighbors = compute_nearest_neighbors(
+            &data_map,
+            &distance_options,
+            k,
+            Some(&actual_precomputed_distances),
+        );
+        write_distances(
+            sorted_output(neighbors.into_iter(), &opts),
+            &sample_ids(&data_map),
+            &opts,
+            output_mode,
+            compared,
+        )?;
+        debug!("Computing + Writing time: {:?}", start.elapsed());
+        info!("Computed the {} nearest neighbors of every sample", k);
+        write_metadata(&opts, data_map.len(), positions, run_start)?;
+        info!("Done");
+        return Ok(());
     }
 
     // Compute the pairwise distances
     let distances = compute_distances(
         &data_map,
-        opts.maxdist,
-        opts.output_mode,
+        &distance_options,
+        output_mode,
         Some(&actual_precomputed_distances),
+        excluded_pairs.as_ref(),
     );
 
-    let writer: Box<dyn Write> = if opts.output == "-" {
-        Box::new(stdout())
-    } else {
-        Box::new(std::fs::File::create(&opts.output)?)
-    };
-
-    let mut writer = BufWriter::new(writer);
-    // // Cancel the program and exit
-    // debug!("Early exit");
-    // return Ok(());
+    if let (true, Some(precomputed_distances_file)) =
+        (opts.update_cache, &opts.precomputed_distances)
+    {
+        let cache_file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(precomputed_distances_file)?;
+        let mut cache_writer = BufWriter::with_capacity(buffer_capacity(&opts), cache_file);
+        let written = append_distances_to_cache(
+            distances.clone(),
+            &actual_precomputed_distances,
+            &mut cache_writer,
+            precomputed_sep(&opts)?,
+        )?;
+        cache_writer.flush()?;
+        info!(
+            "Added {} distances to the cache file: {}",
+            written, precomputed_distances_file
+        );
+    }
 
-    write_distances_to_file(
+    write_output(
         distances,
-        &mut writer,
-        opts.output_sep,
-        opts.output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &opts,
+        output_mode,
+        compared,
     )?;
 
     debug!("Computing + Writing time: {:?}", start.elapsed());
@@ -165,6 +2010,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         Some(maxdist) => info!("Computed distances with a maximum distance of {}", maxdist),
         None => info!("Computed all distances"),
     }
+    write_metadata(&opts, data_map.len(), positions, run_start)?;
 
     info!("Done");
 
@@ -1,14 +1,20 @@
 use core::panic;
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use std::io::{BufRead, BufWriter, Write};
+use std::io::{BufRead, BufWriter, Read, Write};
 use std::str::FromStr;
 
-use bio::io::fasta;
 use clap::ValueEnum;
+use log::{debug, error, warn};
 use rayon::prelude::*;
 
-use crate::types::{InputFormat, InputMatrix, SupportedTypeVec};
+use crate::indexed::CHUNK_PAIRS;
+use crate::types::{
+    ChewBBACAinteger, ComparisonPolicy, Hash, HomogeneousMatrix, InferredPolicy, InputFormat,
+    InputMatrix, MinHashSketch, NucleotideAll, PackedNucleotides, PackedPresence, Presence, Site,
+    SupportedTypeVec,
+};
 
 #[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
 pub enum OutputMode {
@@ -16,6 +22,29 @@ pub enum OutputMode {
     LowerTriangle,
     /// Output the full distance matrix
     Full,
+    /// Output the lower triangle with the diagonal, so every row ends with the distance of the sample to itself
+    LowerTriangleDiag,
+}
+
+impl OutputMode {
+    /// The number of pairs of row `i` of a matrix with `n` samples, which are the columns
+    /// `0..row_len` of the row
+    pub fn row_len(&self, i: usize, n: usize) -> usize {
+        match self {
+            OutputMode::LowerTriangle => i,
+            OutputMode::LowerTriangleDiag => i + 1,
+            OutputMode::Full => n,
+        }
+    }
+
+    /// The number of pairs of a matrix with `n` samples
+    pub fn pairs(&self, n: usize) -> usize {
+        match self {
+            OutputMode::LowerTriangle => n * n.saturating_sub(1) / 2,
+            OutputMode::LowerTriangleDiag => n * (n + 1) / 2,
+            OutputMode::Full => n * n,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
@@ -24,296 +53,3690 @@ pub enum OutputFormat {
     Tabular,
     /// Output the distances in a Phylip format
     Phylip,
+    /// Output the distances as a square matrix with the sample names as header row and first column
+    Matrix,
+    /// Output the distances as a NEXUS distances block, as read by SplitsTree and PAUP*
+    Nexus,
+    /// Output the distances as a condensed vector with one distance per line, in the order of SciPy's pdist: the pairs (i, j) with i < j, sorted by i and then by j
+    Condensed,
+    /// Output the distances as newline-delimited JSON with one object with the keys id1, id2 and distance per line. Undefined distances are null, and distances at the maximum are strings like ">=10"
+    Ndjson,
+    /// Output the lower triangle as little-endian unsigned 32-bit integers after a header with the sample names, as described in the README. It can be read back as precomputed distances. Only for distances that are counts
+    Binary,
 }
 
-pub fn read_and_parse_tabular<R: BufRead>(
-    reader: R,
-    input_format: InputFormat,
-    separator: char,
-    skip_header: bool,
-) -> Result<InputMatrix, Box<dyn Error>> {
-    let mut lines = reader.lines();
+/// The layout of a precomputed distances file
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum PrecomputedFormat {
+    /// A tabular long format with two IDs and the distance on every line
+    #[default]
+    Tabular,
+    /// A square matrix with the sample names as header row and first column
+    Matrix,
+    /// The binary output format
+    Binary,
+}
+
+/// How sample IDs are normalized when they are read
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IdNormalization {
+    /// Trim the whitespace around the IDs
+    Trim,
+    /// Trim the whitespace around the IDs and lowercase them
+    Lowercase,
+}
 
-    if skip_header {
-        lines.next();
+impl IdNormalization {
+    pub fn apply(self, id: &str) -> String {
+        match self {
+            IdNormalization::Trim => id.trim().to_string(),
+            IdNormalization::Lowercase => id.trim().to_lowercase(),
+        }
     }
+}
 
-    let mut data_vec = Vec::new();
+/// Normalizes the IDs in place, if a normalization is given
+pub(crate) fn normalize_ids<'a>(
+    ids: impl Iterator<Item = &'a mut String>,
+    normalization: Option<IdNormalization>,
+) {
+    if let Some(normalization) = normalization {
+        for id in ids {
+            *id = normalization.apply(id);
+        }
+    }
+}
 
-    for line in lines {
-        let line = line?;
-        let mut fields = line.split(separator);
-        let id = fields
-            .next()
-            .ok_or_else(|| "Missing ID field at the start of the line")?;
-        let id = id.to_string();
+fn normalized_id(id: &str, normalization: Option<IdNormalization>) -> String {
+    normalization.map_or_else(|| id.to_string(), |normalization| normalization.apply(id))
+}
 
-        let row_data = match input_format {
-            InputFormat::Cgmlst => SupportedTypeVec::Cgmlst(parse_fields(fields)?),
-            InputFormat::CgmlstHash => SupportedTypeVec::SHA1Hash(parse_fields(fields)?),
-            _ => return Err("Input format not implemented".into()),
-        };
+/// The model used to turn the differences between two samples into a distance
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum Model {
+    /// The number of differences
+    #[default]
+    Hamming,
+    /// The Kimura 2-parameter distance for the fasta and phylip input formats. Ambiguous and missing sites are excluded from the transition and transversion counts
+    K80,
+    /// The Tamura-Nei distance for the fasta and phylip input formats. The base frequencies are estimated from all samples and the sites are counted like for k80
+    Tn93,
+    /// The Jaccard distance of the present loci for the binary input format, one minus the loci present in both samples over the loci present in either. Two samples without present loci have a distance of 0
+    Jaccard,
+}
 
-        data_vec.push((id, row_data));
+/// Options that control how the input is parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Characters that are treated as gaps in the FastaAll format
+    pub gap_chars: Vec<u8>,
+    /// Characters that are treated as missing in the FastaAll format
+    pub missing_chars: Vec<u8>,
+    /// Compare the bases of the FastaAll format case sensitively, so soft-masked bases differ
+    pub case_sensitive: bool,
+    /// Keep tabular rows with a different number of loci than the first row instead of returning an
+    /// error
+    pub allow_ragged: bool,
+    /// Keep records that share an ID instead of returning an error
+    pub allow_duplicate_ids: bool,
+    /// The field separator of tabular input
+    pub separator: char,
+    /// Skip the first line of tabular input
+    pub skip_header: bool,
+    /// Parse tabular input as RFC 4180 CSV, so fields may be quoted and contain the separator
+    pub quoted: bool,
+    /// Tabular input has the loci as rows and the samples as columns
+    pub transpose: bool,
+    /// The k-mer length of the FastaSketch format
+    pub sketch_k: usize,
+    /// The number of hashes in the sketches of the FastaSketch format
+    pub sketch_size: usize,
+    /// The seed of the k-mer hashes of the FastaSketch format
+    pub sketch_seed: u64,
+    /// Only keep these loci of tabular input, given by header name or by index starting at 0
+    /// for the first column after the ID
+    pub loci: Option<Vec<String>>,
+    /// Read the names of Phylip input from the first 10 characters of the line instead of up to
+    /// the first whitespace
+    pub phylip_strict: bool,
+    /// Normalize the sample IDs before duplicates are checked
+    pub normalize_ids: Option<IdNormalization>,
+    /// How inferred alleles of the Cgmlst format are compared
+    pub inferred_policy: InferredPolicy,
+    /// Return an error for tabular rows that don't have this number of loci
+    pub expect_loci: Option<usize>,
+    /// Pad tabular rows with fewer loci than `expect_loci` with missing alleles instead
+    pub pad_missing: bool,
+    /// Take the sample ID of FASTA records from this field of the header ID, starting at 0
+    pub id_field: Option<usize>,
+    /// The delimiter of the fields of `id_field`
+    pub id_delimiter: char,
+    /// Keep parsing after a line of tabular input or a FASTA record that cannot be parsed, and
+    /// return an error for all of them at the end
+    pub collect_errors: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            gap_chars: Vec::new(),
+            missing_chars: Vec::new(),
+            case_sensitive: false,
+            allow_ragged: false,
+            allow_duplicate_ids: false,
+            separator: '\t',
+            skip_header: false,
+            quoted: false,
+            transpose: false,
+            sketch_k: 21,
+            sketch_size: 1000,
+            sketch_seed: 0,
+            loci: None,
+            phylip_strict: false,
+            normalize_ids: None,
+            inferred_policy: InferredPolicy::default(),
+            expect_loci: None,
+            pad_missing: false,
+            id_field: None,
+            id_delimiter: '|',
+            collect_errors: false,
+        }
     }
+}
 
-    Ok(data_vec)
+/// Options that control how the distance between two samples is calculated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DistanceOptions<'a> {
+    /// Stop counting differences once this distance is reached
+    pub maxdist: Option<usize>,
+    /// How the sites of the two samples are compared
+    pub policy: ComparisonPolicy,
+    /// The distance is undefined when fewer positions are non-missing in both samples
+    pub min_shared: Option<usize>,
+    /// The model used to compute the distance
+    pub model: Model,
+    /// The weight of every position. A difference adds its weight to the distance instead of 1
+    pub weights: Option<&'a [f64]>,
+    /// The costs of differences between specific alleles of the Cgmlst format
+    pub costs: Option<&'a CostMatrix>,
+    /// The base frequencies of the alignment for the TN93 model. Equal frequencies are assumed
+    /// when they are not given
+    pub base_frequencies: Option<BaseFrequencies>,
 }
 
-fn parse_fields<'a, I, T>(fields: I) -> Result<Vec<T>, Box<dyn Error>>
-where
-    I: Iterator<Item = &'a str>,
-    T: FromStr,
-    T::Err: Error + 'static,
-{
-    fields
-        .map(|s| s.parse::<T>().map_err(|e| Box::new(e) as Box<dyn Error>))
-        .collect()
+/// The distance between two samples
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Distance {
+    /// The number of differences between the samples
+    Exact(usize),
+    /// The calculation stopped at the maximum distance, so the distance is at least this large
+    AtLeast(usize),
+    /// A distance estimated by a substitution model, or a weighted sum of differences
+    Estimate(f64),
+    /// The samples share too few non-missing positions for a meaningful distance
+    Undefined,
 }
 
-pub fn read_and_parse_fasta<R: BufRead>(
+impl Distance {
+    /// The distance as a number, where a capped distance counts as its bound, or None when it
+    /// is undefined
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Distance::Exact(dist) | Distance::AtLeast(dist) => Some(dist as f64),
+            Distance::Estimate(dist) => Some(dist),
+            Distance::Undefined => None,
+        }
+    }
+}
+
+/// The compression of the input files
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum InputCompression {
+    /// Detect the compression from the magic bytes at the start of the input
+    #[default]
+    Auto,
+    /// The input is not compressed and is read as it is
+    None,
+    /// gzip, which needs the gzip feature
+    Gzip,
+    /// bzip2, which needs the bzip2 feature
+    Bzip2,
+    /// xz, which needs the xz feature
+    Xz,
+    /// zstd, which needs the zstd feature
+    Zstd,
+}
+
+impl InputCompression {
+    /// The compression of the magic bytes at the start of the input, or None if there is none
+    fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(b"\x1F\x8B") {
+            InputCompression::Gzip
+        } else if magic.starts_with(b"BZh") {
+            InputCompression::Bzip2
+        } else if magic.starts_with(b"\xFD7zXZ\x00") {
+            InputCompression::Xz
+        } else if magic.starts_with(b"\x28\xB5\x2F\xFD") {
+            InputCompression::Zstd
+        } else {
+            InputCompression::None
+        }
+    }
+}
+
+/// Wraps the reader in a decompressor when the input starts with the magic bytes of gzip, bzip2,
+/// xz or zstd. Each format needs its cargo feature, otherwise compressed input returns an error.
+pub fn decompress<'a, R: BufRead + 'a>(reader: R) -> Result<Box<dyn BufRead + 'a>, Box<dyn Error>> {
+    decompress_as(reader, InputCompression::Auto)
+}
+
+/// Wraps the reader in the decompressor of the compression. Only the automatic detection reads
+/// ahead in the input, so a given compression also works for streams that should not be peeked.
+pub fn decompress_as<'a, R: BufRead + 'a>(
+    mut reader: R,
+    compression: InputCompression,
+) -> Result<Box<dyn BufRead + 'a>, Box<dyn Error>> {
+    let compression = match compression {
+        InputCompression::Auto => InputCompression::detect(reader.fill_buf()?),
+        compression => compression,
+    };
+    match compression {
+        InputCompression::Auto | InputCompression::None => Ok(Box::new(reader)),
+        InputCompression::Gzip => {
+            #[cfg(feature = "gzip")]
+            return Ok(Box::new(std::io::BufReader::new(
+                flate2::bufread::MultiGzDecoder::new(reader),
+            )));
+            #[cfg(not(feature = "gzip"))]
+            return Err(
+                "The input is gzip compressed, but distle was built without the gzip feature"
+                    .into(),
+            );
+        }
+        InputCompression::Bzip2 => {
+            #[cfg(feature = "bzip2")]
+            return Ok(Box::new(std::io::BufReader::new(
+                bzip2::bufread::MultiBzDecoder::new(reader),
+            )));
+            #[cfg(not(feature = "bzip2"))]
+            return Err(
+                "The input is bzip2 compressed, but distle was built without the bzip2 feature"
+                    .into(),
+            );
+        }
+        InputCompression::Xz => {
+            #[cfg(feature = "xz")]
+            return Ok(Box::new(std::io::BufReader::new(
+                xz2::bufread::XzDecoder::new_multi_decoder(reader),
+            )));
+            #[cfg(not(feature = "xz"))]
+            return Err(
+                "The input is xz compressed, but distle was built without the xz feature".into(),
+            );
+        }
+        InputCompression::Zstd => {
+            // The decoder reads all concatenated frames like the other decoders
+            #[cfg(feature = "zstd")]
+            return Ok(Box::new(std::io::BufReader::new(
+                zstd::stream::read::Decoder::with_buffer(reader)?,
+            )));
+            #[cfg(not(feature = "zstd"))]
+            return Err(
+                "The input is zstd compressed, but distle was built without the zstd feature"
+                    .into(),
+            );
+        }
+    }
+}
+
+/// Reads a cgMLST table with one sample per line and the ID in the first column.
+///
+/// With `transpose` the table has one locus per line instead, and the sample IDs are read from
+/// the header line. The whole table is then kept in memory as text before it is parsed, which
+/// takes several times the memory of the parsed matrix.
+pub fn read_and_parse_tabular<R: BufRead>(
     reader: R,
     input_format: InputFormat,
-) -> Result<InputMatrix, Box<dyn Error>> {
-    let reader = fasta::Reader::new(reader);
-    let mut data_vec = Vec::new();
+    options: &ParseOptions,
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let separator = options.separator;
+    let requested = options.loci.as_deref();
+    let mut errors = ParseErrors::new(options.collect_errors);
+    // The line numbers of the rows, which transposed rows don't have
+    let mut row_lines = Vec::new();
+    let mut data_vec = if options.transpose {
+        let mut table = read_table(reader, options)?;
+        if let Some(requested) = requested {
+            let names: Vec<&str> = table
+                .iter()
+                .skip(1)
+                .map(|locus| locus.first().map_or("", String::as_str))
+                .collect();
+            let selected = resolve_loci(requested, Some(&names))?;
+            // Keep the header line and the selected loci
+            let mut index = 0;
+            table.retain(|_| {
+                index += 1;
+                index == 1 || selected.binary_search(&(index - 2)).is_ok()
+            });
+        }
+        transpose_table(&table, input_format, options.inferred_policy)?
+    } else if options.quoted {
+        let mut csv_reader = csv_reader(reader, separator, options.skip_header)?;
+        let selected = match requested {
+            Some(requested) => {
+                let header = if options.skip_header {
+                    Some(csv_reader.headers()?.iter().skip(1).collect::<Vec<_>>())
+                } else {
+                    None
+                };
+                Some(resolve_loci(requested, header.as_deref())?)
+            }
+            None => None,
+        };
 
-    for record in reader.records() {
-        let record = record?;
-        let id = record.id().to_string();
+        let mut data_vec = Vec::new();
+        for record in csv_reader.records() {
+            let record = record?;
+            let row = parse_selected_row(
+                record.iter(),
+                selected.as_deref(),
+                input_format,
+                options.inferred_policy,
+            );
+            let line = record
+                .position()
+                .map_or(0, |position| position.line() as usize);
+            if let Some(row) = errors.check(row, || format!("Line {}", line))? {
+                data_vec.push(row);
+                row_lines.push(line);
+            }
+        }
+        data_vec
+    } else {
+        let mut lines = reader.lines();
 
-        let row_data = match input_format {
-            InputFormat::Fasta => SupportedTypeVec::Nucleotide(parse_fasta_seq(record.seq())?),
-            InputFormat::FastaAll => {
-                SupportedTypeVec::NucleotideAll(parse_fasta_seq(record.seq())?)
+        let header = if options.skip_header {
+            lines.next().transpose()?
+        } else {
+            None
+        };
+        let selected = match requested {
+            Some(requested) => {
+                let names = header
+                    .as_ref()
+                    .map(|header| header.split(separator).skip(1).collect::<Vec<_>>());
+                Some(resolve_loci(requested, names.as_deref())?)
             }
-            _ => return Err("Input format not implemented".into()),
+            None => None,
         };
 
-        data_vec.push((id, row_data));
+        let mut data_vec = Vec::new();
+        let first_line = 1 + usize::from(header.is_some());
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            let row = parse_selected_row(
+                line.split(separator),
+                selected.as_deref(),
+                input_format,
+                options.inferred_policy,
+            );
+            if let Some(row) = errors.check(row, || format!("Line {}", first_line + index))? {
+                data_vec.push(row);
+                row_lines.push(first_line + index);
+            }
+        }
+        data_vec
+    };
+
+    normalize_ids(data_vec.iter_mut().map(|(id, _)| id), options.normalize_ids);
+    if let Some(expected) = options.expect_loci {
+        check_expected_loci(&mut data_vec, expected, options.pad_missing)?;
+    }
+    if !options.allow_ragged {
+        check_row_lengths(&data_vec, &row_lines, &mut errors)?;
     }
+    errors.finish()?;
+    if !options.allow_duplicate_ids {
+        check_duplicate_ids(&sample_ids(&data_vec))?;
+    }
+
+    Ok(HomogeneousMatrix::try_from(data_vec)?)
+}
 
-    Ok(data_vec)
+/// Returns an error for the first row that doesn't have the expected number of loci. With
+/// `pad` shorter rows are padded with missing alleles instead, so only longer rows are an error.
+fn check_expected_loci(
+    data_vec: &mut InputMatrix,
+    expected: usize,
+    pad: bool,
+) -> Result<(), Box<dyn Error>> {
+    for (id, row) in data_vec.iter_mut() {
+        if pad && row.len() < expected {
+            row.pad_missing(expected)?;
+        }
+        if row.len() != expected {
+            return Err(format!(
+                "Sample {} has {} loci, but {} loci are expected",
+                id,
+                row.len(),
+                expected
+            )
+            .into());
+        }
+    }
+    Ok(())
 }
 
-fn parse_fasta_seq<T: From<u8>>(seq: &[u8]) -> Result<Vec<T>, Box<dyn Error>> {
-    Ok(seq.iter().map(|&u| T::from(u)).collect())
+/// Checks that every row has the same number of loci as the first row, since only the loci that
+/// both rows have would be compared. A row with another number is an error at its line, or at
+/// its row number for transposed input.
+fn check_row_lengths(
+    data_vec: &InputMatrix,
+    row_lines: &[usize],
+    errors: &mut ParseErrors,
+) -> Result<(), Box<dyn Error>> {
+    let Some((first_id, first_row)) = data_vec.first() else {
+        return Ok(());
+    };
+    for (index, (id, row)) in data_vec.iter().enumerate() {
+        if row.len() == first_row.len() {
+            continue;
+        }
+        let err = format!(
+            "Sample {} has {} loci, but the first sample {} has {}. Use --allow-ragged to compare \
+            only the loci that both samples have",
+            id,
+            row.len(),
+            first_id,
+            first_row.len()
+        );
+        errors.check::<()>(Err(err.into()), || match row_lines.get(index) {
+            Some(line) => format!("Line {}", line),
+            None => format!("Row {}", index + 1),
+        })?;
+    }
+    Ok(())
 }
 
-pub fn read_and_parse_tabular_distances<'a, R: BufRead>(
+fn csv_reader<R: BufRead>(
     reader: R,
     separator: char,
-) -> Result<HashMap<(String, String), usize>, Box<dyn Error>> {
-    let mut distances = HashMap::new();
-    for line in reader.lines() {
-        let line = line?;
-        let mut fields = line.split(separator);
-        let id1: String = fields
-            .next()
-            .ok_or("Missing ID field at start of line")?
-            .into();
-        let id2: String = fields
-            .next()
-            .ok_or("Missing ID field at start of line")?
-            .into();
-        let dist = fields.next().ok_or("Missing distance field")?.parse()?;
-        distances.insert((id1.clone(), id2.clone()), dist);
-        distances.insert((id1, id2), dist); // Also insert the reverse in case the input has a different order
-    }
-    Ok(distances)
+    has_headers: bool,
+) -> Result<csv::Reader<R>, Box<dyn Error>> {
+    let separator = u8::try_from(separator)
+        .map_err(|_| "The separator must be an ASCII character for quoted input")?;
+    Ok(csv::ReaderBuilder::new()
+        .delimiter(separator)
+        .has_headers(has_headers)
+        .flexible(true)
+        .from_reader(reader))
 }
 
-pub fn compute_distances<'a>(
-    data_map: &'a InputMatrix,
-    maxdist: Option<usize>,
-    output_mode: OutputMode,
-    already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
-) -> impl Iterator<Item = (&'a str, &'a str, usize)> + Clone {
-    let len = data_map.len();
+/// Reads all fields of the table, including the header line
+fn read_table<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    if options.quoted {
+        let mut csv_reader = csv_reader(reader, options.separator, false)?;
+        csv_reader
+            .records()
+            .map(|record| Ok(record?.iter().map(String::from).collect()))
+            .collect()
+    } else {
+        reader
+            .lines()
+            .map(|line| Ok(line?.split(options.separator).map(String::from).collect()))
+            .collect()
+    }
+}
 
-    (0..len)
-        .into_par_iter()
-        .flat_map(move |i| {
-            let max_j = match output_mode {
-                OutputMode::LowerTriangle => i,
-                OutputMode::Full => len,
-            };
-            (0..max_j).into_par_iter().map(move |j| {
-                let (id1, row1) = &data_map[i];
-                let (id2, row2) = &data_map[j];
+/// Turns a table with the sample IDs in the header line and one locus per line into rows
+fn transpose_table(
+    table: &[Vec<String>],
+    input_format: InputFormat,
+    inferred_policy: InferredPolicy,
+) -> Result<InputMatrix, Box<dyn Error>> {
+    let (header, loci) = table
+        .split_first()
+        .ok_or("Missing header line with the sample IDs")?;
+    if let Some(locus) = loci.iter().find(|locus| locus.len() != header.len()) {
+        return Err(format!(
+            "Locus {} has {} fields, but the header has {}",
+            locus[0],
+            locus.len(),
+            header.len()
+        )
+        .into());
+    }
 
-                let dist = already_computed
-                    .and_then(|distances| distances.get(&(id1.as_str(), id2.as_str())).cloned())
-                    .unwrap_or_else(|| calculate_distance(row1, row2, maxdist));
+    (1..header.len())
+        .map(|column| {
+            let fields = std::iter::once(header[column].as_str())
+                .chain(loci.iter().map(|locus| locus[column].as_str()));
+            parse_row(fields, input_format, inferred_policy)
+        })
+        .collect()
+}
 
-                (id1.as_str(), id2.as_str(), dist)
-            })
+/// Finds the indices of the requested loci, by header name if the input has a header and by
+/// index otherwise. The indices are sorted, so the loci keep the order of the input.
+fn resolve_loci(
+    requested: &[String],
+    names: Option<&[&str]>,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut selected = requested
+        .iter()
+        .map(|locus| {
+            names
+                .and_then(|names| names.iter().position(|name| name == locus))
+                .or_else(|| {
+                    locus
+                        .parse()
+                        .ok()
+                        .filter(|&index| names.is_none_or(|names| index < names.len()))
+                })
+                .ok_or_else(|| locus_not_found(locus, names.is_some()))
         })
-        .collect::<Vec<_>>()
-        .into_iter()
+        .collect::<Result<Vec<usize>, _>>()?;
+    selected.sort_unstable();
+    selected.dedup();
+    Ok(selected)
 }
 
-fn calculate_distance(
-    row1: &SupportedTypeVec,
-    row2: &SupportedTypeVec,
-    maxdist: Option<usize>,
-) -> usize {
-    match (row1, row2) {
-        (SupportedTypeVec::Nucleotide(r1), SupportedTypeVec::Nucleotide(r2)) => {
-            compute_distance_eq(r1, r2, maxdist)
-        }
-        (SupportedTypeVec::NucleotideAll(r1), SupportedTypeVec::NucleotideAll(r2)) => {
-            compute_distance_eq(r1, r2, maxdist)
-        }
-        (SupportedTypeVec::Cgmlst(r1), SupportedTypeVec::Cgmlst(r2)) => {
-            compute_distance_eq(r1, r2, maxdist)
+/// The error for a locus that is neither a header name nor an index, which for input without a
+/// header line is a name that cannot be looked up
+fn locus_not_found(locus: &str, has_names: bool) -> String {
+    if has_names || locus.parse::<usize>().is_ok() {
+        format!("Locus {} not found in the input", locus)
+    } else {
+        format!(
+            "Locus {} is given by name, but the input has no header line with the locus names",
+            locus
+        )
+    }
+}
+
+/// Reads the names of the loci of tabular input from its header line, or from the first column
+/// with `transpose`, and keeps only the loci that are selected. Returns None for input without a
+/// header line, where the loci only have indices.
+pub fn read_locus_names<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let names: Vec<String> = if options.transpose {
+        let table = read_table(reader, options)?;
+        table
+            .iter()
+            .skip(1)
+            .map(|locus| locus.first().cloned().unwrap_or_default())
+            .collect()
+    } else if !options.skip_header {
+        return Ok(None);
+    } else if options.quoted {
+        let mut csv_reader = csv_reader(reader, options.separator, false)?;
+        match csv_reader.records().next() {
+            Some(record) => record?.iter().skip(1).map(String::from).collect(),
+            None => Vec::new(),
         }
-        (SupportedTypeVec::SHA1Hash(r1), SupportedTypeVec::SHA1Hash(r2)) => {
-            compute_distance_eq(r1, r2, maxdist)
+    } else {
+        match reader.lines().next() {
+            Some(line) => line?
+                .split(options.separator)
+                .skip(1)
+                .map(String::from)
+                .collect(),
+            None => Vec::new(),
         }
-        _ => panic!("Unsupported type"),
+    };
+    let Some(requested) = options.loci.as_deref() else {
+        return Ok(Some(names));
+    };
+    let refs: Vec<&str> = names.iter().map(String::as_str).collect();
+    let selected = resolve_loci(requested, Some(&refs))?;
+    Ok(Some(
+        selected.into_iter().map(|i| names[i].clone()).collect(),
+    ))
+}
+
+/// Finds a locus of the weights or the cost matrix by header name, or by its index starting at 0
+/// for the first column after the ID
+fn parse_locus(
+    locus: &str,
+    names: Option<&[String]>,
+    loci: usize,
+) -> Result<usize, Box<dyn Error>> {
+    if let Some(index) = names.and_then(|names| names.iter().position(|name| name == locus)) {
+        return Ok(index);
+    }
+    let index: usize = locus
+        .parse()
+        .map_err(|_| locus_not_found(locus, names.is_some()))?;
+    if index >= loci {
+        return Err(format!(
+            "Locus index {} is out of range for the {} loci of the input",
+            index, loci
+        )
+        .into());
     }
+    Ok(index)
 }
 
-fn compute_distance_eq<T: PartialEq>(row1: &[T], row2: &[T], maxdist: Option<usize>) -> usize {
-    let maxdist = maxdist.unwrap_or(usize::MAX);
-    let mut count = 0;
+/// Parses a row that only keeps the selected loci, if a selection is given
+fn parse_selected_row<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+    selected: Option<&[usize]>,
+    input_format: InputFormat,
+    inferred_policy: InferredPolicy,
+) -> Result<(String, SupportedTypeVec), Box<dyn Error>> {
+    let Some(selected) = selected else {
+        return parse_row(fields, input_format, inferred_policy);
+    };
+    let id = fields
+        .next()
+        .ok_or("Missing ID field at the start of the line")?;
+    let loci: Vec<&str> = fields.collect();
+    let kept = selected
+        .iter()
+        .map(|&index| {
+            loci.get(index)
+                .copied()
+                .ok_or_else(|| format!("Sample {} has no locus {}", id, index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    parse_row(
+        std::iter::once(id).chain(kept),
+        input_format,
+        inferred_policy,
+    )
+}
 
-    for (x, y) in row1.iter().zip(row2.iter()) {
-        if x != y {
-            count += 1;
-            if count >= maxdist {
-                break;
+fn parse_row<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+    input_format: InputFormat,
+    inferred_policy: InferredPolicy,
+) -> Result<(String, SupportedTypeVec), Box<dyn Error>> {
+    let id = fields
+        .next()
+        .ok_or("Missing ID field at the start of the line")?;
+    let id = id.to_string();
+
+    let row_data = match input_format {
+        InputFormat::Cgmlst => SupportedTypeVec::Cgmlst(
+            fields
+                .map(|field| ChewBBACAinteger::parse_inferred(field, inferred_policy))
+                .collect::<Result<_, _>>()?,
+        ),
+        InputFormat::CgmlstHash => {
+            // The width of the hashes is detected from their number of hex digits
+            let fields: Vec<&str> = fields.collect();
+            if fields.iter().any(|field| field.len() > 40) {
+                SupportedTypeVec::SHA256Hash(parse_fields(fields.into_iter())?)
+            } else {
+                SupportedTypeVec::SHA1Hash(parse_fields(fields.into_iter())?)
             }
         }
-    }
-    count
+        InputFormat::Binary => SupportedTypeVec::Binary(
+            fields
+                .map(str::parse::<Presence>)
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err("Input format not implemented".into()),
+    };
+
+    Ok((id, row_data))
 }
 
-pub fn write_distances_to_file<'a, W: Write>(
-    distances: impl Iterator<Item = (&'a str, &'a str, usize)> + Clone,
-    writer: W,
-    output_sep: char,
-    output_format: OutputFormat,
-    number_of_samples: usize,
-) -> Result<(), Box<dyn Error>> {
-    let writer = BufWriter::new(writer);
+/// The errors of single lines or records. Without collecting, the first error is returned as is.
+struct ParseErrors {
+    collect: bool,
+    errors: Vec<String>,
+}
 
-    match output_format {
-        OutputFormat::Tabular => write_distances_to_long_format(distances, writer, output_sep),
-        OutputFormat::Phylip => {
-            write_distances_to_philip(distances, writer, output_sep, number_of_samples)
+impl ParseErrors {
+    fn new(collect: bool) -> Self {
+        ParseErrors {
+            collect,
+            errors: Vec::new(),
         }
     }
-}
 
-fn write_distances_to_long_format<'a, W: Write>(
-    distances: impl Iterator<Item = (&'a str, &'a str, usize)>,
-    mut writer: W,
-    output_sep: char,
-) -> Result<(), Box<dyn Error>> {
-    for (id1, id2, dist) in distances {
-        writeln!(writer, "{}{}{}{}{}", id1, output_sep, id2, output_sep, dist)?;
+    /// Returns the parsed value, or keeps the error with its location and returns None when
+    /// errors are collected
+    fn check<T>(
+        &mut self,
+        result: Result<T, Box<dyn Error>>,
+        location: impl FnOnce() -> String,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.collect => {
+                self.errors.push(format!("{}: {}", location(), err));
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Logs every collected error and returns an error with their number, if there are any
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        for err in &self.errors {
+            error!("{}", err);
+        }
+        Err(format!("Found {} errors while parsing the input", self.errors.len()).into())
     }
-    Ok(())
 }
 
-fn write_distances_to_philip<'a, W: Write>(
-    distances: impl Iterator<Item = (&'a str, &'a str, usize)> + Clone,
-    mut writer: W,
-    output_sep: char,
-    number_of_samples: usize,
-) -> Result<(), Box<dyn Error>> {
-    write!(writer, "{}", number_of_samples)?;
+/// Returns an error listing every ID that occurs more than once in the input
+pub(crate) fn check_duplicate_ids(ids: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for id in ids {
+        if !seen.insert(id) && !duplicates.contains(id) {
+            duplicates.push(*id);
+        }
+    }
 
-    let mut first = true;
-    let mut prev_id = "";
-    for (id, id2, dist) in distances {
-        if first && id != id2 {
-            writeln!(writer)?;
-            write!(writer, "{}", id2)?;
+    if duplicates.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Duplicate sample IDs in the input: {}",
+            duplicates.join(", ")
+        )
+        .into())
+    }
+}
+
+/// Checks the parsed input for problems that would otherwise only be found one at a time or not
+/// at all: duplicate sample IDs and rows with a different number of positions than the first
+/// row. Sketches can have any size.
+pub fn validate_rows(data_map: &InputMatrix, allow_duplicate_ids: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !allow_duplicate_ids {
+        if let Err(err) = check_duplicate_ids(&sample_ids(data_map)) {
+            problems.push(err.to_string());
         }
-        if id != prev_id {
-            writeln!(writer)?;
-            write!(writer, "{}", id)?;
-            prev_id = id;
+    }
+    if let Some((first_id, first_row)) = data_map.first() {
+        if !matches!(first_row, SupportedTypeVec::Sketch(_)) {
+            for (id, row) in &data_map[1..] {
+                if row.len() != first_row.len() {
+                    problems.push(format!(
+                        "Sample {} has {} positions, but sample {} has {}",
+                        id,
+                        row.len(),
+                        first_id,
+                        first_row.len()
+                    ));
+                }
+            }
         }
-        write!(writer, "{}{}", output_sep, dist)?;
-        first = false;
     }
-    writeln!(writer)?;
-    Ok(())
+    problems
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::types::{ChewBBACAinteger, Nucleotide, NucleotideAll, SHA1Hash};
-    use std::str::FromStr;
+fn parse_fields<'a, I, T>(fields: I) -> Result<Vec<T>, Box<dyn Error>>
+where
+    I: Iterator<Item = &'a str>,
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    fields
+        .map(|s| s.parse::<T>().map_err(|e| Box::new(e) as Box<dyn Error>))
+        .collect()
+}
 
-    use super::*;
+pub fn read_and_parse_fasta<R: BufRead>(
+    reader: R,
+    input_format: InputFormat,
+    options: &ParseOptions,
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let mut reader = reader;
+    check_fasta_start(reader.fill_buf()?)?;
+    let mut data_vec = Vec::new();
+    let parser = SequenceParser::new(input_format, options)?;
+    let mut characters = 0;
+    let mut unexpected = 0;
+    let mut errors = ParseErrors::new(options.collect_errors);
 
-    #[test]
-    fn test_compute_distance_eq() {
-        let row1 = vec![1, 2, 3, 4, 5];
-        let row2 = vec![1, 2, 3, 4, 5];
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 0);
+    for record in fasta_records(reader) {
+        let (line, header, seq) = record?;
+        let id = std::str::from_utf8(&header)
+            .map_err(Box::from)
+            .and_then(|header| header_id(fasta_id(header), options));
+        let Some(id) = errors.check(id, || format!("Line {}", line))? else {
+            continue;
+        };
+        let row_data = parser.parse(&seq);
+        characters += seq.len();
+        unexpected += count_unexpected_characters(&seq, input_format);
 
-        let row1 = vec![1, 2, 3, 4, 5];
-        let row2 = vec![1, 2, 3, 4, 6];
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 1);
+        data_vec.push((id, row_data));
+    }
+    errors.finish()?;
+    report_unexpected_characters(characters, unexpected, input_format);
 
-        let row1 = vec![1, 2, 3, 4, 5];
-        let row2 = vec![1, 2, 3, 4, 6];
-        assert_eq!(compute_distance_eq(&row1, &row2, Some(1)), 1);
+    normalize_ids(data_vec.iter_mut().map(|(id, _)| id), options.normalize_ids);
+    if !options.allow_duplicate_ids {
+        check_duplicate_ids(&sample_ids(&data_vec))?;
     }
 
-    #[test]
-    fn test_compute_distance_eq_for_chewbbaca() {
-        let x0 = ChewBBACAinteger::from_str("-").unwrap();
-        let x1 = ChewBBACAinteger::from_str("1").unwrap();
-        let x2 = ChewBBACAinteger::from_str("2").unwrap();
-        let x3 = ChewBBACAinteger::from_str("3").unwrap();
-        let plot = ChewBBACAinteger::from_str("plot5").unwrap();
-        let x3_new = ChewBBACAinteger::from_str("INF-3").unwrap();
+    Ok(HomogeneousMatrix::try_from(data_vec)?)
+}
 
-        let row1 = vec![x0, x1, x2, x3, x1];
-        let row2 = vec![x0, x1, x1, x2, x1];
-        let row3 = vec![x0, x1, x2, x3_new, plot];
-        assert_eq!(compute_distance_eq(&row1, &row3, None), 0);
-        assert_eq!(compute_distance_eq(&row2, &row3, None), 2);
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 2);
-    }
+/// The line number of the header, the header without the '>' and the sequence of a FASTA record
+type FastaRecord = (usize, Vec<u8>, Vec<u8>);
 
-    #[test]
-    fn test_compute_distance_eq_for_chewbbaca_hash() {
-        let x0 = SHA1Hash::from_str("-").unwrap();
-        let x1 = SHA1Hash::from_str("6bc8d04609de559621859873ef301f221cf5d991").unwrap();
-        let x2 = SHA1Hash::from_str("1e354c3d41dc0d3c403db19f22de23299a33a1c8").unwrap();
-        let x3 = SHA1Hash::from_str("beb636132e9cb496f1c1d37ecafdd62ed02060b0").unwrap();
-        let row1 = vec![x0, x1, x2, x3, x1];
-        let row2 = vec![x0, x1, x1, x2, x1];
-        let row3 = vec![x0, x0, x2, x0, x0];
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 2);
-        assert_eq!(compute_distance_eq(&row1, &row3, None), 0);
-        assert_eq!(compute_distance_eq(&row2, &row3, None), 1);
-    }
+/// Reads the FASTA records line by line. The lines are kept as bytes, so a header that is not
+/// valid UTF-8 is only an error of its own record, and the line ends and trailing whitespace of
+/// the sequence lines are left out.
+fn fasta_records<R: BufRead>(reader: R) -> impl Iterator<Item = std::io::Result<FastaRecord>> {
+    let mut lines = reader.split(b'\n');
+    let mut line_number = 0;
+    let mut header: Option<(usize, Vec<u8>)> = None;
+    let mut seq = Vec::new();
+    std::iter::from_fn(move || {
+        for line in lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            line_number += 1;
+            if let Some(next_header) = line.strip_prefix(b">") {
+                let next = (line_number, next_header.to_vec());
+                if let Some((number, previous)) = header.replace(next) {
+                    return Some(Ok((number, previous, std::mem::take(&mut seq))));
+                }
+            } else if header.is_some() {
+                seq.extend_from_slice(line.trim_ascii_end());
+            }
+        }
+        header
+            .take()
+            .map(|(number, last)| Ok((number, last, std::mem::take(&mut seq))))
+    })
+}
 
-    #[test]
-    fn test_compute_distance_eq_for_fasta() {
-        let a = Nucleotide::from_str("A").unwrap();
-        let c = Nucleotide::from_str("C").unwrap();
-        let g = Nucleotide::from_str("G").unwrap();
-        let t = Nucleotide::from_str("T").unwrap();
-        let n = Nucleotide::from_str("n").unwrap();
-        let d = Nucleotide::from_str("-").unwrap();
+/// Returns the ID of a FASTA header, which is the header up to the first whitespace
+fn fasta_id(header: &str) -> &str {
+    header
+        .trim_end()
+        .split(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+}
 
-        let row1 = vec![a, c, g, t, n, d];
-        let row2 = vec![a, n, d, d, d, n];
-        let row3 = vec![c, c, g, t, n, d];
+/// Above this fraction of characters outside the alphabet of the input format, the input is
+/// probably of the wrong type
+const UNEXPECTED_CHARACTERS_WARNING: f64 = 0.2;
 
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 0);
-        assert_eq!(compute_distance_eq(&row1, &row3, None), 1);
-        assert_eq!(compute_distance_eq(&row2, &row3, None), 1);
+/// Counts the characters outside the alphabet of the input format, which are A, C, G, T, N and
+/// gaps for nucleotides and letters, stops and gaps for amino acids. Every character is valid in
+/// the fasta-all input format.
+fn count_unexpected_characters(seq: &[u8], input_format: InputFormat) -> usize {
+    let expected = |c: &u8| match input_format {
+        InputFormat::Fasta | InputFormat::FastaSketch => matches!(
+            c.to_ascii_uppercase(),
+            b'A' | b'C' | b'G' | b'T' | b'N' | b'-'
+        ),
+        InputFormat::Protein => c.is_ascii_alphabetic() || matches!(c, b'*' | b'-'),
+        _ => true,
+    };
+    seq.iter().filter(|c| !expected(c)).count()
+}
+
+/// Logs the fraction of characters outside the alphabet in verbose mode, and warns when it is so
+/// high that the input format is probably wrong, like amino acids read as nucleotides
+fn report_unexpected_characters(characters: usize, unexpected: usize, input_format: InputFormat) {
+    if characters == 0 || input_format == InputFormat::FastaAll {
+        return;
+    }
+    let fraction = unexpected as f64 / characters as f64;
+    debug!(
+        "{:.2}% of the characters are not in the alphabet of the {:?} input format",
+        fraction * 100.0,
+        input_format
+    );
+    if fraction > UNEXPECTED_CHARACTERS_WARNING {
+        warn!(
+            "{:.0}% of the characters are not in the alphabet of the {:?} input format, so the input may be of another type",
+            fraction * 100.0,
+            input_format
+        );
+    }
+}
+
+/// Returns the sample ID of a FASTA header ID, which is the whole ID unless a field is selected
+pub(crate) fn header_id(id: &str, options: &ParseOptions) -> Result<String, Box<dyn Error>> {
+    let Some(field) = options.id_field else {
+        return Ok(id.to_string());
+    };
+    id.split(options.id_delimiter)
+        .nth(field)
+        .map(str::to_string)
+        .ok_or_else(|| format!("The FASTA header {} has no ID field {}", id, field).into())
+}
+
+/// Reads a nucleotide alignment in Phylip format. The header line has the number of samples and
+/// positions, followed by the sequences either sequential, where a sequence can span several
+/// lines, or interleaved in blocks where only the first block has the names. The layout is
+/// detected from the line lengths. Whitespace within the sequences is ignored.
+pub fn read_and_parse_phylip<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        // Blank lines only separate the blocks of interleaved alignments
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    let Some((header, lines)) = lines.split_first() else {
+        return Ok(HomogeneousMatrix::default());
+    };
+    let mut fields = header.split_whitespace().map(str::parse::<usize>);
+    let (Some(Ok(samples)), Some(Ok(positions))) = (fields.next(), fields.next()) else {
+        return Err(format!(
+            "Expected the number of samples and positions on the first line of the Phylip input, got '{}'",
+            header
+        )
+        .into());
+    };
+
+    let records = parse_phylip_sequential(lines, samples, positions, options.phylip_strict)
+        .or_else(|sequential| {
+            parse_phylip_interleaved(lines, samples, positions, options.phylip_strict).map_err(
+                |interleaved| {
+                    format!(
+                        "The Phylip input is neither sequential ({}) nor interleaved ({})",
+                        sequential, interleaved
+                    )
+                },
+            )
+        })?;
+
+    let parser = SequenceParser::new(InputFormat::Phylip, options)?;
+    let mut data_vec: InputMatrix = records
+        .into_iter()
+        .map(|(id, seq)| (id, parser.parse(&seq)))
+        .collect();
+    normalize_ids(data_vec.iter_mut().map(|(id, _)| id), options.normalize_ids);
+    if !options.allow_duplicate_ids {
+        check_duplicate_ids(&sample_ids(&data_vec))?;
+    }
+    Ok(HomogeneousMatrix::try_from(data_vec)?)
+}
+
+/// Splits a Phylip line into the name and the rest of the line. Strict names are the first 10
+/// characters, relaxed names end at the first whitespace.
+fn split_phylip_name(line: &str, strict: bool) -> (String, &str) {
+    if strict {
+        let end = line.char_indices().nth(10).map_or(line.len(), |(i, _)| i);
+        (line[..end].trim().to_string(), &line[end..])
+    } else {
+        let line = line.trim_start();
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        (line[..end].to_string(), &line[end..])
+    }
+}
+
+fn phylip_residues(text: &str) -> impl Iterator<Item = u8> + '_ {
+    text.bytes().filter(|b| !b.is_ascii_whitespace())
+}
+
+fn parse_phylip_sequential(
+    lines: &[String],
+    samples: usize,
+    positions: usize,
+    strict: bool,
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut lines = lines.iter();
+    let mut records = Vec::with_capacity(samples);
+    for _ in 0..samples {
+        let line = lines
+            .next()
+            .ok_or_else(|| format!("fewer than {} samples", samples))?;
+        let (id, rest) = split_phylip_name(line, strict);
+        let mut seq: Vec<u8> = phylip_residues(rest).collect();
+        while seq.len() < positions {
+            let line = lines
+                .next()
+                .ok_or_else(|| format!("sample {} has fewer than {} positions", id, positions))?;
+            seq.extend(phylip_residues(line));
+        }
+        if seq.len() > positions {
+            return Err(format!(
+                "sample {} has more than {} positions",
+                id, positions
+            ));
+        }
+        records.push((id, seq));
+    }
+    if lines.next().is_some() {
+        return Err(format!("more lines than {} samples", samples));
+    }
+    Ok(records)
+}
+
+fn parse_phylip_interleaved(
+    lines: &[String],
+    samples: usize,
+    positions: usize,
+    strict: bool,
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    if samples == 0 || !lines.len().is_multiple_of(samples) {
+        return Err(format!(
+            "the lines are not in blocks of {} samples",
+            samples
+        ));
+    }
+    let mut records: Vec<(String, Vec<u8>)> = lines[..samples]
+        .iter()
+        .map(|line| {
+            let (id, rest) = split_phylip_name(line, strict);
+            (id, phylip_residues(rest).collect())
+        })
+        .collect();
+    for (i, line) in lines[samples..].iter().enumerate() {
+        records[i % samples].1.extend(phylip_residues(line));
+    }
+    match records.iter().find(|(_, seq)| seq.len() != positions) {
+        Some((id, seq)) => Err(format!(
+            "sample {} has {} positions instead of {}",
+            id,
+            seq.len(),
+            positions
+        )),
+        None => Ok(records),
+    }
+}
+
+/// Returns an error that suggests what to do when the input does not start like FASTA, which is
+/// clearer than the error of the FASTA parser. FASTQ input is recognized by its leading '@'.
+pub(crate) fn check_fasta_start(start: &[u8]) -> Result<(), Box<dyn Error>> {
+    match start.first() {
+        None | Some(b'>') => Ok(()),
+        Some(b'@') => Err(
+            "The input looks like FASTQ, which is not supported. Convert the reads \
+            to FASTA first, for example by assembling them, and use --input-format fasta-sketch if \
+            the sequences are not aligned"
+                .into(),
+        ),
+        Some(_) => Err(
+            "Expected a FASTA record starting with '>'. For tabular input use \
+            --input-format cgmlst or cgmlst-hash, and for Phylip input --input-format phylip"
+                .into(),
+        ),
+    }
+}
+
+/// Parses the sequences of FASTA records into rows of the input format
+pub(crate) struct SequenceParser {
+    input_format: InputFormat,
+    nucleotide_all_lut: [NucleotideAll; 256],
+    sketch_k: usize,
+    sketch_size: usize,
+    sketch_seed: u64,
+}
+
+impl SequenceParser {
+    pub(crate) fn new(
+        input_format: InputFormat,
+        options: &ParseOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        match input_format {
+            InputFormat::Fasta
+            | InputFormat::Phylip
+            | InputFormat::FastaAll
+            | InputFormat::Protein => {}
+            InputFormat::FastaSketch => {
+                if !(1..=32).contains(&options.sketch_k) {
+                    return Err("The k-mer length of the sketches must be between 1 and 32".into());
+                }
+                if options.sketch_size == 0 {
+                    return Err("The sketch size must be at least 1".into());
+                }
+            }
+            _ => return Err("Input format not implemented".into()),
+        }
+        let missing_chars = [options.gap_chars.as_slice(), &options.missing_chars].concat();
+        Ok(SequenceParser {
+            input_format,
+            nucleotide_all_lut: NucleotideAll::lookup_table(&missing_chars, options.case_sensitive),
+            sketch_k: options.sketch_k,
+            sketch_size: options.sketch_size,
+            sketch_seed: options.sketch_seed,
+        })
+    }
+
+    pub(crate) fn parse(&self, seq: &[u8]) -> SupportedTypeVec {
+        match self.input_format {
+            InputFormat::Fasta | InputFormat::Phylip => {
+                SupportedTypeVec::Nucleotide(parse_fasta_seq(seq))
+            }
+            InputFormat::Protein => SupportedTypeVec::AminoAcid(parse_fasta_seq(seq)),
+            InputFormat::FastaAll => SupportedTypeVec::NucleotideAll(
+                seq.iter()
+                    .map(|&u| self.nucleotide_all_lut[u as usize])
+                    .collect(),
+            ),
+            InputFormat::FastaSketch => SupportedTypeVec::Sketch(MinHashSketch::from_sequence(
+                seq,
+                self.sketch_k,
+                self.sketch_size,
+                self.sketch_seed,
+            )),
+            _ => unreachable!("tabular formats are rejected when the parser is created"),
+        }
+    }
+}
+
+fn parse_fasta_seq<T: From<u8>, C: FromIterator<T>>(seq: &[u8]) -> C {
+    seq.iter().map(|&u| T::from(u)).collect()
+}
+
+/// Reads precomputed distances in tabular long format. Every pair is stored under the IDs in
+/// sorted order. When the same pair appears more than once with different distances, the last
+/// one is kept with a warning, or an error is returned if `symmetric_check` is set.
+pub fn read_and_parse_tabular_distances<R: BufRead>(
+    reader: R,
+    separator: char,
+    symmetric_check: bool,
+    normalization: Option<IdNormalization>,
+) -> Result<HashMap<(String, String), usize>, Box<dyn Error>> {
+    let mut distances = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split(separator);
+        let id1: String = fields
+            .next()
+            .ok_or("Missing ID field at start of line")?
+            .into();
+        let id2: String = fields
+            .next()
+            .ok_or("Missing ID field at start of line")?
+            .into();
+        let dist = fields.next().ok_or("Missing distance field")?.parse()?;
+        insert_precomputed_distance(
+            &mut distances,
+            normalized_id(&id1, normalization),
+            normalized_id(&id2, normalization),
+            dist,
+            symmetric_check,
+        )?;
+    }
+    Ok(distances)
+}
+
+/// Distances that were read instead of computed, with the samples in the order they first appear
+#[derive(Debug, Default)]
+pub struct DistanceTable {
+    ids: Vec<String>,
+    indices: HashMap<String, usize>,
+    /// The distances by the indices of the samples, with the larger index first
+    distances: HashMap<(usize, usize), Distance>,
+}
+
+impl DistanceTable {
+    /// Reads distances in the tabular long format and adds them to the table. The distance field
+    /// is an integer, a decimal number, a capped distance like '>=5' or the undefined value. The
+    /// separator, header line and ID normalization are taken from the parse options. When the
+    /// same pair appears more than once with different distances, the last one is kept with a
+    /// warning, or an error is returned if `symmetric_check` is set.
+    pub fn read<R: BufRead>(
+        &mut self,
+        reader: R,
+        options: &ParseOptions,
+        undefined_value: &str,
+        symmetric_check: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        for line in reader.lines().skip(usize::from(options.skip_header)) {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split(options.separator);
+            let id1 = fields.next().unwrap_or_default();
+            let id2 = fields.next().ok_or("Missing ID field at start of line")?;
+            let field = fields.next().ok_or("Missing distance field")?.trim();
+            let dist = parse_distance(field, undefined_value).ok_or_else(|| {
+                format!(
+                    "Cannot read the distance '{}' of {} and {}",
+                    field, id1, id2
+                )
+            })?;
+
+            let i = self.index(normalized_id(id1, options.normalize_ids));
+            let j = self.index(normalized_id(id2, options.normalize_ids));
+            match self.distances.insert((i.max(j), i.min(j)), dist) {
+                Some(previous) if previous != dist => {
+                    let message = format!(
+                        "Conflicting distances for {} and {}: {} and {}",
+                        self.ids[i],
+                        self.ids[j],
+                        previous
+                            .as_f64()
+                            .map_or("undefined".into(), |d| d.to_string()),
+                        dist.as_f64().map_or("undefined".into(), |d| d.to_string())
+                    );
+                    if symmetric_check {
+                        return Err(message.into());
+                    }
+                    warn!("{}", message);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, id: String) -> usize {
+        if let Some(&index) = self.indices.get(&id) {
+            return index;
+        }
+        self.ids.push(id.clone());
+        self.indices.insert(id, self.ids.len() - 1);
+        self.ids.len() - 1
+    }
+
+    /// Returns the sample IDs in the order they first appear
+    pub fn sample_ids(&self) -> Vec<&str> {
+        self.ids.iter().map(String::as_str).collect()
+    }
+
+    /// Returns the distances in the same order as [`compute_distances`]. Pairs that were not
+    /// read are undefined, except for the comparison of a sample with itself, which is 0.
+    pub fn distances(
+        &self,
+        output_mode: OutputMode,
+    ) -> impl Iterator<Item = (&str, &str, Distance)> + '_ {
+        let n = self.ids.len();
+        (0..n).flat_map(move |i| {
+            (0..output_mode.row_len(i, n)).map(move |j| {
+                let dist = match self.distances.get(&(i.max(j), i.min(j))) {
+                    Some(&dist) => dist,
+                    None if i == j => Distance::Exact(0),
+                    None => Distance::Undefined,
+                };
+                (self.ids[i].as_str(), self.ids[j].as_str(), dist)
+            })
+        })
+    }
+}
+
+/// Parses a distance as written by the tabular output format
+fn parse_distance(field: &str, undefined_value: &str) -> Option<Distance> {
+    if field == undefined_value {
+        return Some(Distance::Undefined);
+    }
+    if let Some(bound) = field.strip_prefix(">=") {
+        return bound.parse().ok().map(Distance::AtLeast);
+    }
+    match field.parse() {
+        Ok(dist) => Some(Distance::Exact(dist)),
+        Err(_) => field.parse().ok().map(Distance::Estimate),
+    }
+}
+
+/// Reads precomputed distances from a square matrix, like the one written by the matrix output
+/// format. The first cell of the header line is ignored. Cells that are not integers, like
+/// capped or undefined distances, are left out.
+pub fn read_and_parse_matrix_distances<R: BufRead>(
+    reader: R,
+    separator: char,
+    symmetric_check: bool,
+    normalization: Option<IdNormalization>,
+) -> Result<HashMap<(String, String), usize>, Box<dyn Error>> {
+    let mut lines = reader.lines();
+    let Some(header) = lines.next() else {
+        return Ok(HashMap::new());
+    };
+    let header = header?;
+    let column_ids: Vec<&str> = header.split(separator).skip(1).collect();
+
+    let mut distances = HashMap::new();
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(separator);
+        let row_id = fields.next().unwrap_or_default();
+        let fields: Vec<&str> = fields.collect();
+        if fields.len() > column_ids.len() {
+            return Err(format!(
+                "Row {} has {} distances, but the header has {} sample IDs",
+                row_id,
+                fields.len(),
+                column_ids.len()
+            )
+            .into());
+        }
+        for (column_id, field) in column_ids.iter().zip(fields) {
+            if let Ok(dist) = field.parse() {
+                insert_precomputed_distance(
+                    &mut distances,
+                    normalized_id(row_id, normalization),
+                    normalized_id(column_id, normalization),
+                    dist,
+                    symmetric_check,
+                )?;
+            }
+        }
+    }
+    Ok(distances)
+}
+
+/// Inserts a precomputed distance under the IDs in sorted order, and reports a conflict with a
+/// distance that was read before as a warning, or as an error with the symmetric check
+fn insert_precomputed_distance(
+    distances: &mut HashMap<(String, String), usize>,
+    id1: String,
+    id2: String,
+    dist: usize,
+    symmetric_check: bool,
+) -> Result<(), Box<dyn Error>> {
+    let key = if id1 <= id2 {
+        (id1.clone(), id2.clone())
+    } else {
+        (id2.clone(), id1.clone())
+    };
+    if let Some(previous) = distances.insert(key, dist) {
+        if previous != dist {
+            let message = format!(
+                "Conflicting precomputed distances for {} and {}: {} and {}",
+                id1, id2, previous, dist
+            );
+            if symmetric_check {
+                return Err(message.into());
+            }
+            warn!("{}", message);
+        }
+    }
+    Ok(())
+}
+
+/// Looks up a precomputed distance by the IDs in sorted order, as they are stored
+pub fn precomputed_distance(
+    distances: &HashMap<(&str, &str), usize>,
+    id1: &str,
+    id2: &str,
+) -> Option<usize> {
+    distances.get(&(id1.min(id2), id1.max(id2))).copied()
+}
+
+/// Ordered pairs of sample IDs
+pub type IdPairs = HashSet<(String, String)>;
+
+/// The pairs of the reference sample with every other sample in input order, with the reference
+/// as the first ID. The reference ID is normalized like the sample IDs.
+pub fn reference_pairs(
+    data_map: &InputMatrix,
+    reference: &str,
+    normalization: Option<IdNormalization>,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let reference = normalized_id(reference, normalization);
+    if !data_map.iter().any(|(id, _)| *id == reference) {
+        return Err(format!("The reference sample {} is not in the input", reference).into());
+    }
+    Ok(data_map
+        .iter()
+        .filter(|(id, _)| *id != reference)
+        .map(|(id, _)| (reference.clone(), id.clone()))
+        .collect())
+}
+
+/// Reads the pairs that should not be compared, one pair of IDs per line. Both orderings of
+/// every pair are stored.
+pub fn read_excluded_pairs<R: BufRead>(
+    reader: R,
+    separator: char,
+    normalization: Option<IdNormalization>,
+) -> Result<IdPairs, Box<dyn Error>> {
+    let mut pairs = HashSet::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(separator);
+        let id1 = normalized_id(fields.next().unwrap_or_default(), normalization);
+        let id2 = normalized_id(
+            fields
+                .next()
+                .ok_or_else(|| format!("Missing second ID in the excluded pair {}", id1))?,
+            normalization,
+        );
+        pairs.insert((id2.clone(), id1.clone()));
+        pairs.insert((id1, id2));
+    }
+    Ok(pairs)
+}
+
+/// Reads the pairs to compare, one pair of IDs per line, in the order of the lines
+pub fn read_pairs<R: BufRead>(
+    reader: R,
+    separator: char,
+    normalization: Option<IdNormalization>,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut pairs = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(separator);
+        let id1 = normalized_id(fields.next().unwrap_or_default(), normalization);
+        let id2 = normalized_id(
+            fields
+                .next()
+                .ok_or_else(|| format!("Missing second ID in the pair {}", id1))?,
+            normalization,
+        );
+        pairs.push((id1, id2));
+    }
+    Ok(pairs)
+}
+
+/// Reads the pairs of a tabular output that may end in a line that was cut off, for example
+/// when the run was interrupted. Returns the pairs in the order they were written and the
+/// length in bytes of the complete lines.
+pub fn read_written_pairs<R: BufRead>(
+    mut reader: R,
+    separator: &str,
+    header: bool,
+) -> Result<(IdPairs, u64), Box<dyn Error>> {
+    let mut pairs = HashSet::new();
+    let mut complete_len = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 || line.last() != Some(&b'\n') {
+            break;
+        }
+        if complete_len > 0 || !header {
+            let text = std::str::from_utf8(&line)?.trim_end_matches(['\n', '\r']);
+            let mut fields = text.split(separator);
+            if let (Some(id1), Some(id2)) = (fields.next(), fields.next()) {
+                pairs.insert((id1.to_string(), id2.to_string()));
+            }
+        }
+        complete_len += read as u64;
+    }
+    Ok((pairs, complete_len))
+}
+
+/// Appends the distances that are not in the cache yet to the cache writer, in the same long
+/// format that `read_and_parse_tabular_distances` reads. Each unordered pair is written once,
+/// self-comparisons are skipped and only exact distances are cached. Returns the number of
+/// pairs written.
+pub fn append_distances_to_cache<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    cache: &HashMap<(&str, &str), usize>,
+    mut writer: W,
+    separator: char,
+) -> Result<usize, Box<dyn Error>> {
+    let mut written = HashSet::new();
+    for (id1, id2, dist) in distances {
+        let Distance::Exact(dist) = dist else {
+            continue;
+        };
+        if id1 == id2 || precomputed_distance(cache, id1, id2).is_some() {
+            continue;
+        }
+        if written.insert((id1.min(id2), id1.max(id2))) {
+            writeln!(writer, "{}{}{}{}{}", id1, separator, id2, separator, dist)?;
+        }
+    }
+    Ok(written.len())
+}
+
+/// Computes the distances between the samples in row-major order: for every sample, the pairs
+/// with the samples before it (lower triangle) or with all samples (full). The pairs are computed
+/// in parallel, but the collect keeps this order, so the output does not depend on the number of
+/// threads. Excluded pairs are left out of the output.
+pub fn compute_distances<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &DistanceOptions,
+    output_mode: OutputMode,
+    already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
+    excluded: Option<&'a HashSet<(&'a str, &'a str)>>,
+) -> impl Iterator<Item = (&'a str, &'a str, Distance)> + Clone {
+    let pairs = PairSource {
+        data_map,
+        output_mode,
+        already_computed,
+        excluded,
+    };
+    pairs.compute_rows(0..data_map.len(), options).into_iter()
+}
+
+/// The IDs of two samples and their distance
+pub type PairDistance<'a> = (&'a str, &'a str, Distance);
+
+/// Computes the distances of only the given pairs, in the order of the pairs. The rows are
+/// looked up by their sample IDs, and an unknown ID is an error.
+pub fn compute_pair_distances<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &DistanceOptions,
+    pairs: &[(String, String)],
+    already_computed: Option<&HashMap<(&str, &str), usize>>,
+) -> Result<Vec<PairDistance<'a>>, Box<dyn Error>> {
+    let rows: HashMap<&str, &'a (String, SupportedTypeVec)> =
+        data_map.iter().map(|row| (row.0.as_str(), row)).collect();
+    let row = |id: &str| {
+        rows.get(id)
+            .copied()
+            .ok_or_else(|| format!("Unknown sample ID {} in the pairs", id))
+    };
+    let pairs = pairs
+        .iter()
+        .map(|(id1, id2)| Ok((row(id1)?, row(id2)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(pairs
+        .into_par_iter()
+        .map(|((id1, row1), (id2, row2))| {
+            let dist = already_computed
+                .and_then(|distances| precomputed_distance(distances, id1, id2))
+                .map(Distance::Exact)
+                .unwrap_or_else(|| calculate_distance(row1, row2, options));
+            (id1.as_str(), id2.as_str(), dist)
+        })
+        .collect())
+}
+
+/// Computes the distances like [`compute_distances`], but one chunk of consecutive rows with at
+/// least `chunk_pairs` pairs at a time, so a chunk can be written before the next one is
+/// computed.
+pub fn compute_distances_in_chunks<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &'a DistanceOptions<'a>,
+    output_mode: OutputMode,
+    already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
+    excluded: Option<&'a HashSet<(&'a str, &'a str)>>,
+    chunk_pairs: usize,
+) -> impl Iterator<Item = Vec<(&'a str, &'a str, Distance)>> + 'a {
+    let pairs = PairSource {
+        data_map,
+        output_mode,
+        already_computed,
+        excluded,
+    };
+    let len = data_map.len();
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start >= len {
+            return None;
+        }
+        let end = pairs.chunk_end(start, chunk_pairs);
+        let chunk = pairs.compute_rows(start..end, options);
+        start = end;
+        Some(chunk)
+    })
+}
+
+/// Computes the full distance matrix one row at a time, as the ID of each sample and its
+/// distances to all samples in input order. The rows are computed in parallel in chunks of at
+/// least [`CHUNK_PAIRS`] pairs, so only the rows of one chunk are kept in memory.
+pub fn compute_distance_rows<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &'a DistanceOptions<'a>,
+    already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
+) -> impl Iterator<Item = (&'a str, Vec<Distance>)> + 'a {
+    let distances = compute_distances_in_chunks(
+        data_map,
+        options,
+        OutputMode::Full,
+        already_computed,
+        None,
+        CHUNK_PAIRS,
+    )
+    .flatten();
+    distance_rows(distances, data_map.len()).map(|row| {
+        let id = row[0].0;
+        (id, row.into_iter().map(|(_, _, dist)| dist).collect())
+    })
+}
+
+/// Groups the distances of the full output mode into the rows of the matrix, which are the
+/// pairs of one sample with all `samples` samples. Only one row is kept in memory at a time.
+pub fn distance_rows<'a>(
+    mut distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    samples: usize,
+) -> impl Iterator<Item = Vec<PairDistance<'a>>> {
+    std::iter::from_fn(move || {
+        let row: Vec<_> = distances.by_ref().take(samples.max(1)).collect();
+        (!row.is_empty()).then_some(row)
+    })
+}
+
+/// Calls `f` with every pair and its distance in the order of [`compute_distances`], without
+/// collecting all distances. The distances are computed in parallel in chunks of at least
+/// [`CHUNK_PAIRS`] pairs, and `f` is called on the calling thread, so it does not have to be
+/// `Sync`. Only the distances of one chunk are kept in memory.
+pub fn compute_distances_with<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &DistanceOptions,
+    output_mode: OutputMode,
+    mut f: impl FnMut(&'a str, &'a str, Distance),
+) {
+    let pairs = PairSource {
+        data_map,
+        output_mode,
+        already_computed: None,
+        excluded: None,
+    };
+    let mut start = 0;
+    while start < data_map.len() {
+        let end = pairs.chunk_end(start, CHUNK_PAIRS);
+        for (id1, id2, dist) in pairs.compute_rows(start..end, options) {
+            f(id1, id2, dist);
+        }
+        start = end;
+    }
+}
+
+/// Calls `f` with every pair and its distance like [`compute_distances_with`], but from the
+/// threads that compute the distances as soon as each distance is known. The pairs come in no
+/// particular order, and `f` must be `Sync` since it is called from several threads at once.
+pub fn par_compute_distances_with<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &DistanceOptions,
+    output_mode: OutputMode,
+    f: impl Fn(&'a str, &'a str, Distance) + Sync + Send,
+) {
+    let pairs = PairSource {
+        data_map,
+        output_mode,
+        already_computed: None,
+        excluded: None,
+    };
+    pairs
+        .par_rows(0..data_map.len(), options)
+        .for_each(|(id1, id2, dist)| f(id1, id2, dist));
+}
+
+/// The pairs of samples that are compared, and the distances that don't have to be calculated
+struct PairSource<'a> {
+    data_map: &'a HomogeneousMatrix,
+    output_mode: OutputMode,
+    already_computed: Option<&'a HashMap<(&'a str, &'a str), usize>>,
+    excluded: Option<&'a HashSet<(&'a str, &'a str)>>,
+}
+
+impl<'a> PairSource<'a> {
+    /// The number of pairs of the row, including excluded ones
+    fn row_len(&self, i: usize) -> usize {
+        self.output_mode.row_len(i, self.data_map.len())
+    }
+
+    /// The index after the last row of the chunk that starts at `start` and has at least
+    /// `chunk_pairs` pairs, or fewer at the end
+    fn chunk_end(&self, start: usize, chunk_pairs: usize) -> usize {
+        let mut end = start;
+        let mut chunk_len = 0;
+        while end < self.data_map.len() && (end == start || chunk_len < chunk_pairs) {
+            chunk_len += self.row_len(end);
+            end += 1;
+        }
+        end
+    }
+
+    fn compute_rows(
+        &self,
+        rows: std::ops::Range<usize>,
+        options: &DistanceOptions,
+    ) -> Vec<(&'a str, &'a str, Distance)> {
+        self.par_rows(rows, options).collect()
+    }
+
+    /// The distances of the rows as a parallel iterator, without an order
+    fn par_rows<'b>(
+        &'b self,
+        rows: std::ops::Range<usize>,
+        options: &'b DistanceOptions,
+    ) -> impl ParallelIterator<Item = (&'a str, &'a str, Distance)> + 'b {
+        let data_map = self.data_map;
+        let already_computed = self.already_computed;
+        let excluded = self.excluded;
+
+        rows.into_par_iter().flat_map(move |i| {
+            (0..self.row_len(i)).into_par_iter().filter_map(move |j| {
+                let (id1, row1) = &data_map[i];
+                let (id2, row2) = &data_map[j];
+                if is_excluded(excluded, id1, id2) {
+                    return None;
+                }
+
+                let dist = already_computed
+                    .and_then(|distances| precomputed_distance(distances, id1, id2))
+                    .map(Distance::Exact)
+                    .unwrap_or_else(|| calculate_distance(row1, row2, options));
+
+                Some((id1.as_str(), id2.as_str(), dist))
+            })
+        })
+    }
+}
+
+/// Whether the ordered pair is in the excluded pairs
+pub(crate) fn is_excluded(excluded: Option<&HashSet<(&str, &str)>>, id1: &str, id2: &str) -> bool {
+    excluded.is_some_and(|excluded| excluded.contains(&(id1, id2)))
+}
+
+/// The number of positions where neither sample is missing under the policy, which are the
+/// positions the distance is based on. Only the overlapping length of the rows is compared, and
+/// sketches have no positions.
+///
+/// # Panics
+///
+/// Panics if the rows have different types, like [`calculate_distance`]
+pub fn compared_positions(
+    row1: &SupportedTypeVec,
+    row2: &SupportedTypeVec,
+    policy: &ComparisonPolicy,
+) -> Option<usize> {
+    fn count<T: Site>(sites: impl Iterator<Item = (T, T)>, policy: &ComparisonPolicy) -> usize {
+        sites
+            .filter(|(x, y)| !x.is_missing_under(policy) && !y.is_missing_under(policy))
+            .count()
+    }
+    fn count_eq<T: Site + Copy>(r1: &[T], r2: &[T], policy: &ComparisonPolicy) -> usize {
+        count(r1.iter().copied().zip(r2.iter().copied()), policy)
+    }
+
+    Some(match (row1, row2) {
+        (SupportedTypeVec::Nucleotide(r1), SupportedTypeVec::Nucleotide(r2)) => {
+            if policy.ignore_terminal_gaps {
+                count(r1.zip_without_terminal_gaps(r2), policy)
+            } else {
+                count(r1.iter().zip(r2.iter()), policy)
+            }
+        }
+        (SupportedTypeVec::NucleotideAll(r1), SupportedTypeVec::NucleotideAll(r2)) => {
+            count_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::Cgmlst(r1), SupportedTypeVec::Cgmlst(r2)) => count_eq(r1, r2, policy),
+        (SupportedTypeVec::SHA1Hash(r1), SupportedTypeVec::SHA1Hash(r2)) => {
+            count_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::SHA256Hash(r1), SupportedTypeVec::SHA256Hash(r2)) => {
+            count_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::AminoAcid(r1), SupportedTypeVec::AminoAcid(r2)) => {
+            count_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::Binary(r1), SupportedTypeVec::Binary(r2)) => r1.len().min(r2.len()),
+        (SupportedTypeVec::Sketch(_), SupportedTypeVec::Sketch(_)) => return None,
+        _ => panic!("Cannot compare rows of different types"),
+    })
+}
+
+/// The positions where the two rows differ under the policy, in increasing order, or None for
+/// sketches, which have no positions. Every difference is listed, without the maximum distance.
+///
+/// # Panics
+///
+/// Panics if the rows have different types, like [`calculate_distance`]
+pub fn differing_positions(
+    row1: &SupportedTypeVec,
+    row2: &SupportedTypeVec,
+    policy: &ComparisonPolicy,
+) -> Option<Vec<usize>> {
+    fn differing<T: Site>(
+        sites: impl Iterator<Item = (T, T)>,
+        policy: &ComparisonPolicy,
+    ) -> Vec<usize> {
+        sites
+            .enumerate()
+            .filter(|(_, (x, y))| x.differs(y, policy))
+            .map(|(index, _)| index)
+            .collect()
+    }
+    fn differing_eq<T: Site + Copy>(r1: &[T], r2: &[T], policy: &ComparisonPolicy) -> Vec<usize> {
+        differing(r1.iter().copied().zip(r2.iter().copied()), policy)
+    }
+
+    Some(match (row1, row2) {
+        (SupportedTypeVec::Nucleotide(r1), SupportedTypeVec::Nucleotide(r2)) => {
+            if policy.ignore_terminal_gaps {
+                differing(r1.zip_without_terminal_gaps(r2), policy)
+            } else {
+                differing(r1.iter().zip(r2.iter()), policy)
+            }
+        }
+        (SupportedTypeVec::NucleotideAll(r1), SupportedTypeVec::NucleotideAll(r2)) => {
+            differing_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::Cgmlst(r1), SupportedTypeVec::Cgmlst(r2)) => {
+            differing_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::SHA1Hash(r1), SupportedTypeVec::SHA1Hash(r2)) => {
+            differing_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::SHA256Hash(r1), SupportedTypeVec::SHA256Hash(r2)) => {
+            differing_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::AminoAcid(r1), SupportedTypeVec::AminoAcid(r2)) => {
+            differing_eq(r1, r2, policy)
+        }
+        (SupportedTypeVec::Binary(r1), SupportedTypeVec::Binary(r2)) => {
+            differing(r1.iter().zip(r2.iter()), policy)
+        }
+        (SupportedTypeVec::Sketch(_), SupportedTypeVec::Sketch(_)) => return None,
+        _ => panic!("Cannot compare rows of different types"),
+    })
+}
+
+/// Calculates the distance between two rows of the same type
+///
+/// # Panics
+///
+/// Panics if the rows have different types, which cannot happen for rows of a [`HomogeneousMatrix`]
+pub fn calculate_distance(
+    row1: &SupportedTypeVec,
+    row2: &SupportedTypeVec,
+    options: &DistanceOptions,
+) -> Distance {
+    match (row1, row2) {
+        (SupportedTypeVec::Nucleotide(r1), SupportedTypeVec::Nucleotide(r2)) => {
+            match options.model {
+                Model::Hamming | Model::Jaccard => compute_distance_packed(r1, r2, options),
+                Model::K80 => kimura_distance(&count_substitutions(r1, r2), options),
+                Model::Tn93 => tamura_nei_distance(&count_substitutions(r1, r2), options),
+            }
+        }
+        (SupportedTypeVec::NucleotideAll(r1), SupportedTypeVec::NucleotideAll(r2)) => {
+            compute_distance_nucleotide_all(r1, r2, options)
+        }
+        (SupportedTypeVec::Cgmlst(r1), SupportedTypeVec::Cgmlst(r2)) => match options.costs {
+            Some(costs) => compute_cost_distance(r1, r2, costs, options),
+            None => compute_distance_eq(r1, r2, options),
+        },
+        (SupportedTypeVec::SHA1Hash(r1), SupportedTypeVec::SHA1Hash(r2)) => {
+            compute_distance_hash(r1, r2, options)
+        }
+        (SupportedTypeVec::SHA256Hash(r1), SupportedTypeVec::SHA256Hash(r2)) => {
+            compute_distance_hash(r1, r2, options)
+        }
+        (SupportedTypeVec::AminoAcid(r1), SupportedTypeVec::AminoAcid(r2)) => {
+            compute_distance_eq(r1, r2, options)
+        }
+        (SupportedTypeVec::Sketch(s1), SupportedTypeVec::Sketch(s2)) => {
+            s1.jaccard(s2).map_or(Distance::Undefined, |jaccard| {
+                Distance::Estimate(1.0 - jaccard)
+            })
+        }
+        (SupportedTypeVec::Binary(r1), SupportedTypeVec::Binary(r2)) => {
+            compute_distance_presence(r1, r2, options)
+        }
+        _ => panic!("Cannot compare rows of different types"),
+    }
+}
+
+/// Returns the number of differences between the rows if it is at most `threshold`, or None when
+/// it is larger or the distance is not a count. Counting stops as soon as the threshold is
+/// exceeded, so this is cheaper than the exact distance for pairs that are far apart. The
+/// maximum distance of the options is replaced by the threshold.
+pub fn distance_at_most(
+    row1: &SupportedTypeVec,
+    row2: &SupportedTypeVec,
+    threshold: usize,
+    options: &DistanceOptions,
+) -> Option<usize> {
+    let options = DistanceOptions {
+        maxdist: Some(threshold.saturating_add(1)),
+        ..*options
+    };
+    match calculate_distance(row1, row2, &options) {
+        Distance::Exact(dist) => Some(dist),
+        _ => None,
+    }
+}
+
+/// Whether the sites are compared with the default policy and without weights or a minimum of
+/// shared positions, so only the differing positions have to be counted
+fn uses_default_comparison(options: &DistanceOptions) -> bool {
+    options.policy == ComparisonPolicy::default()
+        && options.min_shared.is_none()
+        && options.weights.is_none()
+}
+
+/// Uses the word-wise comparison when the default comparison applies, and otherwise falls back
+/// to comparing the unpacked nucleotides one by one.
+fn compute_distance_packed(
+    row1: &PackedNucleotides,
+    row2: &PackedNucleotides,
+    options: &DistanceOptions,
+) -> Distance {
+    if uses_default_comparison(options) {
+        let maxdist = options.maxdist.unwrap_or(usize::MAX);
+        return capped_distance(row1.count_mismatches(row2, maxdist), options);
+    }
+    if options.policy.ignore_terminal_gaps {
+        return compute_distance_sites(row1.zip_without_terminal_gaps(row2), options);
+    }
+    compute_distance_sites(row1.iter().zip(row2.iter()), options)
+}
+
+/// Counts the differing bits word by word unless the loci are weighted, or computes the Jaccard
+/// distance for that model. No position is missing, so all overlapping positions are shared.
+fn compute_distance_presence(
+    row1: &PackedPresence,
+    row2: &PackedPresence,
+    options: &DistanceOptions,
+) -> Distance {
+    if options.weights.is_some() {
+        return compute_distance_sites(row1.iter().zip(row2.iter()), options);
+    }
+    if row1.len().min(row2.len()) < options.min_shared.unwrap_or(0) {
+        return Distance::Undefined;
+    }
+    if options.model == Model::Jaccard {
+        let (intersection, union) = row1.intersection_and_union(row2);
+        if union == 0 {
+            return Distance::Estimate(0.0);
+        }
+        return Distance::Estimate(1.0 - intersection as f64 / union as f64);
+    }
+    let maxdist = options.maxdist.unwrap_or(usize::MAX);
+    capped_distance(row1.count_mismatches(row2, maxdist), options)
+}
+
+/// Uses the SIMD comparison of the simd feature when the default comparison applies
+fn compute_distance_nucleotide_all(
+    row1: &[NucleotideAll],
+    row2: &[NucleotideAll],
+    options: &DistanceOptions,
+) -> Distance {
+    #[cfg(feature = "simd")]
+    if uses_default_comparison(options) {
+        let maxdist = options.maxdist.unwrap_or(usize::MAX);
+        let count = crate::simd::count_nucleotide_mismatches(row1, row2, maxdist);
+        return capped_distance(count, options);
+    }
+    compute_distance_eq(row1, row2, options)
+}
+
+/// Like [`compute_distance_nucleotide_all`] for the hashes of the cgmlst-hash input format
+fn compute_distance_hash<const N: usize>(
+    row1: &[Hash<N>],
+    row2: &[Hash<N>],
+    options: &DistanceOptions,
+) -> Distance {
+    #[cfg(feature = "simd")]
+    if uses_default_comparison(options) {
+        let maxdist = options.maxdist.unwrap_or(usize::MAX);
+        let count = crate::simd::count_hash_mismatches(row1, row2, maxdist);
+        return capped_distance(count, options);
+    }
+    compute_distance_eq(row1, row2, options)
+}
+
+fn compute_distance_eq<T: Site + Copy>(
+    row1: &[T],
+    row2: &[T],
+    options: &DistanceOptions,
+) -> Distance {
+    compute_distance_sites(row1.iter().copied().zip(row2.iter().copied()), options)
+}
+
+fn compute_distance_sites<T: Site>(
+    sites: impl Iterator<Item = (T, T)>,
+    options: &DistanceOptions,
+) -> Distance {
+    if let Some(weights) = options.weights {
+        return compute_weighted_distance(sites, weights, options);
+    }
+    let maxdist = options.maxdist.unwrap_or(usize::MAX);
+    let min_shared = options.min_shared.unwrap_or(0);
+    let mut count = 0;
+    let mut shared = 0;
+
+    for (x, y) in sites {
+        // Shared positions are only counted until the threshold is reached
+        if shared < min_shared
+            && !x.is_missing_under(&options.policy)
+            && !y.is_missing_under(&options.policy)
+        {
+            shared += 1;
+        }
+        if x.differs(&y, &options.policy) {
+            count += 1;
+            if count >= maxdist && shared >= min_shared {
+                break;
+            }
+        }
+    }
+    if shared < min_shared {
+        Distance::Undefined
+    } else {
+        capped_distance(count, options)
+    }
+}
+
+/// Sums the weights of the differing sites instead of counting them
+fn compute_weighted_distance<T: Site>(
+    sites: impl Iterator<Item = (T, T)>,
+    weights: &[f64],
+    options: &DistanceOptions,
+) -> Distance {
+    let maxdist = options
+        .maxdist
+        .map_or(f64::INFINITY, |maxdist| maxdist as f64);
+    let min_shared = options.min_shared.unwrap_or(0);
+    let mut sum = 0.0;
+    let mut shared = 0;
+
+    for ((x, y), weight) in sites.zip(weights) {
+        if shared < min_shared
+            && !x.is_missing_under(&options.policy)
+            && !y.is_missing_under(&options.policy)
+        {
+            shared += 1;
+        }
+        if x.differs(&y, &options.policy) {
+            sum += weight;
+            if sum >= maxdist && shared >= min_shared {
+                break;
+            }
+        }
+    }
+    match options.maxdist {
+        _ if shared < min_shared => Distance::Undefined,
+        Some(maxdist) if sum >= maxdist as f64 => Distance::AtLeast(maxdist),
+        _ => Distance::Estimate(sum),
+    }
+}
+
+/// The cost of a difference between two alleles of a locus, by the index of the locus and the
+/// two allele numbers in increasing order
+pub type CostMatrix = HashMap<(usize, u16, u16), f64>;
+
+/// Sums the costs of the differing loci, where a difference that is not in the cost matrix costs
+/// 1 and a difference with a missing allele is never looked up. Every difference needs a lookup,
+/// so this is slower than the comparison without costs.
+fn compute_cost_distance(
+    row1: &[ChewBBACAinteger],
+    row2: &[ChewBBACAinteger],
+    costs: &CostMatrix,
+    options: &DistanceOptions,
+) -> Distance {
+    let maxdist = options
+        .maxdist
+        .map_or(f64::INFINITY, |maxdist| maxdist as f64);
+    let min_shared = options.min_shared.unwrap_or(0);
+    let mut sum = 0.0;
+    let mut shared = 0;
+
+    for (locus, (x, y)) in row1.iter().zip(row2).enumerate() {
+        let called = !x.is_missing() && !y.is_missing();
+        if shared < min_shared && called {
+            shared += 1;
+        }
+        if x.differs(y, &options.policy) {
+            let (a, b) = (x.allele(), y.allele());
+            sum += if called {
+                costs
+                    .get(&(locus, a.min(b), a.max(b)))
+                    .copied()
+                    .unwrap_or(1.0)
+            } else {
+                1.0
+            };
+            if sum >= maxdist && shared >= min_shared {
+                break;
+            }
+        }
+    }
+    match options.maxdist {
+        _ if shared < min_shared => Distance::Undefined,
+        Some(maxdist) if sum >= maxdist as f64 => Distance::AtLeast(maxdist),
+        _ => Distance::Estimate(sum),
+    }
+}
+
+/// Reads the costs of differences between alleles from lines with the header name or the index
+/// of the locus, starting at 0 for the first column after the ID, the two allele numbers and the cost. The
+/// order of the alleles does not matter.
+pub fn read_cost_matrix<R: BufRead>(
+    reader: R,
+    separator: char,
+    loci: usize,
+    names: Option<&[String]>,
+) -> Result<CostMatrix, Box<dyn Error>> {
+    let mut costs = HashMap::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(separator);
+        let locus = fields.next().ok_or("Missing locus")?.trim();
+        let index = parse_locus(locus, names, loci)?;
+        let a: u16 = fields.next().ok_or("Missing allele")?.trim().parse()?;
+        let b: u16 = fields
+            .next()
+            .ok_or("Missing second allele")?
+            .trim()
+            .parse()?;
+        let cost: f64 = fields.next().ok_or("Missing cost")?.trim().parse()?;
+        let cost = non_negative(cost, "Cost", locus, number + 1)?;
+        costs.insert((index, a.min(b), a.max(b)), cost);
+    }
+    Ok(costs)
+}
+
+/// Reads the loci to keep, one header name or index per line
+pub fn read_loci<R: BufRead>(reader: R) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut loci = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let locus = line.trim();
+        if !locus.is_empty() {
+            loci.push(locus.to_string());
+        }
+    }
+    Ok(loci)
+}
+
+/// Reads the weights of the loci from lines with the header name or the index of the locus,
+/// starting at 0 for the first column after the ID, and its weight. Loci that are not listed have a weight of 1.
+pub fn read_weights<R: BufRead>(
+    reader: R,
+    separator: char,
+    loci: usize,
+    names: Option<&[String]>,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut weights = vec![1.0; loci];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(separator);
+        let locus = fields.next().ok_or("Missing locus")?.trim();
+        let index = parse_locus(locus, names, loci)?;
+        let weight: f64 = fields.next().ok_or("Missing weight")?.trim().parse()?;
+        weights[index] = non_negative(weight, "Weight", locus, number + 1)?;
+    }
+    Ok(weights)
+}
+
+/// Rejects a weight or cost that is not a finite number of at least 0
+fn non_negative(value: f64, name: &str, locus: &str, line: usize) -> Result<f64, Box<dyn Error>> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!(
+            "{} {} of locus {} on line {} is not a finite number of at least 0",
+            name, value, locus, line
+        )
+        .into());
+    }
+    Ok(value)
+}
+
+/// Marks the count as a lower bound when it reached the maximum distance
+fn capped_distance(count: usize, options: &DistanceOptions) -> Distance {
+    match options.maxdist {
+        Some(maxdist) if count >= maxdist => Distance::AtLeast(maxdist),
+        _ => Distance::Exact(count),
+    }
+}
+
+/// Aggregate statistics of the missing positions over all pairs of samples
+#[derive(Debug, PartialEq)]
+pub struct MissingStatistics {
+    /// The number of positions of the longest sample
+    pub positions: usize,
+    /// The average number of positions that are missing in either sample of a pair
+    pub mean_missing: f64,
+    /// The average number of positions that are non-missing in both samples of a pair
+    pub mean_compared: f64,
+}
+
+/// Computes the missing statistics from the number of missing samples per position, which gives
+/// the number of pairs that miss a position without comparing all pairs. Positions are missing
+/// under the comparison policy like in [`compared_positions`], and positions past the end of a
+/// shorter row are missing. With `ignore_terminal_gaps` the terminal gaps of every sample are
+/// missing, while a pair only leaves out the positions before and after the first and last
+/// position that neither sample has a gap at, so the averages can differ slightly from the
+/// compared positions of the pairs. Sketches have no positions to report.
+pub fn missing_statistics<R: Borrow<SupportedTypeVec>>(
+    rows: impl IntoIterator<Item = R>,
+    policy: &ComparisonPolicy,
+) -> MissingStatistics {
+    let mut missing_samples: Vec<usize> = Vec::new();
+    let mut lengths = Vec::new();
+    for row in rows {
+        let missing = row.borrow().missing_under(policy);
+        if missing.len() > missing_samples.len() {
+            missing_samples.resize(missing.len(), 0);
+        }
+        for (count, _) in missing_samples.iter_mut().zip(&missing).filter(|(_, &m)| m) {
+            *count += 1;
+        }
+        lengths.push(missing.len());
+    }
+    lengths.sort_unstable();
+    let positions = missing_samples.len();
+    let samples = lengths.len();
+    let pairs = |n: usize| (n * n.saturating_sub(1) / 2) as f64;
+    let total_pairs = pairs(samples);
+
+    let missing_pairs: f64 = missing_samples
+        .iter()
+        .enumerate()
+        .map(|(index, missing)| {
+            let shorter = lengths.partition_point(|&len| len <= index);
+            total_pairs - pairs(samples - missing - shorter)
+        })
+        .sum();
+
+    let mean_missing = if total_pairs > 0.0 {
+        missing_pairs / total_pairs
+    } else {
+        0.0
+    };
+    MissingStatistics {
+        positions,
+        mean_missing,
+        mean_compared: positions as f64 - mean_missing,
+    }
+}
+
+/// The positions that are non-missing in all samples, for the complete deletion. The mask is as
+/// long as the longest row, and positions past the end of a shorter row are missing.
+pub fn complete_deletion_mask(data_map: &HomogeneousMatrix) -> Vec<bool> {
+    let positions = data_map.iter().map(|(_, row)| row.len()).max().unwrap_or(0);
+    (0..positions)
+        .into_par_iter()
+        .map(|index| data_map.iter().all(|(_, row)| !row.is_missing(index)))
+        .collect()
+}
+
+/// The positions that can change a distance. A position is invariant when all samples have
+/// the same value, or when it is missing in all samples, unless gaps are counted as differences.
+pub fn variable_columns_mask(data_map: &HomogeneousMatrix, policy: &ComparisonPolicy) -> Vec<bool> {
+    let positions = data_map.iter().map(|(_, row)| row.len()).max().unwrap_or(0);
+    let Some((_, first)) = data_map.first() else {
+        return Vec::new();
+    };
+    (0..positions)
+        .into_par_iter()
+        .map(|index| {
+            let identical = data_map
+                .iter()
+                .all(|(_, row)| first.identical_at(row, index));
+            let all_missing =
+                !policy.gaps_as_diff && data_map.iter().all(|(_, row)| row.is_missing(index));
+            !identical && !all_missing
+        })
+        .collect()
+}
+
+/// Removes the invariant positions of [`variable_columns_mask`] and returns how many were
+/// removed. The Hamming distances stay the same, but the number of compared positions does not.
+pub fn remove_identical_columns(
+    data_map: &mut HomogeneousMatrix,
+    policy: &ComparisonPolicy,
+) -> Result<usize, Box<dyn Error>> {
+    let keep = variable_columns_mask(data_map, policy);
+    let removed = keep.iter().filter(|&&keep| !keep).count();
+    if removed > 0 {
+        *data_map = select_columns(std::mem::take(data_map), &keep)?;
+    }
+    Ok(removed)
+}
+
+/// Keeps only the positions of the mask in every row
+pub fn select_columns(
+    data_map: HomogeneousMatrix,
+    keep: &[bool],
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let rows = data_map
+        .into_inner()
+        .into_par_iter()
+        .map(|(id, row)| Ok((id, row.select_columns(keep)?)))
+        .collect::<Result<InputMatrix, &str>>()?;
+    Ok(HomogeneousMatrix::try_from(rows)?)
+}
+
+/// Options that control how the distances are written
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub output_format: OutputFormat,
+    /// The output mode the distances were computed with
+    pub output_mode: OutputMode,
+    pub output_sep: String,
+    /// The column names of the header line, if one should be written
+    pub header: Option<Vec<String>>,
+    /// Written instead of the distance when it is undefined
+    pub undefined_value: String,
+    /// Truncate or pad the sample names in Phylip output to 10 characters
+    pub phylip_strict: bool,
+    /// Only write the pairs with a distance at or below this cutoff. Applies to the tabular format
+    pub max_output_dist: Option<f64>,
+    /// The number of decimals of distances that are not counts. By default they are written in
+    /// the shortest form, which is an integer for whole numbers
+    pub precision: Option<usize>,
+    /// Leave out the comparisons of a sample with itself. Applies to the tabular format
+    pub no_self: bool,
+    /// Swap the two sample IDs of every pair in the tabular and NDJSON formats
+    pub transpose: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            output_format: OutputFormat::Tabular,
+            output_mode: OutputMode::LowerTriangle,
+            output_sep: "\t".to_string(),
+            header: None,
+            undefined_value: "NA".to_string(),
+            phylip_strict: false,
+            max_output_dist: None,
+            precision: None,
+            no_self: false,
+            transpose: false,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Whether the distance passes the output cutoff. Undefined distances never do.
+    fn within_cutoff(&self, distance: Distance) -> bool {
+        match self.max_output_dist {
+            None => true,
+            // Undefined distances never pass the cutoff
+            Some(cutoff) => distance.as_f64().is_some_and(|dist| dist <= cutoff),
+        }
+    }
+
+    /// The IDs of the pair in the order they are written in the long format
+    fn ordered<'b>(&self, id1: &'b str, id2: &'b str) -> (&'b str, &'b str) {
+        if self.transpose {
+            (id2, id1)
+        } else {
+            (id1, id2)
+        }
+    }
+
+    /// Whether the pair passes the cutoff and the self-comparison filter of the long format
+    fn written_in_long_format(&self, (id1, id2, distance): &(&str, &str, Distance)) -> bool {
+        self.within_cutoff(*distance) && !(self.no_self && id1 == id2)
+    }
+
+    fn format(&self, distance: Distance) -> FormattedDistance<'_> {
+        FormattedDistance {
+            distance,
+            options: self,
+        }
+    }
+}
+
+struct FormattedDistance<'a> {
+    distance: Distance,
+    options: &'a OutputOptions,
+}
+
+impl std::fmt::Display for FormattedDistance<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.distance {
+            Distance::Exact(dist) => write!(f, "{}", dist),
+            // Phylip, NEXUS and condensed readers only accept numbers, so the bound is written as is
+            Distance::AtLeast(dist) => match self.options.output_format {
+                OutputFormat::Phylip | OutputFormat::Nexus | OutputFormat::Condensed => {
+                    write!(f, "{}", dist)
+                }
+                OutputFormat::Tabular
+                | OutputFormat::Matrix
+                | OutputFormat::Binary
+                | OutputFormat::Ndjson => write!(f, ">={}", dist),
+            },
+            Distance::Estimate(dist) => match self.options.precision {
+                Some(precision) => write!(f, "{:.*}", precision, dist),
+                None => write!(f, "{}", dist),
+            },
+            Distance::Undefined => write!(f, "{}", self.options.undefined_value),
+        }
+    }
+}
+
+/// Returns the sample IDs in input order
+pub fn sample_ids(data_map: &InputMatrix) -> Vec<&str> {
+    data_map.iter().map(|(id, _)| id.as_str()).collect()
+}
+
+/// Keeps the `k` nearest neighbors of every sample from the distances of the full output mode,
+/// together with the neighbors that tie with the k-th nearest one. The neighbors of a sample are
+/// sorted by distance, and self-comparisons and undefined or non-finite distances are left out.
+/// Only the distances of one sample are kept in memory at a time.
+pub fn nearest_neighbors<'a>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    samples: usize,
+    k: usize,
+) -> impl Iterator<Item = (&'a str, &'a str, Distance)> {
+    distance_rows(distances, samples)
+        .enumerate()
+        .flat_map(move |(row, pairs)| {
+            let neighbors = pairs
+                .into_iter()
+                .enumerate()
+                .filter(|(column, _)| *column != row)
+                .map(|(_, pair)| pair)
+                .collect();
+            closest(neighbors, k)
+        })
+}
+
+/// Finds the `k` nearest neighbors of every sample like [`nearest_neighbors`], but compares the
+/// samples itself. Once a sample has `k` neighbors, the distance of its k-th nearest neighbor so
+/// far is the threshold of [`distance_at_most`] for its remaining comparisons, so the samples
+/// that are farther away are only compared until they exceed it. The distances must be counts.
+pub fn compute_nearest_neighbors<'a>(
+    data_map: &'a HomogeneousMatrix,
+    options: &DistanceOptions,
+    k: usize,
+    already_computed: Option<&HashMap<(&str, &str), usize>>,
+) -> Vec<PairDistance<'a>> {
+    let rows: Vec<_> = (0..data_map.len())
+        .into_par_iter()
+        .map(|i| {
+            let (id1, row1) = &data_map[i];
+            // The k smallest distances so far, in ascending order
+            let mut nearest: Vec<usize> = Vec::new();
+            let mut neighbors = Vec::new();
+            for (j, (id2, row2)) in data_map.iter().enumerate() {
+                if j == i {
+                    continue;
+                }
+                // Distances from the maximum distance on are all capped to it, so they tie
+                let threshold = k
+                    .checked_sub(1)
+                    .and_then(|last| nearest.get(last).copied())
+                    .filter(|&kth| options.maxdist.is_none_or(|maxdist| kth < maxdist));
+                let dist = match already_computed
+                    .and_then(|distances| precomputed_distance(distances, id1, id2))
+                {
+                    Some(dist) => Distance::Exact(dist),
+                    None => match threshold {
+                        Some(threshold) => match distance_at_most(row1, row2, threshold, options) {
+                            Some(dist) => Distance::Exact(dist),
+                            None => continue,
+                        },
+                        None => calculate_distance(row1, row2, options),
+                    },
+                };
+                if let Distance::Exact(count) | Distance::AtLeast(count) = dist {
+                    nearest.insert(nearest.partition_point(|&near| near <= count), count);
+                    nearest.truncate(k);
+                }
+                neighbors.push((id1.as_str(), id2.as_str(), dist));
+            }
+            closest(neighbors, k)
+        })
+        .collect();
+    rows.into_iter().flatten().collect()
+}
+
+/// Sorts the neighbors of a sample by distance and keeps the `k` nearest ones, together with the
+/// neighbors that tie with the k-th nearest one. Undefined and non-finite distances are left out.
+fn closest(mut neighbors: Vec<PairDistance>, k: usize) -> Vec<PairDistance> {
+    neighbors.retain(|(_, _, dist)| dist.as_f64().is_some_and(f64::is_finite));
+    // The sort is stable, so tied neighbors stay in input order
+    let value = |dist: &Distance| dist.as_f64().unwrap_or(f64::INFINITY);
+    neighbors.sort_by(|(_, _, a), (_, _, b)| value(a).total_cmp(&value(b)));
+    if let Some(kth) = neighbors
+        .get(k.saturating_sub(1))
+        .map(|(_, _, dist)| value(dist))
+    {
+        neighbors.retain(|(_, _, dist)| value(dist) <= kth);
+    }
+    neighbors
+}
+
+/// The field separator of tabular input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputSeparator {
+    Char(char),
+    /// Detect the separator of every file from its first data line
+    Auto,
+}
+
+impl std::str::FromStr for InputSeparator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(InputSeparator::Auto);
+        }
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(InputSeparator::Char(c)),
+            _ => Err("Expected a single character or 'auto'".to_string()),
+        }
+    }
+}
+
+impl InputSeparator {
+    /// Returns the separator character. A separator that is detected is detected from the first
+    /// data line in the buffer of the reader, so nothing is consumed.
+    pub fn resolve<R: BufRead>(
+        self,
+        reader: &mut R,
+        skip_header: bool,
+    ) -> Result<char, Box<dyn Error>> {
+        let InputSeparator::Char(separator) = self else {
+            let text = String::from_utf8_lossy(reader.fill_buf()?);
+            let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+            let first = lines.next();
+            let line = if skip_header {
+                lines.next().or(first)
+            } else {
+                first
+            };
+            let separator = detect_separator(line.unwrap_or_default());
+            debug!("Detected the input separator {:?}", separator);
+            return Ok(separator);
+        };
+        Ok(separator)
+    }
+}
+
+/// Detects the separator of a line as the one of tab, comma and semicolon that splits the line
+/// into the most fields, preferring them in that order on ties. A line without any of them is
+/// taken to be separated by tabs.
+pub fn detect_separator(line: &str) -> char {
+    let mut best = ('\t', 1);
+    for separator in ['\t', ',', ';'] {
+        let fields = line.split(separator).count();
+        if fields > best.1 {
+            best = (separator, fields);
+        }
+    }
+    best.0
+}
+
+/// Parses a separator that may contain the escape sequences `\t` for a tab and `\\` for a
+/// backslash. Line breaks are rejected since they would break up the rows.
+pub fn parse_separator(s: &str) -> Result<String, String> {
+    let mut separator = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => separator.push('\t'),
+                Some('\\') => separator.push('\\'),
+                Some(other) => return Err(format!("Unsupported escape sequence \\{}", other)),
+                None => return Err("The separator ends with a single backslash".to_string()),
+            },
+            '\n' | '\r' => return Err("The separator cannot contain a line break".to_string()),
+            c => separator.push(c),
+        }
+    }
+    if separator.is_empty() {
+        return Err("The separator cannot be empty".to_string());
+    }
+    Ok(separator)
+}
+
+/// The sums and counts of the distances of every sample to the other samples, for their means.
+/// Self-comparisons and undefined distances are left out, and capped distances count as their
+/// bound.
+#[derive(Debug)]
+pub struct MeanDistances<'a> {
+    output_mode: OutputMode,
+    sums: HashMap<&'a str, (f64, u64)>,
+}
+
+impl<'a> MeanDistances<'a> {
+    pub fn new(output_mode: OutputMode) -> Self {
+        MeanDistances {
+            output_mode,
+            sums: HashMap::new(),
+        }
+    }
+
+    /// Adds a distance of the stream. The lower triangle holds every pair once, so the distance
+    /// is added to both samples, while the full matrix holds it again with the IDs swapped.
+    pub fn add(&mut self, id1: &'a str, id2: &'a str, distance: Distance) {
+        let Some(dist) = distance.as_f64() else {
+            return;
+        };
+        if id1 == id2 {
+            return;
+        }
+        let ids = match self.output_mode {
+            OutputMode::LowerTriangle | OutputMode::LowerTriangleDiag => &[id1, id2][..],
+            OutputMode::Full => &[id1][..],
+        };
+        for id in ids {
+            let (sum, count) = self.sums.entry(id).or_default();
+            *sum += dist;
+            *count += 1;
+        }
+    }
+
+    /// The mean distance of the sample to the others, or None if it has no defined distances
+    pub fn mean(&self, id: &str) -> Option<f64> {
+        self.sums.get(id).map(|&(sum, count)| sum / count as f64)
+    }
+
+    /// Writes a line with every sample and its mean distance in the order of `sample_ids`
+    pub fn write<W: Write>(
+        &self,
+        sample_ids: &[&str],
+        mut writer: W,
+        options: &OutputOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let sep = options.output_sep.as_str();
+        if options.header.is_some() {
+            writeln!(writer, "sample{}mean_distance", sep)?;
+        }
+        for id in sample_ids {
+            let mean = self
+                .mean(id)
+                .map_or(Distance::Undefined, Distance::Estimate);
+            writeln!(writer, "{}{}{}", id, sep, options.format(mean))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the sample IDs one per line, so the order of the output can be reconstructed
+pub fn write_sample_order<W: Write>(
+    sample_ids: &[&str],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    for id in sample_ids {
+        writeln!(writer, "{}", id)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a sample order like the one of [`write_sample_order`], one ID per line. The IDs are
+/// normalized like the sample IDs.
+pub fn read_sample_order<R: BufRead>(
+    reader: R,
+    normalization: Option<IdNormalization>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let id = normalized_id(&line, normalization);
+        if !seen.insert(id.clone()) {
+            return Err(format!("Duplicate sample ID {} in the order", id).into());
+        }
+        order.push(id);
+    }
+    Ok(order)
+}
+
+/// Sorts the samples in the order of the IDs. Samples that are not in the order and IDs that are
+/// not in the input return an error, unless `tolerant` is set. The samples that are not in the
+/// order then come last in input order, and the IDs that are not in the input are skipped.
+pub fn reorder_samples(
+    data_map: HomogeneousMatrix,
+    order: &[String],
+    tolerant: bool,
+) -> Result<HomogeneousMatrix, Box<dyn Error>> {
+    let positions: HashMap<&str, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (id.as_str(), i))
+        .collect();
+    if !tolerant {
+        if let Some((id, _)) = data_map
+            .iter()
+            .find(|(id, _)| !positions.contains_key(id.as_str()))
+        {
+            return Err(format!("Sample {} is not in the order", id).into());
+        }
+        let ids: HashSet<&str> = data_map.iter().map(|(id, _)| id.as_str()).collect();
+        if let Some(id) = order.iter().find(|id| !ids.contains(id.as_str())) {
+            return Err(format!("Sample {} of the order is not in the input", id).into());
+        }
+    }
+    let mut rows = data_map.into_inner();
+    // The sort is stable, so the samples that are not in the order keep their input order
+    rows.sort_by_key(|(id, _)| positions.get(id.as_str()).copied().unwrap_or(order.len()));
+    Ok(HomogeneousMatrix::try_from(rows)?)
+}
+
+/// Writes the parsed rows back as FASTA with one line per sequence, as given by
+/// [`SupportedTypeVec::to_sequence`]. Only for the sequence input formats.
+pub fn write_parsed_fasta<W: Write>(
+    data_map: &InputMatrix,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    for (id, row) in data_map {
+        let Some(sequence) = row.to_sequence() else {
+            return Err("Only sequences can be written as FASTA".into());
+        };
+        writeln!(writer, ">{}", id)?;
+        writer.write_all(&sequence)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The substitutions between two aligned nucleotide sequences at sites where both have a base
+#[derive(Debug, PartialEq, Default)]
+struct Substitutions {
+    /// A<->G
+    purine_transitions: usize,
+    /// C<->T
+    pyrimidine_transitions: usize,
+    transversions: usize,
+    compared: usize,
+}
+
+fn count_substitutions(row1: &PackedNucleotides, row2: &PackedNucleotides) -> Substitutions {
+    let mut substitutions = Substitutions::default();
+    for (x, y) in row1.iter().zip(row2.iter()) {
+        if !x.is_base() || !y.is_base() {
+            continue;
+        }
+        substitutions.compared += 1;
+        if x != y {
+            if !x.is_transition(&y) {
+                substitutions.transversions += 1;
+            } else if x.is_purine() {
+                substitutions.purine_transitions += 1;
+            } else {
+                substitutions.pyrimidine_transitions += 1;
+            }
+        }
+    }
+    substitutions
+}
+
+/// The frequencies of A, C, G and T
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BaseFrequencies(pub [f64; 4]);
+
+impl Default for BaseFrequencies {
+    fn default() -> Self {
+        BaseFrequencies([0.25; 4])
+    }
+}
+
+/// Estimates the base frequencies from the unambiguous bases of the nucleotide rows. Equal
+/// frequencies are returned when there are no bases.
+pub fn base_frequencies<R: Borrow<SupportedTypeVec>>(
+    rows: impl IntoIterator<Item = R>,
+) -> BaseFrequencies {
+    let mut counts = [0usize; 4];
+    for row in rows {
+        if let SupportedTypeVec::Nucleotide(row) = row.borrow() {
+            for index in row.iter().filter_map(|nucleotide| nucleotide.base_index()) {
+                counts[index] += 1;
+            }
+        }
+    }
+    let total: usize = counts.iter().sum();
+    if total == 0 {
+        return BaseFrequencies::default();
+    }
+    BaseFrequencies(counts.map(|count| count as f64 / total as f64))
+}
+
+/// The Kimura 2-parameter distance. It is undefined when the pair is too divergent for the
+/// logarithms, or when fewer sites than `min_shared` could be compared.
+fn kimura_distance(substitutions: &Substitutions, options: &DistanceOptions) -> Distance {
+    if substitutions.compared == 0 || substitutions.compared < options.min_shared.unwrap_or(0) {
+        return Distance::Undefined;
+    }
+    let transitions = substitutions.purine_transitions + substitutions.pyrimidine_transitions;
+    let p = transitions as f64 / substitutions.compared as f64;
+    let q = substitutions.transversions as f64 / substitutions.compared as f64;
+    let a = 1.0 - 2.0 * p - q;
+    let b = 1.0 - 2.0 * q;
+    if a <= 0.0 || b <= 0.0 {
+        return Distance::Undefined;
+    }
+    // The distance is never negative, but identical samples would give -0
+    Distance::Estimate((-0.5 * a.ln() - 0.25 * b.ln()).abs())
+}
+
+/// The Tamura-Nei distance. Like the Kimura distance it is undefined when the pair is too
+/// divergent for the logarithms, or when fewer sites than `min_shared` could be compared. It is
+/// also undefined when one of the bases does not occur in the alignment.
+fn tamura_nei_distance(substitutions: &Substitutions, options: &DistanceOptions) -> Distance {
+    if substitutions.compared == 0 || substitutions.compared < options.min_shared.unwrap_or(0) {
+        return Distance::Undefined;
+    }
+    let BaseFrequencies([g_a, g_c, g_g, g_t]) = options.base_frequencies.unwrap_or_default();
+    if g_a * g_c * g_g * g_t == 0.0 {
+        return Distance::Undefined;
+    }
+    let g_r = g_a + g_g;
+    let g_y = g_c + g_t;
+
+    let compared = substitutions.compared as f64;
+    let p1 = substitutions.purine_transitions as f64 / compared;
+    let p2 = substitutions.pyrimidine_transitions as f64 / compared;
+    let q = substitutions.transversions as f64 / compared;
+
+    let w1 = 1.0 - p1 * g_r / (2.0 * g_a * g_g) - q / (2.0 * g_r);
+    let w2 = 1.0 - p2 * g_y / (2.0 * g_c * g_t) - q / (2.0 * g_y);
+    let w3 = 1.0 - q / (2.0 * g_r * g_y);
+    if w1 <= 0.0 || w2 <= 0.0 || w3 <= 0.0 {
+        return Distance::Undefined;
+    }
+    let distance = -2.0 * g_a * g_g / g_r * w1.ln()
+        - 2.0 * g_c * g_t / g_y * w2.ln()
+        - 2.0 * (g_r * g_y - g_a * g_g * g_y / g_r - g_c * g_t * g_r / g_y) * w3.ln();
+    // The distance is never negative, but identical samples would give -0
+    Distance::Estimate(distance.abs())
+}
+
+pub fn write_distances_to_file<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    writer: W,
+    sample_ids: &[&str],
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let writer = BufWriter::new(writer);
+
+    match options.output_format {
+        OutputFormat::Tabular => write_distances_to_long_format(distances, writer, options),
+        OutputFormat::Phylip => write_distances_to_philip(distances, writer, sample_ids, options),
+        OutputFormat::Matrix => write_square_matrix(distances, writer, sample_ids.len(), options),
+        OutputFormat::Nexus => write_distances_to_nexus(distances, writer, sample_ids, options),
+        OutputFormat::Condensed => write_condensed(distances, writer, sample_ids, options),
+        OutputFormat::Binary => write_binary(distances, writer, sample_ids, options),
+        OutputFormat::Ndjson => write_ndjson(distances, writer, options),
+    }
+}
+
+/// Writes the string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Writes one JSON object per pair and line. The pairs are filtered like the long format.
+fn write_ndjson<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    for (id1, id2, dist) in distances.filter(|pair| options.written_in_long_format(pair)) {
+        let distance = match dist {
+            Distance::Undefined => "null".to_string(),
+            Distance::AtLeast(_) => json_string(&options.format(dist).to_string()),
+            Distance::Estimate(estimate) if !estimate.is_finite() => "null".to_string(),
+            _ => options.format(dist).to_string(),
+        };
+        let (id1, id2) = options.ordered(id1, id2);
+        writeln!(
+            writer,
+            "{{\"id1\":{},\"id2\":{},\"distance\":{}}}",
+            json_string(id1),
+            json_string(id2),
+            distance
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_distances_to_long_format<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    write_long_header(&mut writer, options)?;
+    for (id1, id2, dist) in distances.filter(|pair| options.written_in_long_format(pair)) {
+        write_long_line(&mut writer, id1, id2, dist, options)?;
+    }
+    Ok(())
+}
+
+/// The columns that [`write_distances_with_compared`] writes after the distance
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ComparedColumns {
+    /// The number of compared positions, named `compared` in the header line
+    pub compared: bool,
+    /// The distance as a fraction of the compared positions, named `normalized`
+    pub normalized: bool,
+}
+
+/// The distance divided by the number of compared positions. It is undefined without compared
+/// positions and for distances that were stopped at the maximum, which have no exact fraction.
+fn normalized_distance(dist: Distance, compared: Option<usize>) -> Distance {
+    match (dist, compared) {
+        (Distance::Exact(dist), Some(compared)) if compared > 0 => {
+            Distance::Estimate(dist as f64 / compared as f64)
+        }
+        (Distance::Estimate(dist), Some(compared)) if compared > 0 => {
+            Distance::Estimate(dist / compared as f64)
+        }
+        _ => Distance::Undefined,
+    }
+}
+
+/// Writes the distances in the tabular long format with the number of compared positions of every
+/// pair, the normalized distance, or both as extra columns. The rows are looked up by their
+/// sample IDs, and the positions are counted in parallel for chunks of [`CHUNK_PAIRS`] pairs.
+/// Sketches have no positions, so their count is written as the undefined value.
+pub fn write_distances_with_compared<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    data_map: &HomogeneousMatrix,
+    policy: &ComparisonPolicy,
+    columns: ComparedColumns,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let output_sep = options.output_sep.as_str();
+    if let Some(header) = &options.header {
+        write!(writer, "{}", header.join(output_sep))?;
+        if columns.compared {
+            write!(writer, "{}compared", output_sep)?;
+        }
+        if columns.normalized {
+            write!(writer, "{}normalized", output_sep)?;
+        }
+        writeln!(writer)?;
+    }
+    let rows: HashMap<&str, &SupportedTypeVec> = data_map
+        .iter()
+        .map(|(id, row)| (id.as_str(), row))
+        .collect();
+    let mut distances = distances.filter(|pair| options.written_in_long_format(pair));
+    loop {
+        let chunk: Vec<_> = distances.by_ref().take(CHUNK_PAIRS).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        let compared: Vec<Option<usize>> = chunk
+            .par_iter()
+            .map(|(id1, id2, _)| compared_positions(rows[id1], rows[id2], policy))
+            .collect();
+        for ((id1, id2, dist), compared) in chunk.into_iter().zip(compared) {
+            let (id1, id2) = options.ordered(id1, id2);
+            write!(
+                writer,
+                "{}{}{}{}{}",
+                id1,
+                output_sep,
+                id2,
+                output_sep,
+                options.format(dist)
+            )?;
+            if columns.compared {
+                match compared {
+                    Some(compared) => write!(writer, "{}{}", output_sep, compared)?,
+                    None => write!(writer, "{}{}", output_sep, options.undefined_value)?,
+                }
+            }
+            if columns.normalized {
+                let normalized = normalized_distance(dist, compared);
+                write!(writer, "{}{}", output_sep, options.format(normalized))?;
+            }
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the distances in the tabular long format, split over one writer per distance bin.
+/// Bin `i` holds the distances above `edges[i - 1]` and up to `edges[i]`. The writer after the
+/// bins of the edges gets the distances above the last edge, and the final writer the undefined
+/// distances, so there have to be two writers more than edges. Every writer gets the header.
+/// Returns the number of distances written to each writer.
+pub fn write_binned_distances<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    writers: &mut [W],
+    edges: &[f64],
+    options: &OutputOptions,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    if writers.len() != edges.len() + 2 {
+        return Err(format!(
+            "Expected {} writers for {} bin edges, got {}",
+            edges.len() + 2,
+            edges.len(),
+            writers.len()
+        )
+        .into());
+    }
+    for writer in writers.iter_mut() {
+        write_long_header(writer, options)?;
+    }
+    let mut counts = vec![0; writers.len()];
+    for (id1, id2, dist) in distances.filter(|pair| options.written_in_long_format(pair)) {
+        let bin = match dist.as_f64() {
+            Some(value) => edges.partition_point(|&edge| edge < value),
+            None => edges.len() + 1,
+        };
+        write_long_line(&mut writers[bin], id1, id2, dist, options)?;
+        counts[bin] += 1;
+    }
+    Ok(counts)
+}
+
+/// The number of pairs at every distance
+#[derive(Debug, Default, PartialEq)]
+pub struct DistanceHistogram {
+    /// The number of pairs with every exact distance
+    pub exact: BTreeMap<usize, u64>,
+    /// The number of pairs that reached the maximum distance, by the maximum distance
+    pub at_least: BTreeMap<usize, u64>,
+    /// The number of pairs with an undefined distance
+    pub undefined: u64,
+}
+
+/// Counts the pairs per distance, leaving out the pairs that the long format would leave out.
+/// Only counts can be tallied, so estimated distances return an error.
+pub fn distance_histogram<'a>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    options: &OutputOptions,
+) -> Result<DistanceHistogram, Box<dyn Error>> {
+    let mut histogram = DistanceHistogram::default();
+    for (_, _, dist) in distances.filter(|pair| options.written_in_long_format(pair)) {
+        match dist {
+            Distance::Exact(dist) => *histogram.exact.entry(dist).or_default() += 1,
+            Distance::AtLeast(dist) => *histogram.at_least.entry(dist).or_default() += 1,
+            Distance::Undefined => histogram.undefined += 1,
+            Distance::Estimate(_) => {
+                return Err("The histogram only supports distances that are counts".into())
+            }
+        }
+    }
+    Ok(histogram)
+}
+
+/// Writes a line with the distance and its number of pairs for every distance in the histogram,
+/// followed by the capped and the undefined distances
+pub fn write_histogram<W: Write>(
+    histogram: &DistanceHistogram,
+    mut writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let sep = options.output_sep.as_str();
+    if options.header.is_some() {
+        writeln!(writer, "distance{}count", sep)?;
+    }
+    let exact = histogram
+        .exact
+        .iter()
+        .map(|(&dist, &count)| (Distance::Exact(dist), count));
+    let at_least = histogram
+        .at_least
+        .iter()
+        .map(|(&dist, &count)| (Distance::AtLeast(dist), count));
+    let undefined = (histogram.undefined > 0).then_some((Distance::Undefined, histogram.undefined));
+    for (dist, count) in exact.chain(at_least).chain(undefined) {
+        writeln!(writer, "{}{}{}", options.format(dist), sep, count)?;
+    }
+    Ok(())
+}
+
+fn write_long_header<W: Write>(writer: &mut W, options: &OutputOptions) -> std::io::Result<()> {
+    match &options.header {
+        Some(header) => writeln!(writer, "{}", header.join(&options.output_sep)),
+        None => Ok(()),
+    }
+}
+
+fn write_long_line<W: Write>(
+    writer: &mut W,
+    id1: &str,
+    id2: &str,
+    dist: Distance,
+    options: &OutputOptions,
+) -> std::io::Result<()> {
+    let (id1, id2) = options.ordered(id1, id2);
+    let output_sep = options.output_sep.as_str();
+    writeln!(
+        writer,
+        "{}{}{}{}{}",
+        id1,
+        output_sep,
+        id2,
+        output_sep,
+        options.format(dist)
+    )
+}
+
+/// Writes the lower triangle as a condensed vector in the order of SciPy's `pdist`, so that
+/// `squareform` restores the matrix with the rows and columns in input order. Line `k` holds the
+/// distance between samples `i` and `j` with `i < j`, where the pairs are sorted by `i` and then
+/// by `j`: (0, 1), (0, 2), ..., (0, n - 1), (1, 2), ... This is the transpose of the row-major
+/// order of the lower triangle, so all distances are collected first.
+fn write_condensed<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    sample_ids: &[&str],
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    if options.output_mode != OutputMode::LowerTriangle {
+        return Err("Condensed output requires the lower triangle output mode".into());
+    }
+    let distances: Vec<_> = distances.collect();
+    let n = sample_ids.len();
+    for i in 0..n {
+        for j in i + 1..n {
+            // The pair of row j and column i of the lower triangle
+            match distances.get(j * (j - 1) / 2 + i) {
+                Some((id1, id2, dist)) if *id1 == sample_ids[j] && *id2 == sample_ids[i] => {
+                    writeln!(writer, "{}", options.format(*dist))?
+                }
+                _ => {
+                    return Err(format!(
+                        "Missing distance between {} and {} in condensed output",
+                        sample_ids[j], sample_ids[i]
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The first bytes of the binary output format
+const BINARY_MAGIC: &[u8; 4] = b"DSTL";
+/// The version of the binary output format
+const BINARY_VERSION: u32 = 1;
+/// A binary distance that is undefined
+const BINARY_UNDEFINED: u32 = u32::MAX;
+/// The bit of a binary distance that marks it as capped by maxdist
+const BINARY_CAPPED: u32 = 1 << 31;
+
+/// Writes the lower triangle in the binary output format. All integers are little-endian `u32`:
+/// the magic bytes `DSTL`, the version 1, the number of samples `n`, every sample name as its
+/// length in bytes followed by its UTF-8 bytes, and then the `n * (n - 1) / 2` distances of the
+/// lower triangle in row-major order. An undefined distance is `u32::MAX`, and a distance capped
+/// by maxdist has the highest bit set on top of its bound.
+fn write_binary<'a, W: Write>(
+    mut distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    sample_ids: &[&str],
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    if options.output_mode != OutputMode::LowerTriangle {
+        return Err("Binary output requires the lower triangle output mode".into());
+    }
+    let to_u32 = |value: usize| u32::try_from(value).map_err(|_| "Too large for binary output");
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&BINARY_VERSION.to_le_bytes())?;
+    writer.write_all(&to_u32(sample_ids.len())?.to_le_bytes())?;
+    for id in sample_ids {
+        writer.write_all(&to_u32(id.len())?.to_le_bytes())?;
+        writer.write_all(id.as_bytes())?;
+    }
+
+    for (i, id) in sample_ids.iter().enumerate() {
+        for column_id in &sample_ids[..i] {
+            let value = match distances.next() {
+                Some((id1, id2, dist)) if id1 == *id && id2 == *column_id => match dist {
+                    // The largest values are left out so a capped distance is never undefined
+                    Distance::Exact(dist) if dist < BINARY_CAPPED as usize - 1 => dist as u32,
+                    Distance::AtLeast(dist) if dist < BINARY_CAPPED as usize - 1 => {
+                        dist as u32 | BINARY_CAPPED
+                    }
+                    Distance::Undefined => BINARY_UNDEFINED,
+                    Distance::Estimate(_) => {
+                        return Err("Binary output only holds distances that are counts".into())
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Distance {} is too large for binary output",
+                            dist.as_f64().unwrap_or_default()
+                        )
+                        .into())
+                    }
+                },
+                _ => {
+                    return Err(format!(
+                        "Missing distance between {} and {} in binary output",
+                        id, column_id
+                    )
+                    .into())
+                }
+            };
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    if distances.next().is_some() {
+        return Err("More distances than expected in binary output".into());
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads precomputed distances from the binary output format. Capped and undefined distances
+/// are left out, like in the other precomputed formats.
+pub fn read_binary_distances<R: Read>(
+    mut reader: R,
+    symmetric_check: bool,
+    normalization: Option<IdNormalization>,
+) -> Result<HashMap<(String, String), usize>, Box<dyn Error>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != BINARY_MAGIC {
+        return Err("The precomputed distances are not in the binary output format".into());
+    }
+    let version = read_u32(&mut reader)?;
+    if version != BINARY_VERSION {
+        return Err(format!(
+            "Unsupported version {} of the binary output format",
+            version
+        )
+        .into());
+    }
+    // The sizes in the header are not trusted for allocations, so a corrupt file is an error
+    let n = read_u32(&mut reader)? as usize;
+    let mut ids = Vec::new();
+    for _ in 0..n {
+        let len = u64::from(read_u32(&mut reader)?);
+        let mut name = Vec::new();
+        if reader.by_ref().take(len).read_to_end(&mut name)? as u64 != len {
+            return Err("The binary precomputed distances end within a sample name".into());
+        }
+        ids.push(normalized_id(&String::from_utf8(name)?, normalization));
+    }
+
+    let mut distances = HashMap::new();
+    for i in 0..n {
+        for j in 0..i {
+            let value = read_u32(&mut reader)?;
+            if value & BINARY_CAPPED == 0 {
+                insert_precomputed_distance(
+                    &mut distances,
+                    ids[i].clone(),
+                    ids[j].clone(),
+                    value as usize,
+                    symmetric_check,
+                )?;
+            }
+        }
+    }
+    Ok(distances)
+}
+
+/// Writes the distances in Phylip format.
+fn write_distances_to_philip<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    sample_ids: &[&str],
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "{}", sample_ids.len())?;
+
+    let names = if options.phylip_strict {
+        strict_phylip_names(sample_ids)
+    } else {
+        sample_ids.iter().map(|id| id.to_string()).collect()
+    };
+
+    write_labelled_rows(
+        distances,
+        writer,
+        sample_ids,
+        &names,
+        &options.output_sep,
+        options,
+        "Phylip",
+    )
+}
+
+/// Writes one row per sample, starting with its name. The rows follow the order of `sample_ids`,
+/// and each row takes as many distances from the stream as the output mode prescribes, so the
+/// structure does not depend on the sample names.
+fn write_labelled_rows<'a, W: Write>(
+    mut distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    sample_ids: &[&str],
+    names: &[String],
+    sep: &str,
+    options: &OutputOptions,
+    format_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    for (i, id) in sample_ids.iter().enumerate() {
+        let row_len = options.output_mode.row_len(i, sample_ids.len());
+        write!(writer, "{}", names[i])?;
+        for column_id in &sample_ids[..row_len] {
+            match distances.next() {
+                Some((id1, id2, dist)) if id1 == *id && id2 == *column_id => {
+                    write!(writer, "{}{}", sep, options.format(dist))?
+                }
+                _ => {
+                    return Err(format!(
+                        "Missing distance between {} and {} in {} output",
+                        id, column_id, format_name
+                    )
+                    .into())
+                }
+            }
+        }
+        writeln!(writer)?;
+    }
+    if distances.next().is_some() {
+        return Err(format!("More distances than expected in {} output", format_name).into());
+    }
+    Ok(())
+}
+
+/// Writes the distances as a NEXUS file with a taxa block and a distances block. The rows follow
+/// the order of `sample_ids` like in the Phylip output.
+fn write_distances_to_nexus<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    sample_ids: &[&str],
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let names: Vec<String> = sample_ids.iter().map(|id| nexus_label(id)).collect();
+    let triangle = match options.output_mode {
+        OutputMode::LowerTriangle => "triangle=lower nodiagonal",
+        OutputMode::LowerTriangleDiag => "triangle=lower diagonal",
+        OutputMode::Full => "triangle=both diagonal",
+    };
+
+    writeln!(writer, "#NEXUS")?;
+    writeln!(writer)?;
+    writeln!(writer, "BEGIN taxa;")?;
+    writeln!(writer, "\tDIMENSIONS ntax={};", sample_ids.len())?;
+    writeln!(writer, "\tTAXLABELS")?;
+    for name in &names {
+        writeln!(writer, "\t\t{}", name)?;
+    }
+    writeln!(writer, "\t;")?;
+    writeln!(writer, "END;")?;
+    writeln!(writer)?;
+    writeln!(writer, "BEGIN distances;")?;
+    writeln!(writer, "\tDIMENSIONS ntax={};", sample_ids.len())?;
+    writeln!(writer, "\tFORMAT {} labels;", triangle)?;
+    writeln!(writer, "\tMATRIX")?;
+    write_labelled_rows(
+        distances,
+        &mut writer,
+        sample_ids,
+        &names,
+        "\t",
+        options,
+        "NEXUS",
+    )?;
+    writeln!(writer, "\t;")?;
+    writeln!(writer, "END;")?;
+    Ok(())
+}
+
+/// Quotes a taxon label when it contains whitespace or NEXUS punctuation. Single quotes inside a
+/// quoted label are doubled.
+fn nexus_label(id: &str) -> String {
+    const PUNCTUATION: &str = "()[]{}/\\,;:=*'\"`+-<>";
+    if !id.is_empty()
+        && !id
+            .chars()
+            .any(|c| c.is_whitespace() || PUNCTUATION.contains(c))
+    {
+        return id.to_string();
+    }
+    format!("'{}'", id.replace('\'', "''"))
+}
+
+/// Truncates or pads the names to exactly 10 characters as the classic Phylip format requires.
+/// Warns when the truncation makes names collide.
+fn strict_phylip_names(sample_ids: &[&str]) -> Vec<String> {
+    let names: Vec<String> = sample_ids
+        .iter()
+        .map(|id| format!("{:<10}", id.chars().take(10).collect::<String>()))
+        .collect();
+
+    let mut seen = HashSet::new();
+    for (name, id) in names.iter().zip(sample_ids) {
+        if !seen.insert(name) {
+            warn!(
+                "Sample {} collides with another sample after truncation to '{}'",
+                id,
+                name.trim_end()
+            );
+        }
+    }
+    names
+}
+
+/// Writes the distances as a square matrix. The distances must contain all pairs (`Full` mode)
+/// in row-major order. Rows are written as soon as they are complete, so only one row is kept
+/// in memory at a time. The first header name, if any, is written in the top-left cell.
+fn write_square_matrix<'a, W: Write>(
+    distances: impl Iterator<Item = (&'a str, &'a str, Distance)>,
+    mut writer: W,
+    samples: usize,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut columns: Vec<&str> = Vec::new();
+    for row in distance_rows(distances, samples) {
+        write_matrix_row(&mut writer, options, &mut columns, &row)?;
+    }
+    Ok(())
+}
+
+fn write_matrix_row<'a, W: Write>(
+    mut writer: W,
+    options: &OutputOptions,
+    columns: &mut Vec<&'a str>,
+    row: &[PairDistance<'a>],
+) -> Result<(), Box<dyn Error>> {
+    let row_id = row[0].0;
+    let incomplete = || {
+        format!(
+            "Incomplete row for {} in matrix output. Use the full output mode",
+            row_id
+        )
+    };
+    // The pairs of another sample mean that this row is shorter than the number of samples
+    if row.iter().any(|(id1, _, _)| *id1 != row_id) {
+        return Err(incomplete().into());
+    }
+    let output_sep = options.output_sep.as_str();
+    // The first row determines the columns of the matrix
+    if columns.is_empty() {
+        columns.extend(row.iter().map(|(_, id, _)| *id));
+        let corner = options
+            .header
+            .as_ref()
+            .and_then(|h| h.first())
+            .map_or("", |s| s.as_str());
+        write!(writer, "{}", corner)?;
+        for id in columns.iter() {
+            write!(writer, "{}{}", output_sep, id)?;
+        }
+        writeln!(writer)?;
+    }
+    if row.len() != columns.len() || row.iter().zip(columns.iter()).any(|(a, b)| a.1 != *b) {
+        return Err(incomplete().into());
+    }
+    write!(writer, "{}", row_id)?;
+    for (_, _, dist) in row {
+        write!(writer, "{}{}", output_sep, options.format(*dist))?;
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{MissingPolicy, Nucleotide, NucleotideAll, SHA1Hash};
+    use std::io::Read;
+    use std::str::FromStr;
+
+    use super::*;
+
+    impl Site for i32 {
+        fn is_missing(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_compute_distance_eq() {
+        let options = DistanceOptions::default();
+        let row1 = vec![1, 2, 3, 4, 5];
+        let row2 = vec![1, 2, 3, 4, 5];
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(0)
+        );
+
+        let row1 = vec![1, 2, 3, 4, 5];
+        let row2 = vec![1, 2, 3, 4, 6];
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(1)
+        );
+
+        let row1 = vec![1, 2, 3, 4, 5];
+        let row2 = vec![1, 2, 3, 4, 6];
+        assert_eq!(
+            compute_distance_eq(
+                &row1,
+                &row2,
+                &DistanceOptions {
+                    maxdist: Some(1),
+                    ..Default::default()
+                }
+            ),
+            Distance::AtLeast(1)
+        );
+        assert_eq!(
+            compute_distance_eq(
+                &row1,
+                &row2,
+                &DistanceOptions {
+                    maxdist: Some(2),
+                    ..Default::default()
+                }
+            ),
+            Distance::Exact(1)
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_for_chewbbaca() {
+        let options = DistanceOptions::default();
+        let x0 = ChewBBACAinteger::from_str("-").unwrap();
+        let x1 = ChewBBACAinteger::from_str("1").unwrap();
+        let x2 = ChewBBACAinteger::from_str("2").unwrap();
+        let x3 = ChewBBACAinteger::from_str("3").unwrap();
+        let plot = ChewBBACAinteger::from_str("plot5").unwrap();
+        let x3_new = ChewBBACAinteger::from_str("INF-3").unwrap();
+
+        let row1 = vec![x0, x1, x2, x3, x1];
+        let row2 = vec![x0, x1, x1, x2, x1];
+        let row3 = vec![x0, x1, x2, x3_new, plot];
+        assert_eq!(
+            compute_distance_eq(&row1, &row3, &options),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            compute_distance_eq(&row2, &row3, &options),
+            Distance::Exact(2)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(2)
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_for_chewbbaca_strict() {
+        let strict = DistanceOptions {
+            policy: ComparisonPolicy {
+                missing: MissingPolicy::Count,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let x0 = ChewBBACAinteger::from_str("-").unwrap();
+        let x1 = ChewBBACAinteger::from_str("1").unwrap();
+        let x2 = ChewBBACAinteger::from_str("2").unwrap();
+        let row1 = vec![x0, x1, x2, x0];
+        let row2 = vec![x0, x0, x2, x1];
+
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &DistanceOptions::default()),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &strict),
+            Distance::Exact(2)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row1, &strict),
+            Distance::Exact(0)
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_for_chewbbaca_hash() {
+        let options = DistanceOptions::default();
+        let x0 = SHA1Hash::from_str("-").unwrap();
+        let x1 = SHA1Hash::from_str("6bc8d04609de559621859873ef301f221cf5d991").unwrap();
+        let x2 = SHA1Hash::from_str("1e354c3d41dc0d3c403db19f22de23299a33a1c8").unwrap();
+        let x3 = SHA1Hash::from_str("beb636132e9cb496f1c1d37ecafdd62ed02060b0").unwrap();
+        let row1 = vec![x0, x1, x2, x3, x1];
+        let row2 = vec![x0, x1, x1, x2, x1];
+        let row3 = vec![x0, x0, x2, x0, x0];
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(2)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row3, &options),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            compute_distance_eq(&row2, &row3, &options),
+            Distance::Exact(1)
+        );
+
+        let presence_absence = DistanceOptions {
+            policy: ComparisonPolicy {
+                missing: MissingPolicy::Count,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_distance_eq(&row1, &row3, &presence_absence),
+            Distance::Exact(3)
+        );
+        assert_eq!(
+            compute_distance_eq(&row2, &row3, &presence_absence),
+            Distance::Exact(4)
+        );
+        assert_eq!(
+            compute_distance_eq(&row3, &row3, &presence_absence),
+            Distance::Exact(0)
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_for_fasta() {
+        let options = DistanceOptions::default();
+        let a = Nucleotide::from_str("A").unwrap();
+        let c = Nucleotide::from_str("C").unwrap();
+        let g = Nucleotide::from_str("G").unwrap();
+        let t = Nucleotide::from_str("T").unwrap();
+        let n = Nucleotide::from_str("n").unwrap();
+        let d = Nucleotide::from_str("-").unwrap();
+
+        let row1 = vec![a, c, g, t, n, d];
+        let row2 = vec![a, n, d, d, d, n];
+        let row3 = vec![c, c, g, t, n, d];
+
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row3, &options),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            compute_distance_eq(&row2, &row3, &options),
+            Distance::Exact(1)
+        );
 
         // >ref
         // TACCGTG
@@ -325,26 +3748,2121 @@ mod tests {
         let sample_a = vec![c, g, t, t, a, c, t];
         let sample_b = vec![n, n, c, n, g, t, n];
 
-        assert_eq!(compute_distance_eq(&ref1, &sample_a, None), 7);
-        assert_eq!(compute_distance_eq(&ref1, &sample_b, None), 0);
-        assert_eq!(compute_distance_eq(&sample_a, &sample_b, None), 3);
+        assert_eq!(
+            compute_distance_eq(&ref1, &sample_a, &options),
+            Distance::Exact(7)
+        );
+        assert_eq!(
+            compute_distance_eq(&ref1, &sample_b, &options),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            compute_distance_eq(&sample_a, &sample_b, &options),
+            Distance::Exact(3)
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_for_fasta_all() {
+        let options = DistanceOptions::default();
+        let a = NucleotideAll::from_str("A").unwrap();
+        let c = NucleotideAll::from_str("C").unwrap();
+        let g = NucleotideAll::from_str("G").unwrap();
+        let t = NucleotideAll::from_str("T").unwrap();
+        let n = NucleotideAll::from_str("n").unwrap();
+        let d = NucleotideAll::from_str("-").unwrap();
+
+        let row1 = vec![a, c, g, t, n, d];
+        let row2 = vec![a, n, d, d, d, n];
+        let row3 = vec![c, c, g, t, n, d];
+
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(5)
+        );
+        assert_eq!(
+            compute_distance_eq(&row1, &row3, &options),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            compute_distance_eq(&row2, &row3, &options),
+            Distance::Exact(6)
+        );
+    }
+
+    #[test]
+    fn test_validate_rows() {
+        let options = ParseOptions {
+            allow_duplicate_ids: true,
+            allow_ragged: true,
+            ..Default::default()
+        };
+        let input = b">S1\nACGT\n>S2\nACG\n>S1\nACGT\n>S3\nACGTA\n";
+        let data_map = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap();
+        assert_eq!(
+            validate_rows(&data_map, false),
+            vec![
+                "Duplicate sample IDs in the input: S1",
+                "Sample S2 has 3 positions, but sample S1 has 4",
+                "Sample S3 has 5 positions, but sample S1 has 4",
+            ]
+        );
+        assert_eq!(validate_rows(&data_map, true).len(), 2);
+
+        let input = "S1\t1\t2\nS2\t1\nS3\t1\t2\n";
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(
+            validate_rows(&data_map, false),
+            vec!["Sample S2 has 1 positions, but sample S1 has 2"]
+        );
+    }
+
+    #[test]
+    fn test_read_fastq() {
+        let input = b"@read1\nACGT\n+\nIIII\n";
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &Default::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("The input looks like FASTQ"), "{}", err);
+
+        let input = b"S1\t1\t2\n";
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &Default::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--input-format cgmlst"), "{}", err);
+
+        let input = b">S1\nACGT\n";
+        assert!(read_and_parse_fasta(&input[..], InputFormat::Fasta, &Default::default()).is_ok());
+        assert!(read_and_parse_fasta(&b""[..], InputFormat::Fasta, &Default::default()).is_ok());
+    }
+
+    #[test]
+    fn test_read_phylip() {
+        let fasta = b">S1\nACGTACGTAC\n>Sample_two\nACGTACGTAA\n>S3\nTCGTAC-TAC\n";
+        let expected =
+            read_and_parse_fasta(&fasta[..], InputFormat::Fasta, &Default::default()).unwrap();
+
+        let sequential = "3 10\nS1 ACGTA CGTAC\nSample_two ACGT\nACGTAA\nS3\nTCGTAC-TAC\n";
+        let interleaved = " 3 10\nS1 ACGTA\nSample_two ACGTA\nS3 TCGTA\n\nCGTAC\nCGTAA\nC-TAC\n";
+        for input in [sequential, interleaved] {
+            let data_map = read_and_parse_phylip(input.as_bytes(), &Default::default()).unwrap();
+            assert_eq!(data_map, expected);
+        }
+
+        let strict = "3 10\nS1        ACGTACGTAC\nSample_twoACGTACGTAA\nS3        TCGTAC-TAC\n";
+        let options = ParseOptions {
+            phylip_strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_and_parse_phylip(strict.as_bytes(), &options).unwrap(),
+            expected
+        );
+        assert!(read_and_parse_phylip(strict.as_bytes(), &Default::default()).is_err());
+
+        let err =
+            read_and_parse_phylip("3 11\nS1 ACGT\n".as_bytes(), &Default::default()).unwrap_err();
+        assert!(err.to_string().starts_with("The Phylip input is neither"));
+        assert!(read_and_parse_phylip("ACGT\n".as_bytes(), &Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_read_fasta_all_case_sensitive() {
+        let input = b">S1\nACGTn\n>S2\nacgTN\n";
+        let options = DistanceOptions::default();
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::FastaAll, &Default::default()).unwrap();
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &options),
+            Distance::Exact(0)
+        );
+
+        let parse_options = ParseOptions {
+            case_sensitive: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::FastaAll, &parse_options).unwrap();
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &options),
+            Distance::Exact(4)
+        );
+    }
+
+    #[test]
+    fn test_read_fasta_all_missing_chars() {
+        let input = b">S1\nAC-GT\n>S2\nACN.T\n>S3\nAC-.G\n";
+        let options = ParseOptions {
+            gap_chars: b"-.".to_vec(),
+            missing_chars: b"N".to_vec(),
+            ..Default::default()
+        };
+        let data_map = read_and_parse_fasta(&input[..], InputFormat::FastaAll, &options).unwrap();
+        let skip = DistanceOptions::default();
+        let count = DistanceOptions {
+            policy: ComparisonPolicy {
+                missing: MissingPolicy::Count,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &skip),
+            Distance::Exact(0)
+        );
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[2].1, &skip),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &count),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            calculate_distance(&data_map[1].1, &data_map[2].1, &count),
+            Distance::Exact(1)
+        );
+
+        // Without missing characters every byte is compared literally
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::FastaAll, &ParseOptions::default())
+                .unwrap();
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &skip),
+            Distance::Exact(2)
+        );
+    }
+
+    #[test]
+    fn test_nexus_label() {
+        assert_eq!(nexus_label("S1_a"), "S1_a");
+        assert_eq!(nexus_label("S 1"), "'S 1'");
+        assert_eq!(nexus_label("S-1"), "'S-1'");
+        assert_eq!(nexus_label("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut plain = String::new();
+        decompress(&b">S1\nACGT\n"[..])
+            .unwrap()
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, ">S1\nACGT\n");
+
+        #[cfg(not(feature = "bzip2"))]
+        assert!(decompress(&b"BZh91AY&SY"[..]).is_err());
+
+        // A given compression does not look at the magic bytes
+        let mut plain = String::new();
+        decompress_as(&b"BZh9"[..], InputCompression::None)
+            .unwrap()
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, "BZh9");
+        #[cfg(not(feature = "gzip"))]
+        assert!(decompress_as(&b">S1\nACGT\n"[..], InputCompression::Gzip).is_err());
+        #[cfg(feature = "gzip")]
+        {
+            use std::io::Write;
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            encoder.write_all(b">S1\nACGT\n").unwrap();
+            let compressed = encoder.finish().unwrap();
+            let mut plain = String::new();
+            decompress(&compressed[..])
+                .unwrap()
+                .read_to_string(&mut plain)
+                .unwrap();
+            assert_eq!(plain, ">S1\nACGT\n");
+        }
+    }
+
+    #[test]
+    fn test_read_duplicate_ids() {
+        let input = b">S1\nACGT\n>S2\nACGA\n>S1\nACGG\n";
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate sample IDs in the input: S1");
+
+        let options = ParseOptions {
+            allow_duplicate_ids: true,
+            ..Default::default()
+        };
+        let data_map = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap();
+        assert_eq!(data_map.len(), 3);
+
+        let input = "S1\t1\t2\nS2\t1\t3\nS2\t2\t2\nS1\t1\t1\n";
+        let err = read_and_parse_tabular(
+            input.as_bytes(),
+            InputFormat::Cgmlst,
+            &ParseOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate sample IDs in the input: S2, S1");
+        assert!(read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).is_ok());
+    }
+
+    #[test]
+    fn test_read_fasta_id_field() {
+        let input = b">abc|S1|2024 description\nACGT\n>abc|S2\nACGA\n";
+        let options = ParseOptions {
+            id_field: Some(1),
+            ..Default::default()
+        };
+        let data_map = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap();
+        assert_eq!(sample_ids(&data_map), vec!["S1", "S2"]);
+
+        let options = ParseOptions {
+            id_field: Some(2),
+            ..Default::default()
+        };
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap_err();
+        assert_eq!(err.to_string(), "The FASTA header abc|S2 has no ID field 2");
+    }
+
+    #[test]
+    fn test_read_tabular_quoted() {
+        let input = "ID,locus1,locus2\n\"S1, batch 1\",1,\"2\"\r\nS2,1,3\n";
+        let options = ParseOptions {
+            separator: ',',
+            skip_header: true,
+            quoted: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(sample_ids(&data_map), vec!["S1, batch 1", "S2"]);
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &DistanceOptions::default()),
+            Distance::Exact(1)
+        );
+
+        // Without quoting the separator in the ID splits the fields, so the rows are ragged
+        let options = ParseOptions {
+            quoted: false,
+            ..options
+        };
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Sample S2 has 2 loci, but the first sample \"S1 has 3."));
+    }
+
+    #[test]
+    fn test_read_tabular_transposed() {
+        let input = "locus\tS1\tS2\tS3\nlocus1\t1\t1\t2\nlocus2\t2\t3\t2\n";
+        let options = ParseOptions {
+            transpose: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+
+        let expected = "S1\t1\t2\nS2\t1\t3\nS3\t2\t2\n";
+        let expected = read_and_parse_tabular(
+            expected.as_bytes(),
+            InputFormat::Cgmlst,
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(data_map, expected);
+
+        let input = "locus\tS1\tS2\nlocus1\t1\t1\nlocus2\t2\n";
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Locus locus2 has 2 fields, but the header has 3"
+        );
+    }
+
+    #[test]
+    fn test_read_tabular_binary() {
+        let input = b"S1\t0\t1\t1\t0\nS2\t1\t1\t0\t0\nS3\t0\t1\t1\t0\n";
+        let data_map =
+            read_and_parse_tabular(&input[..], InputFormat::Binary, &ParseOptions::default())
+                .unwrap();
+        let options = DistanceOptions::default();
+        let distances: Vec<_> =
+            compute_distances(&data_map, &options, OutputMode::LowerTriangle, None, None).collect();
+        assert_eq!(
+            distances,
+            vec![
+                ("S2", "S1", Distance::Exact(2)),
+                ("S3", "S1", Distance::Exact(0)),
+                ("S3", "S2", Distance::Exact(2)),
+            ]
+        );
+
+        let weights = [1.0, 0.5, 0.5, 1.0];
+        let weighted = DistanceOptions {
+            weights: Some(&weights),
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &weighted),
+            Distance::Estimate(1.5)
+        );
+
+        let jaccard = DistanceOptions {
+            model: Model::Jaccard,
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &jaccard),
+            Distance::Estimate(1.0 - 1.0 / 3.0)
+        );
+        let empty = read_and_parse_tabular(
+            &b"S1\t0\t0\nS2\t0\t0\n"[..],
+            InputFormat::Binary,
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            calculate_distance(&empty[0].1, &empty[1].1, &jaccard),
+            Distance::Estimate(0.0)
+        );
+
+        let input = b"S1\t0\t1\nS2\t1\tNA\n";
+        let result =
+            read_and_parse_tabular(&input[..], InputFormat::Binary, &ParseOptions::default());
+        assert!(result.unwrap_err().to_string().contains("'NA'"));
+    }
+
+    #[test]
+    fn test_read_tabular_empty_fields() {
+        // Interior and trailing empty fields are missing loci and keep their columns
+        let input = "S1\t\t22\t\nS2\t11\t\t33\n";
+        for input_format in [InputFormat::Cgmlst, InputFormat::CgmlstHash] {
+            let expected = read_and_parse_tabular(
+                "S1\t-\t22\t-\nS2\t11\t-\t33\n".as_bytes(),
+                input_format,
+                &Default::default(),
+            )
+            .unwrap();
+            for quoted in [false, true] {
+                let options = ParseOptions {
+                    quoted,
+                    ..Default::default()
+                };
+                let data_map =
+                    read_and_parse_tabular(input.as_bytes(), input_format, &options).unwrap();
+                assert_eq!(data_map[0].1.len(), 3);
+                assert!(data_map[0].1.is_missing(0) && data_map[0].1.is_missing(2));
+                assert!(!data_map[1].1.is_missing(0) && data_map[1].1.is_missing(1));
+                assert_eq!(data_map, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_tabular_collect_errors() {
+        let input = "FILE\tl1\tl2\nS1\t0\t1\nS2\t2\t1\nS3\t1\tx\n";
+        let options = ParseOptions {
+            skip_header: true,
+            ..Default::default()
+        };
+        assert!(read_and_parse_tabular(input.as_bytes(), InputFormat::Binary, &options).is_err());
+
+        let options = ParseOptions {
+            collect_errors: true,
+            ..options
+        };
+        let mut errors = ParseErrors::new(true);
+        let row = parse_row(
+            "S2\t2".split('\t'),
+            InputFormat::Binary,
+            InferredPolicy::Match,
+        );
+        assert!(errors
+            .check(row, || "Line 3".to_string())
+            .unwrap()
+            .is_none());
+        assert!(errors.errors[0].starts_with("Line 3: "));
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Binary, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Found 2 errors while parsing the input");
+        let quoted = ParseOptions {
+            quoted: true,
+            ..options.clone()
+        };
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Binary, &quoted).unwrap_err();
+        assert_eq!(err.to_string(), "Found 2 errors while parsing the input");
+
+        // Rows with another number of loci are collected with the parse errors
+        let input = "FILE\tl1\tl2\nS1\t0\t1\nS2\t1\nS3\t1\tx\nS4\t0\t1\t1\n";
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Binary, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Found 3 errors while parsing the input");
+        let ragged = ParseOptions {
+            allow_ragged: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular("S1\t0\t1\nS2\t1\n".as_bytes(), InputFormat::Binary, &ragged)
+                .unwrap();
+        let mut errors = ParseErrors::new(true);
+        check_row_lengths(&data_map.into_inner(), &[2, 5], &mut errors).unwrap();
+        assert_eq!(errors.errors.len(), 1);
+        assert!(errors.errors[0].starts_with("Line 5: Sample S2 has 1 loci"));
+    }
+
+    #[test]
+    fn test_read_fasta_collect_errors() {
+        let input = b">x|S1 description\nAC\nGT \n>S2\nACGT\n>x|\xff\nACGT\n>x|S3\r\nACGA\r\n";
+        let records: Vec<_> = fasta_records(&input[..]).map(Result::unwrap).collect();
+        assert_eq!(
+            records[0],
+            (1, b"x|S1 description".to_vec(), b"ACGT".to_vec())
+        );
+        assert_eq!(records[3], (8, b"x|S3\r".to_vec(), b"ACGA".to_vec()));
+
+        let options = ParseOptions {
+            id_field: Some(1),
+            ..Default::default()
+        };
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap_err();
+        assert_eq!(err.to_string(), "The FASTA header S2 has no ID field 1");
+
+        let options = ParseOptions {
+            collect_errors: true,
+            ..options
+        };
+        let err = read_and_parse_fasta(&input[..], InputFormat::Fasta, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Found 2 errors while parsing the input");
+    }
+
+    #[test]
+    fn test_read_tabular_expect_loci() {
+        let input = b"S1\t1\t2\t3\nS2\t1\t2\n";
+        let options = ParseOptions {
+            expect_loci: Some(3),
+            ..Default::default()
+        };
+        let result = read_and_parse_tabular(&input[..], InputFormat::Cgmlst, &options);
+        assert!(result.unwrap_err().to_string().contains("S2 has 2 loci"));
+
+        let options = ParseOptions {
+            pad_missing: true,
+            ..options
+        };
+        let data_map = read_and_parse_tabular(&input[..], InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(data_map[1].1.len(), 3);
+        assert!(data_map[1].1.is_missing(2));
+
+        let options = ParseOptions {
+            expect_loci: Some(2),
+            ..options
+        };
+        let result = read_and_parse_tabular(&input[..], InputFormat::Cgmlst, &options);
+        assert!(result.unwrap_err().to_string().contains("S1 has 3 loci"));
+    }
+
+    #[test]
+    fn test_read_tabular_ragged() {
+        let input = "S1\t1\t2\t3\nS2\t1\t2\nS3\t1\t2\t3\n";
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &Default::default())
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Sample S2 has 2 loci, but the first sample S1 has 3."));
+
+        let options = ParseOptions {
+            allow_ragged: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(data_map[1].1.len(), 2);
+    }
+
+    #[test]
+    fn test_read_tabular_sha256() {
+        // The hashes of S1 and S2 share their first 20 bytes, and S3 only has a missing locus
+        let prefix = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b";
+        let input = format!(
+            "S1\t{0}2b0b822cd15d6c15b0f00a08\t{0}00000000000000000000000a\n\
+             S2\t{0}2b0b822cd15d6c15b0f00a09\t{0}00000000000000000000000a\n\
+             S3\t-\t{0}00000000000000000000000a\n",
+            prefix
+        );
+        let data_map = read_and_parse_tabular(
+            input.as_bytes(),
+            InputFormat::CgmlstHash,
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(data_map
+            .iter()
+            .all(|(_, row)| matches!(row, SupportedTypeVec::SHA256Hash(_))));
+        let options = DistanceOptions::default();
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &options),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[2].1, &options),
+            Distance::Exact(0)
+        );
+
+        let too_long = format!("S1\t{0}{0}\n", prefix);
+        assert!(read_and_parse_tabular(
+            too_long.as_bytes(),
+            InputFormat::CgmlstHash,
+            &Default::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_normalize_ids() {
+        let input = " Sample1 \t1\t2\nSAMPLE2\t1\t3\n";
+        let options = ParseOptions {
+            normalize_ids: Some(IdNormalization::Lowercase),
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(sample_ids(&data_map), vec!["sample1", "sample2"]);
+
+        let options = ParseOptions {
+            normalize_ids: Some(IdNormalization::Trim),
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(sample_ids(&data_map), vec!["Sample1", "SAMPLE2"]);
+
+        // IDs that only differ in case become duplicates
+        let input = "Sample1\t1\nsample1\t2\n";
+        let options = ParseOptions {
+            normalize_ids: Some(IdNormalization::Lowercase),
+            ..Default::default()
+        };
+        assert!(read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).is_err());
+
+        let cache = read_and_parse_tabular_distances(
+            &b"Sample1\tSAMPLE2\t4\n"[..],
+            '\t',
+            true,
+            Some(IdNormalization::Lowercase),
+        )
+        .unwrap();
+        assert_eq!(
+            cache.get(&("sample1".to_string(), "sample2".to_string())),
+            Some(&4)
+        );
+        let pairs =
+            read_excluded_pairs(&b" S1\tS2\n"[..], '\t', Some(IdNormalization::Trim)).unwrap();
+        assert!(pairs.contains(&("S2".to_string(), "S1".to_string())));
+    }
+
+    #[test]
+    fn test_read_tabular_loci() {
+        let input = "FILE\tlocus1\tlocus2\tlocus3\nS1\t1\t2\t3\nS2\t4\t5\t6\n";
+        let expected = read_and_parse_tabular(
+            "S1\t1\t3\nS2\t4\t6\n".as_bytes(),
+            InputFormat::Cgmlst,
+            &Default::default(),
+        )
+        .unwrap();
+        let loci = |loci: &[&str]| Some(loci.iter().map(|locus| locus.to_string()).collect());
+
+        let options = ParseOptions {
+            skip_header: true,
+            loci: loci(&["locus3", "0"]),
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        assert_eq!(data_map, expected);
+
+        let csv_options = ParseOptions {
+            quoted: true,
+            ..options.clone()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &csv_options).unwrap();
+        assert_eq!(data_map, expected);
+
+        let transposed = "locus\tS1\tS2\nlocus1\t1\t4\nlocus2\t2\t5\nlocus3\t3\t6\n";
+        let transposed_options = ParseOptions {
+            transpose: true,
+            loci: loci(&["locus1", "2"]),
+            ..Default::default()
+        };
+        let data_map = read_and_parse_tabular(
+            transposed.as_bytes(),
+            InputFormat::Cgmlst,
+            &transposed_options,
+        )
+        .unwrap();
+        assert_eq!(data_map, expected);
+
+        let options = ParseOptions {
+            loci: loci(&["locus4"]),
+            ..options
+        };
+        let err =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Locus locus4 not found in the input");
+
+        let options = ParseOptions {
+            loci: loci(&["3"]),
+            ..Default::default()
+        };
+        let err = read_and_parse_tabular("S1\t1\t2\n".as_bytes(), InputFormat::Cgmlst, &options)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Sample S1 has no locus 3");
+    }
+
+    #[test]
+    fn test_compute_distance_eq_weighted() {
+        let weights = [1.0, 0.5, 2.0, 0.25];
+        let options = DistanceOptions {
+            weights: Some(&weights),
+            ..Default::default()
+        };
+        let x0 = ChewBBACAinteger::from_str("-").unwrap();
+        let x1 = ChewBBACAinteger::from_str("1").unwrap();
+        let x2 = ChewBBACAinteger::from_str("2").unwrap();
+        let row1 = vec![x1, x1, x0, x1];
+        let row2 = vec![x1, x2, x2, x2];
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Estimate(0.75)
+        );
+
+        let options = DistanceOptions {
+            maxdist: Some(1),
+            policy: ComparisonPolicy {
+                missing: MissingPolicy::Count,
+                ..Default::default()
+            },
+            ..options
+        };
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::AtLeast(1)
+        );
     }
 
     #[test]
-    fn test_compute_distance_eq_for_fasta_all() {
-        let a = NucleotideAll::from_str("A").unwrap();
-        let c = NucleotideAll::from_str("C").unwrap();
-        let g = NucleotideAll::from_str("G").unwrap();
-        let t = NucleotideAll::from_str("T").unwrap();
-        let n = NucleotideAll::from_str("n").unwrap();
-        let d = NucleotideAll::from_str("-").unwrap();
+    fn test_compute_cost_distance() {
+        let costs = read_cost_matrix(&b"1\t2\t1\t0.25\n2\t1\t3\t0.5\n"[..], '\t', 3, None).unwrap();
+        assert!(read_cost_matrix(&b"3\t1\t2\t0.5\n"[..], '\t', 3, None).is_err());
+        assert!(read_cost_matrix(&b"0\t1\t2\n"[..], '\t', 3, None).is_err());
+        let err = read_cost_matrix(&b"0\t1\t2\t-0.5\n"[..], '\t', 3, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cost -0.5 of locus 0 on line 1 is not a finite number of at least 0"
+        );
+        assert!(read_cost_matrix(&b"0\t1\t2\tNaN\n"[..], '\t', 3, None).is_err());
+        assert!(read_cost_matrix(&b"0\t1\t2\tinf\n"[..], '\t', 3, None).is_err());
 
-        let row1 = vec![a, c, g, t, n, d];
-        let row2 = vec![a, n, d, d, d, n];
-        let row3 = vec![c, c, g, t, n, d];
+        let alleles = |row: &[&str]| -> SupportedTypeVec {
+            SupportedTypeVec::Cgmlst(row.iter().map(|a| a.parse().unwrap()).collect())
+        };
+        let row1 = alleles(&["1", "1", "1"]);
+        let row2 = alleles(&["2", "2", "2"]);
+        let options = DistanceOptions {
+            costs: Some(&costs),
+            ..Default::default()
+        };
+        // Locus 0 has no costs, locus 1 has the cost of 1 and 2, locus 2 only of 1 and 3
+        assert_eq!(
+            calculate_distance(&row1, &row2, &options),
+            Distance::Estimate(2.25)
+        );
+        let row3 = alleles(&["1", "-", "3"]);
+        assert_eq!(
+            calculate_distance(&row1, &row3, &options),
+            Distance::Estimate(0.5)
+        );
+    }
+
+    #[test]
+    fn test_detect_separator() {
+        assert_eq!(detect_separator("S1,1,2,3"), ',');
+        assert_eq!(detect_separator("S1;1;2,3"), ';');
+        assert_eq!(detect_separator("S1\t1,2\t3"), '\t');
+        assert_eq!(detect_separator("S1"), '\t');
+
+        let mut input = &b"ID;a;b\nS1,1,2\n"[..];
+        assert_eq!(InputSeparator::Auto.resolve(&mut input, true).unwrap(), ',');
+        assert_eq!(
+            InputSeparator::Auto.resolve(&mut input, false).unwrap(),
+            ';'
+        );
+        assert_eq!(input.len(), 14);
+        assert_eq!("auto".parse(), Ok(InputSeparator::Auto));
+        assert_eq!(",".parse(), Ok(InputSeparator::Char(',')));
+        assert!("ab".parse::<InputSeparator>().is_err());
+    }
+
+    #[test]
+    fn test_compute_pair_distances() {
+        let input = b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let pairs = read_pairs(&b"S3\tS1\n\nS2\tS2\nS1\tS2\n"[..], '\t', None).unwrap();
+        let options = DistanceOptions::default();
+        let precomputed = HashMap::from([(("S1", "S2"), 5)]);
+        let distances =
+            compute_pair_distances(&data_map, &options, &pairs, Some(&precomputed)).unwrap();
+        assert_eq!(
+            distances,
+            vec![
+                ("S3", "S1", Distance::Exact(2)),
+                ("S2", "S2", Distance::Exact(0)),
+                ("S1", "S2", Distance::Exact(5)),
+            ]
+        );
+
+        let pairs = vec![("S1".to_string(), "S4".to_string())];
+        let err = compute_pair_distances(&data_map, &options, &pairs, None).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown sample ID S4 in the pairs");
+        assert!(read_pairs(&b"S1\n"[..], '\t', None).is_err());
+    }
+
+    #[test]
+    fn test_read_weights() {
+        let weights = read_weights(&b"0\t0.5\n2\t2\n\n"[..], '\t', 3, None).unwrap();
+        assert_eq!(weights, vec![0.5, 1.0, 2.0]);
+        assert!(read_weights(&b"3\t0.5\n"[..], '\t', 3, None).is_err());
+        assert!(read_weights(&b"0\n"[..], '\t', 3, None).is_err());
+
+        let names: Vec<String> = ["locus1", "locus2", "locus3"].map(String::from).into();
+        let weights = read_weights(&b"locus3\t2\n0\t0.5\n"[..], '\t', 3, Some(&names)).unwrap();
+        assert_eq!(weights, vec![0.5, 1.0, 2.0]);
+        let err = read_weights(&b"locus4\t2\n"[..], '\t', 3, Some(&names)).unwrap_err();
+        assert_eq!(err.to_string(), "Locus locus4 not found in the input");
+        let err = read_weights(&b"locus3\t2\n"[..], '\t', 3, None).unwrap_err();
+        assert!(err.to_string().contains("has no header line"));
+
+        let err = read_weights(&b"1\t1\n0\t-5\n"[..], '\t', 3, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Weight -5 of locus 0 on line 2 is not a finite number of at least 0"
+        );
+        assert!(read_weights(&b"0\tNaN\n"[..], '\t', 3, None).is_err());
+        assert!(read_weights(&b"0\tinf\n"[..], '\t', 3, None).is_err());
+    }
+
+    #[test]
+    fn test_write_distances_with_compared() {
+        let input = "S1\t1\t2\t-\nS2\t1\t3\t4\nS3\t-\t-\t4\n";
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &Default::default())
+                .unwrap();
+        let policy = ComparisonPolicy::default();
+        assert_eq!(
+            compared_positions(&data_map[0].1, &data_map[1].1, &policy),
+            Some(2)
+        );
+
+        let options = DistanceOptions::default();
+        let output_options = OutputOptions {
+            header: Some(vec!["id1".into(), "id2".into(), "distance".into()]),
+            ..Default::default()
+        };
+        for (compared, normalized, expected) in [
+            (
+                true,
+                false,
+                "id1\tid2\tdistance\tcompared\nS2\tS1\t1\t2\nS3\tS1\t0\t0\nS3\tS2\t0\t1\n",
+            ),
+            (
+                true,
+                true,
+                "id1\tid2\tdistance\tcompared\tnormalized\nS2\tS1\t1\t2\t0.5\nS3\tS1\t0\t0\tNA\nS3\tS2\t0\t1\t0\n",
+            ),
+        ] {
+            let distances =
+                compute_distances(&data_map, &options, OutputMode::LowerTriangle, None, None);
+            let columns = ComparedColumns {
+                compared,
+                normalized,
+            };
+            let mut output = Vec::new();
+            write_distances_with_compared(
+                distances,
+                &mut output,
+                &data_map,
+                &policy,
+                columns,
+                &output_options,
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_count_unexpected_characters() {
+        assert_eq!(
+            count_unexpected_characters(b"ACgtn-", InputFormat::Fasta),
+            0
+        );
+        assert_eq!(
+            count_unexpected_characters(b"MKVLEQ", InputFormat::Fasta),
+            6
+        );
+        assert_eq!(
+            count_unexpected_characters(b"MKV*-?", InputFormat::Protein),
+            1
+        );
+        assert_eq!(count_unexpected_characters(b"?!", InputFormat::FastaAll), 0);
+    }
+
+    #[test]
+    fn test_reference_pairs() {
+        let data = b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let pairs = reference_pairs(&data_map, " S2 ", Some(IdNormalization::Trim)).unwrap();
+        let pair = |id1: &str, id2: &str| (id1.to_string(), id2.to_string());
+        assert_eq!(pairs, vec![pair("S2", "S1"), pair("S2", "S3")]);
+        let distances =
+            compute_pair_distances(&data_map, &DistanceOptions::default(), &pairs, None).unwrap();
+        assert_eq!(
+            distances,
+            vec![
+                ("S2", "S1", Distance::Exact(1)),
+                ("S2", "S3", Distance::Exact(1))
+            ]
+        );
+        let err = reference_pairs(&data_map, "S4", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The reference sample S4 is not in the input"
+        );
+    }
+
+    #[test]
+    fn test_read_locus_names() {
+        let input = "FILE\tlocus1\tlocus2\tlocus3\nS1\t1\t2\t3\n";
+        let options = ParseOptions {
+            skip_header: true,
+            loci: Some(vec!["locus3".to_string(), "0".to_string()]),
+            ..Default::default()
+        };
+        let names = read_locus_names(input.as_bytes(), &options).unwrap();
+        assert_eq!(
+            names,
+            Some(vec!["locus1".to_string(), "locus3".to_string()])
+        );
+        let names = read_locus_names(input.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(names, None);
+
+        let transposed = "locus\tS1\nlocus1\t1\nlocus2\t2\n";
+        let options = ParseOptions {
+            transpose: true,
+            ..Default::default()
+        };
+        let names = read_locus_names(transposed.as_bytes(), &options).unwrap();
+        assert_eq!(
+            names,
+            Some(vec!["locus1".to_string(), "locus2".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_sketch_distance() {
+        let input = b">S1\nACGTTGCAAGGCTTAACGGA\n>S2\nACGTTGCAAGG\n>S3\nTTTTTTTTTTTT\n";
+        let options = ParseOptions {
+            sketch_k: 5,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::FastaSketch, &options).unwrap();
+        let distance_options = DistanceOptions::default();
+
+        // S2 has 6 of the 15 distinct canonical k-mers of S1
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[1].1, &distance_options),
+            Distance::Estimate(1.0 - 6.0 / 15.0)
+        );
+        assert_eq!(
+            calculate_distance(&data_map[0].1, &data_map[2].1, &distance_options),
+            Distance::Estimate(1.0)
+        );
+
+        let options = ParseOptions {
+            sketch_k: 33,
+            ..Default::default()
+        };
+        assert!(read_and_parse_fasta(&input[..], InputFormat::FastaSketch, &options).is_err());
+    }
+
+    #[test]
+    fn test_mean_distances() {
+        let lower = vec![
+            ("b", "a", Distance::Exact(2)),
+            ("c", "a", Distance::Exact(4)),
+            ("c", "b", Distance::Undefined),
+        ];
+        let full = vec![
+            ("a", "a", Distance::Exact(0)),
+            ("a", "b", Distance::Exact(2)),
+            ("a", "c", Distance::Exact(4)),
+            ("b", "a", Distance::Exact(2)),
+            ("b", "b", Distance::Exact(0)),
+            ("b", "c", Distance::Undefined),
+            ("c", "a", Distance::Exact(4)),
+            ("c", "b", Distance::Undefined),
+            ("c", "c", Distance::Exact(0)),
+        ];
+        for (output_mode, distances) in
+            [(OutputMode::LowerTriangle, lower), (OutputMode::Full, full)]
+        {
+            let mut means = MeanDistances::new(output_mode);
+            for (id1, id2, dist) in distances {
+                means.add(id1, id2, dist);
+            }
+            assert_eq!(means.mean("a"), Some(3.0));
+            assert_eq!(means.mean("b"), Some(2.0));
+            assert_eq!(means.mean("c"), Some(4.0));
+        }
+
+        let mut means = MeanDistances::new(OutputMode::LowerTriangle);
+        means.add("b", "a", Distance::Exact(3));
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            precision: Some(1),
+            ..Default::default()
+        };
+        means
+            .write(&["a", "b", "c"], &mut output, &options)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a\t3.0\nb\t3.0\nc\tNA\n"
+        );
+    }
+
+    #[test]
+    fn test_write_sample_order() {
+        let mut output = Vec::new();
+        write_sample_order(&["S2", "S1", "S3"], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "S2\nS1\nS3\n");
+    }
+
+    #[test]
+    fn test_write_parsed_fasta() {
+        let input = b">S1\nacgRt-\n>S2\nAC.NTX\n";
+        let parse = |input_format, options: &ParseOptions| {
+            let data_map = read_and_parse_fasta(&input[..], input_format, options).unwrap();
+            let mut output = Vec::new();
+            write_parsed_fasta(&data_map, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let options = ParseOptions::default();
+        assert_eq!(
+            parse(InputFormat::Fasta, &options),
+            ">S1\nACGNT-\n>S2\nACNNTN\n"
+        );
+        assert_eq!(
+            parse(InputFormat::Protein, &options),
+            ">S1\nACGRT-\n>S2\nACXNTX\n"
+        );
+        let options = ParseOptions {
+            missing_chars: b".".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            parse(InputFormat::FastaAll, &options),
+            ">S1\nacgrt-\n>S2\nac?ntx\n"
+        );
+
+        let data_map = read_and_parse_tabular(&b"S1\t1\t2\n"[..], InputFormat::Cgmlst, &options);
+        assert!(write_parsed_fasta(&data_map.unwrap(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_missing_statistics() {
+        let input = b">S1\nACGT\n>S2\nAN-T\n>S3\nACNN\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let statistics =
+            missing_statistics(data_map.iter().map(|(_, row)| row), &Default::default());
+
+        // The pairs miss 2, 2 and 3 positions
+        assert_eq!(statistics.positions, 4);
+        assert!((statistics.mean_missing - 7.0 / 3.0).abs() < 1e-9);
+        assert!((statistics.mean_compared - 5.0 / 3.0).abs() < 1e-9);
+
+        // Ambiguous bases are only compared without strict_acgt, like in compared_positions
+        let input = b">S1\nACGT\n>S2\nRCGT\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let (row1, row2) = (&data_map[0].1, &data_map[1].1);
+        for strict_acgt in [false, true] {
+            let policy = ComparisonPolicy {
+                strict_acgt,
+                ..Default::default()
+            };
+            let statistics = missing_statistics(data_map.iter().map(|(_, row)| row), &policy);
+            let compared = compared_positions(row1, row2, &policy).unwrap();
+            assert_eq!(statistics.mean_compared, compared as f64);
+        }
+
+        // Positions past the end of a shorter row are missing
+        let input = "S1\t1\t2\t3\nS2\t1\t2\n";
+        let options = ParseOptions {
+            allow_ragged: true,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        let statistics =
+            missing_statistics(data_map.iter().map(|(_, row)| row), &Default::default());
+        assert_eq!(statistics.positions, 3);
+        assert_eq!(statistics.mean_missing, 1.0);
+    }
+
+    #[test]
+    fn test_differing_positions() {
+        let input = b">S1\nACGTA-\n>S2\nTCNTC-\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let (row1, row2) = (&data_map[0].1, &data_map[1].1);
+        let policy = ComparisonPolicy::default();
+        assert_eq!(differing_positions(row1, row2, &policy), Some(vec![0, 4]));
+        let values: Vec<_> = [0, 2, 5, 6].map(|index| row2.value_at(index)).into();
+        assert_eq!(
+            values,
+            [Some("T".into()), Some("N".into()), Some("-".into()), None]
+        );
+
+        let input = "S1\t1\tINF-2\t-\nS2\t3\t2\t4\n";
+        let options = ParseOptions {
+            inferred_policy: InferredPolicy::Mismatch,
+            ..Default::default()
+        };
+        let data_map =
+            read_and_parse_tabular(input.as_bytes(), InputFormat::Cgmlst, &options).unwrap();
+        let (row1, row2) = (&data_map[0].1, &data_map[1].1);
+        assert_eq!(differing_positions(row1, row2, &policy), Some(vec![0, 1]));
+        let values: Vec<_> = (0..3).map(|index| row1.value_at(index).unwrap()).collect();
+        assert_eq!(values, ["1", "INF-2", "-"]);
+    }
+
+    #[test]
+    fn test_reorder_samples() {
+        let input = b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let order =
+            read_sample_order(&b"S3\n\n S1\nS2\n"[..], Some(IdNormalization::Trim)).unwrap();
+        assert_eq!(order, ["S3", "S1", "S2"]);
+        let reordered = reorder_samples(data_map.clone(), &order, false).unwrap();
+        assert_eq!(sample_ids(&reordered), ["S3", "S1", "S2"]);
+        assert!(read_sample_order(&b"S1\nS1\n"[..], None).is_err());
+
+        let order = ["S4".to_string(), "S2".to_string()];
+        let err = reorder_samples(data_map.clone(), &order, false).unwrap_err();
+        assert_eq!(err.to_string(), "Sample S1 is not in the order");
+        let reordered = reorder_samples(data_map, &order, true).unwrap();
+        assert_eq!(sample_ids(&reordered), ["S2", "S1", "S3"]);
+    }
+
+    #[test]
+    fn test_complete_deletion() {
+        let input = b">S1\nACGTAC\n>S2\nTN-TAG\n>S3\nGCGAA\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let keep = complete_deletion_mask(&data_map);
+        // S3 is one position shorter, so its last position is missing as well
+        assert_eq!(keep, vec![true, false, false, true, true, false]);
+
+        let data_map = select_columns(data_map, &keep).unwrap();
+        let sequences: Vec<_> = data_map
+            .iter()
+            .map(|(_, row)| row.to_sequence().unwrap())
+            .collect();
+        assert_eq!(
+            sequences,
+            vec![b"ATA".to_vec(), b"TTA".to_vec(), b"GAA".to_vec()]
+        );
+        let distances: Vec<_> = compute_distances(
+            &data_map,
+            &DistanceOptions::default(),
+            OutputMode::LowerTriangle,
+            None,
+            None,
+        )
+        .map(|(_, _, dist)| dist)
+        .collect();
+        assert_eq!(
+            distances,
+            vec![Distance::Exact(1), Distance::Exact(2), Distance::Exact(2)]
+        );
+    }
+
+    #[test]
+    fn test_compute_distance_eq_min_shared() {
+        let x0 = ChewBBACAinteger::from_str("-").unwrap();
+        let x1 = ChewBBACAinteger::from_str("1").unwrap();
+        let x2 = ChewBBACAinteger::from_str("2").unwrap();
+        let row1 = vec![x0, x1, x2, x1, x0];
+        let row2 = vec![x1, x0, x1, x1, x0];
+        let options = DistanceOptions {
+            min_shared: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Exact(1)
+        );
+        let options = DistanceOptions {
+            min_shared: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_distance_eq(&row1, &row2, &options),
+            Distance::Undefined
+        );
+    }
+
+    #[test]
+    fn test_write_undefined_distance() {
+        let distances = vec![
+            ("b", "a", Distance::Undefined),
+            ("c", "a", Distance::Exact(3)),
+            ("c", "b", Distance::AtLeast(2)),
+        ];
+        let options = OutputOptions {
+            undefined_value: "-1".to_string(),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["a", "b", "c"],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output, b"b\ta\t-1\nc\ta\t3\nc\tb\t>=2\n");
+    }
+
+    #[test]
+    fn test_write_max_output_dist() {
+        let distances = vec![
+            ("b", "a", Distance::Exact(2)),
+            ("c", "a", Distance::Exact(3)),
+            ("c", "b", Distance::Undefined),
+            ("d", "a", Distance::Estimate(1.5)),
+        ];
+        let options = OutputOptions {
+            max_output_dist: Some(2.0),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["a", "b", "c", "d"],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output, b"b\ta\t2\nd\ta\t1.5\n");
+    }
+
+    #[test]
+    fn test_excluded_pairs() {
+        let pairs = read_excluded_pairs(&b"S2\tS1\n\nS3\tS3\n"[..], '\t', None).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert!(read_excluded_pairs(&b"S1\n"[..], '\t', None).is_err());
+
+        let pairs: HashSet<(&str, &str)> = pairs
+            .iter()
+            .map(|(id1, id2)| (id1.as_str(), id2.as_str()))
+            .collect();
+        let data = b">S1\nAAAA\n>S2\nAAAT\n>S3\nAATT\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions::default();
+        let pairs_of = |output_mode| -> Vec<(&str, &str)> {
+            compute_distances(&data_map, &options, output_mode, None, Some(&pairs))
+                .map(|(id1, id2, _)| (id1, id2))
+                .collect()
+        };
+        assert_eq!(
+            pairs_of(OutputMode::LowerTriangle),
+            vec![("S3", "S1"), ("S3", "S2")]
+        );
+        assert_eq!(
+            pairs_of(OutputMode::Full),
+            vec![
+                ("S1", "S1"),
+                ("S1", "S3"),
+                ("S2", "S2"),
+                ("S2", "S3"),
+                ("S3", "S1"),
+                ("S3", "S2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compute_distances_in_chunks() {
+        let data = b">S1\nAAAA\n>S2\nAAAT\n>S3\nAATT\n>S4\nTTTT\n>S5\nAATA\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions::default();
+        for output_mode in [OutputMode::LowerTriangle, OutputMode::Full] {
+            let expected: Vec<_> =
+                compute_distances(&data_map, &options, output_mode, None, None).collect();
+            for chunk_pairs in [0, 3, 100] {
+                let chunks: Vec<_> = compute_distances_in_chunks(
+                    &data_map,
+                    &options,
+                    output_mode,
+                    None,
+                    None,
+                    chunk_pairs,
+                )
+                .collect();
+                assert_eq!(chunks.concat(), expected);
+                if chunk_pairs == 0 {
+                    assert_eq!(chunks.len(), data_map.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_distance_rows() {
+        let data = b">S1\nAAAA\n>S2\nAAAT\n>S3\nAATT\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions::default();
+        let rows: Vec<_> = compute_distance_rows(&data_map, &options, None).collect();
+        let exact = |row: &[usize]| row.iter().map(|&d| Distance::Exact(d)).collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                ("S1", exact(&[0, 1, 2])),
+                ("S2", exact(&[1, 0, 1])),
+                ("S3", exact(&[2, 1, 0])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_written_pairs() {
+        let output = b"id1\tid2\tdistance\nS2\tS1\t1\nS3\tS1\t2\nS3\tS";
+        let (pairs, complete_len) = read_written_pairs(&output[..], "\t", true).unwrap();
+        assert_eq!(complete_len, 33);
+        assert_eq!(
+            pairs,
+            HashSet::from([
+                ("S2".to_string(), "S1".to_string()),
+                ("S3".to_string(), "S1".to_string())
+            ])
+        );
+
+        let (pairs, complete_len) = read_written_pairs(&b"S2, S1, 1\n"[..], ", ", false).unwrap();
+        assert_eq!(complete_len, 10);
+        assert!(pairs.contains(&("S2".to_string(), "S1".to_string())));
+    }
+
+    #[test]
+    fn test_nearest_neighbors() {
+        let data = b">S1\nAAAA\n>S2\nAAAT\n>S3\nAATT\n>S4\nTTTT\n>S5\nAATA\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions::default();
+        let distances = compute_distances(&data_map, &options, OutputMode::Full, None, None);
+        let neighbors: Vec<_> = nearest_neighbors(distances, data_map.len(), 1).collect();
+        assert_eq!(
+            neighbors,
+            vec![
+                ("S1", "S2", Distance::Exact(1)),
+                ("S1", "S5", Distance::Exact(1)),
+                ("S2", "S1", Distance::Exact(1)),
+                ("S2", "S3", Distance::Exact(1)),
+                ("S3", "S2", Distance::Exact(1)),
+                ("S3", "S5", Distance::Exact(1)),
+                ("S4", "S3", Distance::Exact(2)),
+                ("S5", "S1", Distance::Exact(1)),
+                ("S5", "S3", Distance::Exact(1)),
+            ]
+        );
+
+        let options = DistanceOptions {
+            maxdist: Some(2),
+            ..Default::default()
+        };
+        let distances = compute_distances(&data_map, &options, OutputMode::Full, None, None);
+        let neighbors: Vec<_> = nearest_neighbors(distances, data_map.len(), 3)
+            .filter(|(id1, _, _)| *id1 == "S4")
+            .collect();
+        assert_eq!(
+            neighbors,
+            vec![
+                ("S4", "S1", Distance::AtLeast(2)),
+                ("S4", "S2", Distance::AtLeast(2)),
+                ("S4", "S3", Distance::AtLeast(2)),
+                ("S4", "S5", Distance::AtLeast(2)),
+            ]
+        );
+
+        let distances = vec![
+            ("S1", "S1", Distance::Estimate(0.0)),
+            ("S1", "S2", Distance::Estimate(f64::NAN)),
+            ("S1", "S3", Distance::Estimate(2.5)),
+            ("S2", "S1", Distance::Estimate(f64::NAN)),
+            ("S2", "S2", Distance::Estimate(0.0)),
+            ("S2", "S3", Distance::Estimate(f64::NAN)),
+            ("S3", "S1", Distance::Estimate(2.5)),
+            ("S3", "S2", Distance::Estimate(f64::NAN)),
+            ("S3", "S3", Distance::Estimate(0.0)),
+        ];
+        let neighbors: Vec<_> = nearest_neighbors(distances.into_iter(), 3, 1).collect();
+        assert_eq!(
+            neighbors,
+            vec![
+                ("S1", "S3", Distance::Estimate(2.5)),
+                ("S3", "S1", Distance::Estimate(2.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compute_nearest_neighbors() {
+        let data =
+            b">S1\nAAAAAA\n>S2\nAAAATT\n>S3\nAATTTT\n>S4\nTTTTTT\n>S5\nAAAAAT\n>S6\nTTTTTA\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        for maxdist in [None, Some(1), Some(3)] {
+            let options = DistanceOptions {
+                maxdist,
+                ..Default::default()
+            };
+            for k in 1..=6 {
+                let distances =
+                    compute_distances(&data_map, &options, OutputMode::Full, None, None);
+                let expected: Vec<_> = nearest_neighbors(distances, data_map.len(), k).collect();
+                assert_eq!(
+                    compute_nearest_neighbors(&data_map, &options, k, None),
+                    expected
+                );
+            }
+        }
+
+        let precomputed = HashMap::from([(("S1", "S4"), 0)]);
+        let neighbors = compute_nearest_neighbors(
+            &data_map,
+            &DistanceOptions::default(),
+            1,
+            Some(&precomputed),
+        );
+        assert_eq!(neighbors[0], ("S1", "S4", Distance::Exact(0)));
+    }
+
+    #[test]
+    fn test_parse_separator() {
+        assert_eq!(parse_separator("\t").unwrap(), "\t");
+        assert_eq!(parse_separator("\\t").unwrap(), "\t");
+        assert_eq!(parse_separator(", ").unwrap(), ", ");
+        assert_eq!(parse_separator(" | ").unwrap(), " | ");
+        assert_eq!(parse_separator("\\\\").unwrap(), "\\");
+        assert!(parse_separator("\\n").is_err());
+        assert!(parse_separator("\n").is_err());
+        assert!(parse_separator("a\\").is_err());
+        assert!(parse_separator("").is_err());
+
+        let distances = vec![("b", "a", Distance::Exact(2))];
+        let options = OutputOptions {
+            output_sep: ", ".to_string(),
+            header: Some(vec!["id1".into(), "id2".into(), "distance".into()]),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(distances.into_iter(), &mut output, &["a", "b"], &options).unwrap();
+        assert_eq!(output, b"id1, id2, distance\nb, a, 2\n");
+    }
+
+    #[test]
+    fn test_write_condensed() {
+        let data = b">S1\nAAAA\n>S2\nAAAT\n>S3\nAATT\n>S4\nTTTT\n";
+        let data_map =
+            read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions {
+            maxdist: Some(4),
+            ..Default::default()
+        };
+        let distances =
+            compute_distances(&data_map, &options, OutputMode::LowerTriangle, None, None);
+        let output_options = OutputOptions {
+            output_format: OutputFormat::Condensed,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances,
+            &mut output,
+            &sample_ids(&data_map),
+            &output_options,
+        )
+        .unwrap();
+        // (S1, S2), (S1, S3), (S1, S4), (S2, S3), (S2, S4), (S3, S4)
+        assert_eq!(output, b"1\n2\n4\n1\n3\n2\n");
+
+        let distances = vec![("S2", "S1", Distance::Exact(1))];
+        let mut output = Vec::new();
+        assert!(write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["S1", "S2", "S3"],
+            &output_options
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_write_binned_distances() {
+        let distances = vec![
+            ("b", "a", Distance::Exact(0)),
+            ("c", "a", Distance::Exact(5)),
+            ("c", "b", Distance::Estimate(5.5)),
+            ("d", "a", Distance::AtLeast(30)),
+            ("d", "b", Distance::Undefined),
+            ("d", "c", Distance::Exact(1)),
+        ];
+        let options = OutputOptions {
+            header: Some(vec!["id1".into(), "id2".into(), "distance".into()]),
+            ..Default::default()
+        };
+        let edges = [0.0, 5.0, 20.0];
+        let mut writers = vec![Vec::new(); 5];
+        let counts = write_binned_distances(
+            distances.clone().into_iter(),
+            &mut writers,
+            &edges,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(counts, vec![1, 2, 1, 1, 1]);
+        let header = "id1\tid2\tdistance\n";
+        let expected = [
+            "b\ta\t0\n",
+            "c\ta\t5\nd\tc\t1\n",
+            "c\tb\t5.5\n",
+            "d\ta\t>=30\n",
+            "d\tb\tNA\n",
+        ];
+        for (writer, lines) in writers.iter().zip(expected) {
+            assert_eq!(
+                String::from_utf8_lossy(writer),
+                format!("{}{}", header, lines)
+            );
+        }
+
+        let mut writers = vec![Vec::new(); 3];
+        assert!(
+            write_binned_distances(distances.into_iter(), &mut writers, &edges, &options).is_err()
+        );
+    }
+
+    #[test]
+    fn test_distance_histogram() {
+        let distances = vec![
+            ("b", "a", Distance::Exact(2)),
+            ("c", "a", Distance::Exact(0)),
+            ("c", "b", Distance::Exact(2)),
+            ("d", "a", Distance::AtLeast(5)),
+            ("d", "b", Distance::Undefined),
+            ("d", "c", Distance::AtLeast(5)),
+        ];
+        let options = OutputOptions {
+            header: Some(vec![]),
+            ..Default::default()
+        };
+        let histogram = distance_histogram(distances.into_iter(), &options).unwrap();
+        assert_eq!(histogram.exact, BTreeMap::from([(0, 1), (2, 2)]));
+        let mut output = Vec::new();
+        write_histogram(&histogram, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "distance\tcount\n0\t1\n2\t2\n>=5\t2\nNA\t1\n"
+        );
+
+        let estimates = vec![("b", "a", Distance::Estimate(0.5))];
+        assert!(distance_histogram(estimates.into_iter(), &options).is_err());
+    }
+
+    #[test]
+    fn test_write_no_self() {
+        let distances = vec![
+            ("a", "a", Distance::Exact(0)),
+            ("a", "b", Distance::Exact(2)),
+            ("b", "a", Distance::Exact(2)),
+            ("b", "b", Distance::Exact(0)),
+        ];
+        let options = OutputOptions {
+            output_mode: OutputMode::Full,
+            no_self: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.clone().into_iter(),
+            &mut output,
+            &["a", "b"],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output, b"a\tb\t2\nb\ta\t2\n");
+
+        let options = OutputOptions {
+            output_format: OutputFormat::Matrix,
+            ..options
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(distances.into_iter(), &mut output, &["a", "b"], &options).unwrap();
+        assert_eq!(output, b"\ta\tb\na\t0\t2\nb\t2\t0\n");
+    }
+
+    #[test]
+    fn test_write_precision() {
+        let distances = vec![
+            ("b", "a", Distance::Estimate(0.123456)),
+            ("c", "a", Distance::Estimate(2.0)),
+            ("c", "b", Distance::Exact(3)),
+        ];
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.clone().into_iter(),
+            &mut output,
+            &["a", "b", "c"],
+            &OutputOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output, b"b\ta\t0.123456\nc\ta\t2\nc\tb\t3\n");
+
+        let options = OutputOptions {
+            output_format: OutputFormat::Phylip,
+            precision: Some(3),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["a", "b", "c"],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output, b"3\na\nb\t0.123\nc\t2.000\t3\n");
+    }
+
+    #[test]
+    fn test_write_phylip_strict() {
+        let distances = vec![
+            (
+                "sample_long_name_2",
+                "sample_long_name_1",
+                Distance::Exact(1),
+            ),
+            ("S3", "sample_long_name_1", Distance::Exact(2)),
+            ("S3", "sample_long_name_2", Distance::Exact(3)),
+        ];
+        let options = OutputOptions {
+            output_format: OutputFormat::Phylip,
+            output_sep: " ".to_string(),
+            phylip_strict: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["sample_long_name_1", "sample_long_name_2", "S3"],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "3\nsample_lon\nsample_lon 1\nS3         2 3\n"
+        );
+    }
+
+    #[test]
+    fn test_write_phylip_lower_triangle_diag() {
+        let input = b">a\nACGT\n>b\nACGA\n>c\nTCGA\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let output_mode = OutputMode::LowerTriangleDiag;
+        let distances: Vec<_> = compute_distances(
+            &data_map,
+            &DistanceOptions::default(),
+            output_mode,
+            None,
+            None,
+        )
+        .collect();
+        assert_eq!(distances.len(), output_mode.pairs(3));
+
+        for (output_format, expected) in [
+            (OutputFormat::Phylip, "3\na\t0\nb\t1\t0\nc\t2\t1\t0\n"),
+            (
+                OutputFormat::Tabular,
+                "a\ta\t0\nb\ta\t1\nb\tb\t0\nc\ta\t2\nc\tb\t1\nc\tc\t0\n",
+            ),
+        ] {
+            let options = OutputOptions {
+                output_format,
+                output_mode,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write_distances_to_file(
+                distances.iter().copied(),
+                &mut output,
+                &sample_ids(&data_map),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_binary_output_roundtrip() {
+        let distances = vec![
+            ("b", "a", Distance::Exact(3)),
+            ("c", "a", Distance::AtLeast(5)),
+            ("c", "b", Distance::Undefined),
+        ];
+        let options = OutputOptions {
+            output_format: OutputFormat::Binary,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(
+            distances.into_iter(),
+            &mut output,
+            &["a", "b", "c"],
+            &options,
+        )
+        .unwrap();
+
+        let le = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+        let expected = [
+            &b"DSTL"[..],
+            &le(&[1, 3, 1]),
+            b"a",
+            &le(&[1]),
+            b"b",
+            &le(&[1]),
+            b"c",
+            &le(&[3, 5 | 1 << 31, u32::MAX]),
+        ]
+        .concat();
+        assert_eq!(output, expected);
+
+        // Only the exact distance is kept as a precomputed distance
+        let precomputed = read_binary_distances(&output[..], false, None).unwrap();
+        assert_eq!(precomputed.len(), 1);
+        assert_eq!(precomputed[&("a".to_string(), "b".to_string())], 3);
+        assert!(read_binary_distances(&b"3\na\nb"[..], false, None).is_err());
+
+        // Sizes in the header that the file does not hold are errors, not allocations
+        let oversized = [&b"DSTL"[..], &le(&[1, u32::MAX, u32::MAX]), b"a"].concat();
+        assert!(read_binary_distances(&oversized[..], false, None).is_err());
+        assert!(read_binary_distances(&output[..output.len() - 2], false, None).is_err());
+
+        let estimates = vec![("b", "a", Distance::Estimate(0.5))];
+        let result =
+            write_distances_to_file(estimates.into_iter(), Vec::new(), &["a", "b"], &options);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_compute_distances_with() {
+        let input = b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n>S4\nTTTT\n";
+        let data_map =
+            read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+        let options = DistanceOptions::default();
+        let expected: Vec<_> =
+            compute_distances(&data_map, &options, OutputMode::Full, None, None).collect();
+
+        let mut ordered = Vec::new();
+        compute_distances_with(&data_map, &options, OutputMode::Full, |id1, id2, dist| {
+            ordered.push((id1, id2, dist))
+        });
+        assert_eq!(ordered, expected);
+
+        let unordered = std::sync::Mutex::new(Vec::new());
+        par_compute_distances_with(&data_map, &options, OutputMode::Full, |id1, id2, dist| {
+            unordered.lock().unwrap().push((id1, id2, dist))
+        });
+        let mut unordered = unordered.into_inner().unwrap();
+        let position = |(id1, id2, _): &(&str, &str, Distance)| (id1.to_string(), id2.to_string());
+        unordered.sort_by_key(position);
+        let mut expected = expected;
+        expected.sort_by_key(position);
+        assert_eq!(unordered, expected);
+    }
+
+    #[test]
+    fn test_kimura_distance() {
+        let seq = |s: &[u8]| {
+            s.iter()
+                .map(|&b| Nucleotide::from(b))
+                .collect::<PackedNucleotides>()
+        };
+        // Two transitions, one transversion and two ambiguous sites out of twelve
+        let row1 = seq(b"AACCGGTTACNA");
+        let row2 = seq(b"GACTGGTTCCAN");
+        let substitutions = count_substitutions(&row1, &row2);
+        assert_eq!(
+            substitutions,
+            Substitutions {
+                purine_transitions: 1,
+                pyrimidine_transitions: 1,
+                transversions: 1,
+                compared: 10
+            }
+        );
+        let options = DistanceOptions {
+            model: Model::K80,
+            ..Default::default()
+        };
+        let dist = calculate_distance(
+            &SupportedTypeVec::Nucleotide(row1),
+            &SupportedTypeVec::Nucleotide(row2),
+            &options,
+        );
+        assert!(
+            matches!(dist, Distance::Estimate(d) if (d - 0.402359).abs() < 1e-6),
+            "{:?}",
+            dist
+        );
+
+        // Saturated pairs don't have a defined distance
+        let substitutions = Substitutions {
+            purine_transitions: 5,
+            transversions: 0,
+            compared: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            kimura_distance(&substitutions, &options),
+            Distance::Undefined
+        );
+    }
+
+    #[test]
+    fn test_distance_at_most() {
+        let seq = |s: &[u8]| {
+            SupportedTypeVec::Nucleotide(
+                s.iter()
+                    .map(|&b| Nucleotide::from(b))
+                    .collect::<PackedNucleotides>(),
+            )
+        };
+        let row1 = seq(b"ACGTACGTAC");
+        let row2 = seq(b"TCGAACGTTC");
+        let options = DistanceOptions {
+            maxdist: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(distance_at_most(&row1, &row2, 3, &options), Some(3));
+        assert_eq!(distance_at_most(&row1, &row2, 2, &options), None);
+        assert_eq!(distance_at_most(&row1, &row1, 0, &options), Some(0));
+
+        let k80 = DistanceOptions {
+            model: Model::K80,
+            ..Default::default()
+        };
+        assert_eq!(distance_at_most(&row1, &row2, 10, &k80), None);
+    }
+
+    #[test]
+    fn test_ignore_terminal_gaps() {
+        let seq = |s: &[u8]| {
+            SupportedTypeVec::Nucleotide(
+                s.iter()
+                    .map(|&b| Nucleotide::from(b))
+                    .collect::<PackedNucleotides>(),
+            )
+        };
+        // The first two and the last position are trimmed, the internal gap still counts
+        let row1 = seq(b"--ACG-TAA");
+        let row2 = seq(b"A-ACGCTG-");
+        let options = |gaps_as_diff, ignore_terminal_gaps| DistanceOptions {
+            policy: ComparisonPolicy {
+                gaps_as_diff,
+                ignore_terminal_gaps,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_distance(&row1, &row2, &options(true, false)),
+            Distance::Exact(4)
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row2, &options(true, true)),
+            Distance::Exact(2)
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row2, &options(false, true)),
+            Distance::Exact(1)
+        );
+        // Without gaps nothing is trimmed
+        let row3 = seq(b"TTACGATAA");
+        assert_eq!(
+            calculate_distance(&row3, &seq(b"ATACGCTGA"), &options(true, true)),
+            Distance::Exact(3)
+        );
+        assert_eq!(
+            calculate_distance(&seq(b"---"), &seq(b"A--"), &options(true, true)),
+            Distance::Exact(0)
+        );
+    }
+
+    #[test]
+    fn test_strict_acgt() {
+        let seq = |s: &[u8]| {
+            SupportedTypeVec::Nucleotide(s.iter().map(|&b| Nucleotide::from(b)).collect())
+        };
+        let row1 = seq(b"ARCGTN-A");
+        let row2 = seq(b"GACTTAAC");
+        let options = |strict_acgt, missing, min_shared| DistanceOptions {
+            policy: ComparisonPolicy {
+                strict_acgt,
+                missing,
+                ..Default::default()
+            },
+            min_shared,
+            ..Default::default()
+        };
+        let strict = |min_shared| options(true, MissingPolicy::Skip, min_shared);
+        assert_eq!(
+            calculate_distance(&row1, &row2, &DistanceOptions::default()),
+            Distance::Exact(3)
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row2, &strict(None)),
+            Distance::Exact(3)
+        );
+        // Counting missing positions does not apply to the strict comparison
+        let row3 = seq(b"ACCGAAAA");
+        assert_eq!(
+            calculate_distance(&row1, &row3, &options(false, MissingPolicy::Count, None)),
+            Distance::Exact(4)
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row3, &options(true, MissingPolicy::Count, None)),
+            Distance::Exact(1)
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row3, &strict(Some(6))),
+            Distance::Undefined
+        );
+        assert_eq!(
+            calculate_distance(&row1, &row3, &strict(Some(5))),
+            Distance::Exact(1)
+        );
+    }
+
+    #[test]
+    fn test_tamura_nei_distance() {
+        let seq = |s: &[u8]| {
+            SupportedTypeVec::Nucleotide(
+                s.iter()
+                    .map(|&b| Nucleotide::from(b))
+                    .collect::<PackedNucleotides>(),
+            )
+        };
+        let rows = [seq(b"AACCGGTTACNA"), seq(b"GACTGGTTCCAN")];
+        let frequencies = base_frequencies(&rows);
+        assert_eq!(
+            frequencies,
+            BaseFrequencies([6.0 / 22.0, 6.0 / 22.0, 5.0 / 22.0, 5.0 / 22.0])
+        );
+
+        // With equal base frequencies and as many transitions of both kinds TN93 equals K80
+        let k80 = DistanceOptions {
+            model: Model::K80,
+            ..Default::default()
+        };
+        let tn93 = DistanceOptions {
+            model: Model::Tn93,
+            ..Default::default()
+        };
+        let expected = calculate_distance(&rows[0], &rows[1], &k80);
+        let dist = calculate_distance(&rows[0], &rows[1], &tn93);
+        assert!(
+            matches!((dist, expected), (Distance::Estimate(d), Distance::Estimate(e)) if (d - e).abs() < 1e-12),
+            "{:?} {:?}",
+            dist,
+            expected
+        );
+
+        let tn93 = DistanceOptions {
+            base_frequencies: Some(frequencies),
+            ..tn93
+        };
+        let dist = calculate_distance(&rows[0], &rows[1], &tn93);
+        assert!(
+            matches!(dist, Distance::Estimate(d) if (d - 0.403273).abs() < 1e-6),
+            "{:?}",
+            dist
+        );
+        assert_eq!(
+            calculate_distance(&rows[0], &rows[0], &tn93),
+            Distance::Estimate(0.0)
+        );
+
+        // Saturated pairs and alignments without one of the bases don't have a defined distance
+        let substitutions = Substitutions {
+            pyrimidine_transitions: 5,
+            compared: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            tamura_nei_distance(&substitutions, &tn93),
+            Distance::Undefined
+        );
+        let no_t = DistanceOptions {
+            base_frequencies: Some(BaseFrequencies([0.5, 0.25, 0.25, 0.0])),
+            ..tn93
+        };
+        assert_eq!(
+            calculate_distance(&rows[0], &rows[1], &no_t),
+            Distance::Undefined
+        );
+    }
+
+    #[test]
+    fn test_append_distances_to_cache() {
+        let cache = read_and_parse_tabular_distances(&b"b\ta\t1\n"[..], '\t', true, None).unwrap();
+        let cache: HashMap<(&str, &str), usize> = cache
+            .iter()
+            .map(|((id1, id2), dist)| ((id1.as_str(), id2.as_str()), *dist))
+            .collect();
+        let distances = vec![
+            ("a", "a", Distance::Exact(0)),
+            ("a", "b", Distance::Exact(1)),
+            ("a", "c", Distance::Exact(2)),
+            ("b", "a", Distance::Exact(1)),
+            ("c", "a", Distance::Exact(2)),
+            ("c", "b", Distance::Undefined),
+        ];
+        let mut output = Vec::new();
+        let written =
+            append_distances_to_cache(distances.into_iter(), &cache, &mut output, '\t').unwrap();
+        assert_eq!(written, 1);
+        assert_eq!(output, b"a\tc\t2\n");
+    }
+
+    #[test]
+    fn test_read_tabular_distances_symmetric_check() {
+        let input = b"a\tb\t1\nc\ta\t2\nb\ta\t1\n";
+        let distances = read_and_parse_tabular_distances(&input[..], '\t', true, None).unwrap();
+        assert_eq!(distances.len(), 2);
+        assert_eq!(distances[&("a".to_string(), "b".to_string())], 1);
+        assert_eq!(distances[&("a".to_string(), "c".to_string())], 2);
+
+        let input = b"a\tb\t1\nb\ta\t3\n";
+        let error = read_and_parse_tabular_distances(&input[..], '\t', true, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting precomputed distances for b and a: 1 and 3"
+        );
+        let distances = read_and_parse_tabular_distances(&input[..], '\t', false, None).unwrap();
+        assert_eq!(distances[&("a".to_string(), "b".to_string())], 3);
+    }
+
+    #[test]
+    fn test_precomputed_distance() {
+        let input = b"b\ta\t1\nc\ta\t2\na\tb\t1\n";
+        let distances = read_and_parse_tabular_distances(&input[..], '\t', true, None).unwrap();
+        assert_eq!(distances.len(), 2);
+        assert!(!distances.contains_key(&("b".to_string(), "a".to_string())));
+
+        let distances: HashMap<(&str, &str), usize> = distances
+            .iter()
+            .map(|((id1, id2), dist)| ((id1.as_str(), id2.as_str()), *dist))
+            .collect();
+        assert_eq!(precomputed_distance(&distances, "b", "a"), Some(1));
+        assert_eq!(precomputed_distance(&distances, "a", "c"), Some(2));
+        assert_eq!(precomputed_distance(&distances, "b", "c"), None);
+    }
+
+    #[test]
+    fn test_distance_table() {
+        let mut table = DistanceTable::default();
+        let options = ParseOptions::default();
+        let input = b"b\ta\t1\nc\ta\tNA\n\nb\tc\t>=5\n";
+        table.read(&input[..], &options, "NA", true).unwrap();
+        table
+            .read(&b"d\tb\t0.5\na\tb\t1\n"[..], &options, "NA", true)
+            .unwrap();
+        assert_eq!(table.sample_ids(), vec!["b", "a", "c", "d"]);
+
+        let distances: Vec<_> = table.distances(OutputMode::LowerTriangle).collect();
+        assert_eq!(
+            distances,
+            vec![
+                ("a", "b", Distance::Exact(1)),
+                ("c", "b", Distance::AtLeast(5)),
+                ("c", "a", Distance::Undefined),
+                ("d", "b", Distance::Estimate(0.5)),
+                ("d", "a", Distance::Undefined),
+                ("d", "c", Distance::Undefined),
+            ]
+        );
+        let diagonal: Vec<_> = table.distances(OutputMode::LowerTriangleDiag).collect();
+        assert_eq!(diagonal[0], ("b", "b", Distance::Exact(0)));
+
+        let error = table
+            .read(&b"a\tb\t2\n"[..], &options, "NA", true)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting distances for a and b: 1 and 2"
+        );
+        assert!(table
+            .read(&b"a\tb\tx\n"[..], &options, "NA", false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_matrix_distances() {
+        let input = b"\ta\tb\tc\na\t0\t1\t>=5\nb\t1\t0\tNA\nc\t>=5\tNA\t0\n";
+        let distances = read_and_parse_matrix_distances(&input[..], '\t', true, None).unwrap();
+        assert_eq!(distances.len(), 4);
+        assert_eq!(distances[&("a".to_string(), "b".to_string())], 1);
+        assert!(!distances.contains_key(&("b".to_string(), "a".to_string())));
+        assert_eq!(distances[&("c".to_string(), "c".to_string())], 0);
+        assert!(!distances.contains_key(&("a".to_string(), "c".to_string())));
+
+        let input = b"\ta\tb\na\t0\t1\nb\t2\t0\n";
+        let error = read_and_parse_matrix_distances(&input[..], '\t', true, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting precomputed distances for b and a: 1 and 2"
+        );
 
-        assert_eq!(compute_distance_eq(&row1, &row2, None), 5);
-        assert_eq!(compute_distance_eq(&row1, &row3, None), 1);
-        assert_eq!(compute_distance_eq(&row2, &row3, None), 6);
+        let input = b"\ta\na\t0\t1\n";
+        assert!(read_and_parse_matrix_distances(&input[..], '\t', true, None).is_err());
     }
 }
@@ -0,0 +1,183 @@
+//! A round trip of a small random input through every output format, to check that a build
+//! writes the formats consistently and reads back the ones that are also precomputed formats.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use clap::ValueEnum;
+
+use crate::processing::{
+    compute_distances, read_and_parse_fasta, read_and_parse_matrix_distances,
+    read_and_parse_tabular_distances, read_binary_distances, sample_ids, write_distances_to_file,
+    Distance, DistanceOptions, OutputFormat, OutputMode, OutputOptions, ParseOptions,
+};
+use crate::types::InputFormat;
+
+const SAMPLES: usize = 8;
+const POSITIONS: usize = 60;
+
+/// Distances by the IDs of a pair in sorted order
+type Distances<'a> = HashMap<(&'a str, &'a str), usize>;
+
+/// FASTA records with random bases, gaps and missing bases. The seed must not be 0.
+fn random_fasta(seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    let mut fasta = Vec::new();
+    for i in 0..SAMPLES {
+        fasta.extend(format!(">S{}\n", i).bytes());
+        for _ in 0..POSITIONS {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            fasta.push(b"ACGTACGTN-"[(state % 10) as usize]);
+        }
+        fasta.push(b'\n');
+    }
+    fasta
+}
+
+/// Computes the distances of a random input from the seed and writes them in every output
+/// format. The tabular, matrix and binary output are read back as precomputed distances, the
+/// condensed and NDJSON output line by line, and for the Phylip and NEXUS output the number of
+/// samples is checked. Returns the names of the output formats that passed.
+pub fn self_test(seed: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    let input = random_fasta(seed.max(1));
+    let data_map = read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default())?;
+    let ids = sample_ids(&data_map);
+    let options = DistanceOptions::default();
+
+    let mut expected = HashMap::new();
+    let distances = compute_distances(
+        &data_map,
+        &options,
+        OutputMode::LowerTriangleDiag,
+        None,
+        None,
+    );
+    for (id1, id2, dist) in distances {
+        let Distance::Exact(dist) = dist else {
+            return Err(format!("The distance between {} and {} is not exact", id1, id2).into());
+        };
+        expected.insert((id1.min(id2), id1.max(id2)), dist);
+    }
+
+    let mut passed = Vec::new();
+    for &output_format in OutputFormat::value_variants() {
+        let output_mode = match output_format {
+            OutputFormat::Matrix => OutputMode::Full,
+            _ => OutputMode::LowerTriangle,
+        };
+        let output_options = OutputOptions {
+            output_format,
+            output_mode,
+            ..Default::default()
+        };
+        let distances = compute_distances(&data_map, &options, output_mode, None, None);
+        let mut output = Vec::new();
+        write_distances_to_file(distances, &mut output, &ids, &output_options)?;
+
+        let name = output_format.to_possible_value().map_or_else(
+            || format!("{:?}", output_format),
+            |value| value.get_name().into(),
+        );
+        check_output(output_format, &output, &ids, &expected).map_err(|e| {
+            format!(
+                "The {} output failed the self-test with seed {}: {}",
+                name, seed, e
+            )
+        })?;
+        passed.push(name);
+    }
+    Ok(passed)
+}
+
+fn check_output(
+    output_format: OutputFormat,
+    output: &[u8],
+    ids: &[&str],
+    expected: &Distances,
+) -> Result<(), Box<dyn Error>> {
+    let read = match output_format {
+        OutputFormat::Tabular => read_and_parse_tabular_distances(output, '\t', true, None)?,
+        OutputFormat::Matrix => read_and_parse_matrix_distances(output, '\t', true, None)?,
+        OutputFormat::Binary => read_binary_distances(output, true, None)?,
+        OutputFormat::Condensed => {
+            let mut values = std::str::from_utf8(output)?.lines();
+            let mut read = HashMap::new();
+            for (i, id1) in ids.iter().enumerate() {
+                for id2 in &ids[i + 1..] {
+                    let value = values.next().ok_or("Too few distances")?.parse()?;
+                    read.insert((id1.min(id2).to_string(), id1.max(id2).to_string()), value);
+                }
+            }
+            if values.next().is_some() {
+                return Err("Too many distances".into());
+            }
+            read
+        }
+        // The sample IDs of the self-test need no escaping, so the objects are split at the keys
+        OutputFormat::Ndjson => {
+            let mut read = HashMap::new();
+            for line in std::str::from_utf8(output)?.lines() {
+                let fields = line
+                    .strip_prefix("{\"id1\":\"")
+                    .and_then(|rest| rest.strip_suffix('}'))
+                    .ok_or("Not a JSON object")?;
+                let (id1, rest) = fields.split_once("\",\"id2\":\"").ok_or("Missing id2")?;
+                let (id2, dist) = rest
+                    .split_once("\",\"distance\":")
+                    .ok_or("Missing distance")?;
+                read.insert(
+                    (id1.min(id2).to_string(), id1.max(id2).to_string()),
+                    dist.parse()?,
+                );
+            }
+            read
+        }
+        // These formats are not read back, so only the number of samples is checked
+        OutputFormat::Phylip | OutputFormat::Nexus => {
+            let text = std::str::from_utf8(output)?;
+            let found = match output_format {
+                OutputFormat::Phylip => text.lines().next() == Some(&ids.len().to_string()),
+                _ => text.contains(&format!("ntax={};", ids.len())),
+            };
+            if !found {
+                return Err(format!("The number of samples {} is missing", ids.len()).into());
+            }
+            return Ok(());
+        }
+    };
+
+    let mut pairs = 0;
+    for ((id1, id2), &dist) in &read {
+        let wanted = expected.get(&(id1.as_str(), id2.as_str()));
+        if wanted != Some(&dist) {
+            return Err(format!(
+                "The distance between {} and {} is {} instead of {:?}",
+                id1, id2, dist, wanted
+            )
+            .into());
+        }
+        pairs += usize::from(id1 != id2);
+    }
+    let wanted = ids.len() * (ids.len() - 1) / 2;
+    if pairs != wanted {
+        return Err(format!("Read {} pairs instead of {}", pairs, wanted).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() {
+        for seed in [0, 1, 42] {
+            let passed = self_test(seed).unwrap();
+            assert_eq!(passed.len(), OutputFormat::value_variants().len());
+        }
+        assert_ne!(random_fasta(1), random_fasta(2));
+    }
+}
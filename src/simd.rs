@@ -0,0 +1,120 @@
+//! Hamming distances of the fasta-all and cgmlst-hash rows with explicit SIMD. Only used for the
+//! default comparison, where a missing position matches anything, so the policies with other
+//! rules for missing positions keep the scalar comparison.
+
+use wide::{i8x16, i8x32, CmpEq};
+
+use crate::types::{Hash, NucleotideAll};
+
+const LANES: usize = 32;
+
+/// Counts the positions where neither character is missing and they differ, 32 at a time. Like
+/// `zip`, only the overlapping length is compared. Counting stops once `maxdist` is reached, in
+/// which case `maxdist` is returned.
+pub fn count_nucleotide_mismatches(
+    row1: &[NucleotideAll],
+    row2: &[NucleotideAll],
+    maxdist: usize,
+) -> usize {
+    let len = row1.len().min(row2.len());
+    let (row1, row2) = (&row1[..len], &row2[..len]);
+    let zero = i8x32::splat(0);
+    let mut count = 0;
+
+    for (chunk1, chunk2) in row1.chunks_exact(LANES).zip(row2.chunks_exact(LANES)) {
+        let x = i8x32::new(std::array::from_fn(|i| chunk1[i].byte() as i8));
+        let y = i8x32::new(std::array::from_fn(|i| chunk2[i].byte() as i8));
+        let matches = x.cmp_eq(y) | x.cmp_eq(zero) | y.cmp_eq(zero);
+        count += LANES - (matches.move_mask() as u32).count_ones() as usize;
+        if count >= maxdist {
+            return maxdist;
+        }
+    }
+    let tail = len - len % LANES;
+    count += row1[tail..]
+        .iter()
+        .zip(&row2[tail..])
+        .filter(|(x, y)| x != y)
+        .count();
+    count.min(maxdist)
+}
+
+/// Counts the loci where neither hash is missing and they differ. The bytes of every hash are
+/// compared 16 at a time. Counting stops like [`count_nucleotide_mismatches`].
+pub fn count_hash_mismatches<const N: usize>(
+    row1: &[Hash<N>],
+    row2: &[Hash<N>],
+    maxdist: usize,
+) -> usize {
+    let mut count = 0;
+    for (x, y) in row1.iter().zip(row2) {
+        if hash_differs(x.bytes(), y.bytes()) {
+            count += 1;
+            if count >= maxdist {
+                return maxdist;
+            }
+        }
+    }
+    count
+}
+
+/// Whether neither hash is only zeros and they differ
+#[inline]
+fn hash_differs<const N: usize>(x: &[u8; N], y: &[u8; N]) -> bool {
+    let zero = i8x16::splat(0);
+    let mut same = true;
+    let mut missing1 = true;
+    let mut missing2 = true;
+
+    let blocks = N / 16;
+    for block in 0..blocks {
+        let x = i8x16::new(std::array::from_fn(|i| x[block * 16 + i] as i8));
+        let y = i8x16::new(std::array::from_fn(|i| y[block * 16 + i] as i8));
+        same &= x.cmp_eq(y).move_mask() == 0xFFFF;
+        missing1 &= x.cmp_eq(zero).move_mask() == 0xFFFF;
+        missing2 &= y.cmp_eq(zero).move_mask() == 0xFFFF;
+    }
+    let tail = blocks * 16;
+    same &= x[tail..] == y[tail..];
+    missing1 &= x[tail..].iter().all(|&b| b == 0);
+    missing2 &= y[tail..].iter().all(|&b| b == 0);
+    !same && !missing1 && !missing2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{SHA1Hash, SHA256Hash};
+
+    #[test]
+    fn test_simd_mismatches() {
+        // Long enough for several blocks and a tail, with missing positions in both rows
+        let seq1: Vec<u8> = (0..100u8).map(|i| b"acgt-"[(i % 5) as usize]).collect();
+        let seq2: Vec<u8> = (0..100u8).map(|i| b"acgtn"[(i % 7 % 5) as usize]).collect();
+        let lut = NucleotideAll::lookup_table(b"-n", false);
+        let row1: Vec<_> = seq1.iter().map(|&b| lut[b as usize]).collect();
+        let row2: Vec<_> = seq2.iter().map(|&b| lut[b as usize]).collect();
+        let expected = row1.iter().zip(&row2).filter(|(x, y)| x != y).count();
+        assert!(expected > 0);
+        assert_eq!(
+            count_nucleotide_mismatches(&row1, &row2, usize::MAX),
+            expected
+        );
+        assert_eq!(count_nucleotide_mismatches(&row1, &row2, 3), 3);
+        assert_eq!(
+            count_nucleotide_mismatches(&row1[..50], &row2, usize::MAX),
+            { row1[..50].iter().zip(&row2).filter(|(x, y)| x != y).count() }
+        );
+
+        let sha1 = |s: &str| s.parse::<SHA1Hash>().unwrap();
+        let row1 = [sha1("01"), sha1("02"), sha1(""), sha1(&"ab".repeat(20))];
+        let row2 = [sha1("01"), sha1("03"), sha1("04"), sha1(&"ac".repeat(20))];
+        assert_eq!(count_hash_mismatches(&row1, &row2, usize::MAX), 2);
+        assert_eq!(count_hash_mismatches(&row1, &row2, 1), 1);
+
+        let sha256 = |s: &str| s.parse::<SHA256Hash>().unwrap();
+        let row1 = [sha256(&"ab".repeat(32)), sha256(&"ab".repeat(32))];
+        let row2 = [sha256(&"ab".repeat(32)), sha256(&"ab".repeat(31))];
+        assert_eq!(count_hash_mismatches(&row1, &row2, usize::MAX), 1);
+    }
+}
@@ -1,34 +1,414 @@
 use clap::ValueEnum;
+use std::collections::BTreeSet;
 use std::fmt::Debug;
+use std::mem::discriminant;
+use std::ops::Deref;
 
 #[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
 pub enum InputFormat {
     /// A cgmlst table with allele numbers. Optimized for ChewBBACA output
     Cgmlst,
-    /// A cgmlst table with SHA1 hashes of the nucleotide of the alleles
+    /// A cgmlst table with SHA1 or SHA256 hashes of the nucleotide of the alleles. The width is detected from the number of hex digits
     CgmlstHash,
     /// An alignment of nucleotide sequences in FASTA format
     Fasta,
     /// An alignment of nucleotide sequences in FASTA format. Counts all differences and not just [ACTG]
     FastaAll,
+    /// An alignment of amino acid sequences in FASTA format. X, gaps and stops match any residue
+    Protein,
+    /// Nucleotide sequences in FASTA format that are compared by the Jaccard distance of MinHash sketches of their k-mers. The sequences don't have to be aligned
+    FastaSketch,
+    /// A nucleotide alignment in sequential or interleaved Phylip format, compared like the fasta input format. Names end at the first whitespace, or are the first 10 characters with phylip-strict
+    Phylip,
+    /// A presence/absence table like a cgmlst table, where every field is 0 or 1. The distance is the number of fields that differ
+    Binary,
+    /// Distances in the tabular long format with two sample IDs and the distance on every line, as written by the tabular output format. The distances are written in the output format without computing them, so distle converts between the formats. The samples are in the order they first appear
+    Distances,
+}
+
+/// How positions that are missing in one of the two samples are compared
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum MissingPolicy {
+    /// Missing positions match anything and do not add to the distance
+    #[default]
+    Skip,
+    /// A missing position counts as a difference unless it is missing in both samples
+    Count,
+}
+
+/// Which positions are compared for a pair of samples
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum Deletion {
+    /// Every pair is compared at the positions that are non-missing in both samples
+    #[default]
+    Pairwise,
+    /// Only the positions that are non-missing in all samples are compared
+    Complete,
+}
+
+/// How inferred alleles of chewBBACA, written as `INF-` followed by the allele number, are compared
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum InferredPolicy {
+    /// An inferred allele is the same as the allele with its number
+    #[default]
+    Match,
+    /// An inferred allele only matches the same inferred allele, so it differs from the allele with its number
+    Mismatch,
+    /// An inferred allele is missing
+    Missing,
+}
+
+/// Controls how the sites of two samples are compared
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ComparisonPolicy {
+    /// How positions that are missing in one of the samples are compared
+    pub missing: MissingPolicy,
+    /// Count gaps as differences with concrete bases in the Fasta format
+    pub gaps_as_diff: bool,
+    /// Leave out the leading and trailing positions where either sample has a gap in the Fasta
+    /// format
+    pub ignore_terminal_gaps: bool,
+    /// Only count differences between two of A, C, G and T in the Fasta format, and treat
+    /// ambiguous bases and gaps as missing
+    pub strict_acgt: bool,
 }
 
 pub type InputMatrix = Vec<(String, SupportedTypeVec)>;
 
+/// An input matrix whose rows all have the same type, so any two of its rows can be compared
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct HomogeneousMatrix(InputMatrix);
+
+impl HomogeneousMatrix {
+    pub fn into_inner(self) -> InputMatrix {
+        self.0
+    }
+}
+
+impl TryFrom<InputMatrix> for HomogeneousMatrix {
+    type Error = String;
+
+    /// Rows of SHA-1 hashes are widened to SHA-256 when other rows have SHA-256 hashes, since a
+    /// row whose hashes are all short or missing cannot tell the widths apart
+    fn try_from(mut rows: InputMatrix) -> Result<Self, Self::Error> {
+        if rows
+            .iter()
+            .any(|(_, row)| matches!(row, SupportedTypeVec::SHA256Hash(_)))
+        {
+            for (_, row) in rows.iter_mut() {
+                if let SupportedTypeVec::SHA1Hash(hashes) = row {
+                    *row = SupportedTypeVec::SHA256Hash(hashes.iter().map(Hash::widen).collect());
+                }
+            }
+        }
+        if let Some((first_id, first_row)) = rows.first() {
+            let first_type = discriminant(first_row);
+            if let Some((id, _)) = rows.iter().find(|(_, row)| discriminant(row) != first_type) {
+                return Err(format!(
+                    "Sample {} has a different type than sample {}",
+                    id, first_id
+                ));
+            }
+        }
+        Ok(HomogeneousMatrix(rows))
+    }
+}
+
+impl Deref for HomogeneousMatrix {
+    type Target = InputMatrix;
+
+    fn deref(&self) -> &InputMatrix {
+        &self.0
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum SupportedTypeVec {
-    Nucleotide(Vec<Nucleotide>),
+    Nucleotide(PackedNucleotides),
     NucleotideAll(Vec<NucleotideAll>),
     Cgmlst(Vec<ChewBBACAinteger>),
     SHA1Hash(Vec<SHA1Hash>),
+    SHA256Hash(Vec<SHA256Hash>),
+    AminoAcid(Vec<AminoAcid>),
+    Sketch(MinHashSketch),
+    Binary(PackedPresence),
+}
+
+impl SupportedTypeVec {
+    /// The number of positions or loci in the row
+    pub fn len(&self) -> usize {
+        match self {
+            SupportedTypeVec::Nucleotide(row) => row.len(),
+            SupportedTypeVec::NucleotideAll(row) => row.len(),
+            SupportedTypeVec::Cgmlst(row) => row.len(),
+            SupportedTypeVec::SHA1Hash(row) => row.len(),
+            SupportedTypeVec::SHA256Hash(row) => row.len(),
+            SupportedTypeVec::AminoAcid(row) => row.len(),
+            SupportedTypeVec::Sketch(sketch) => sketch.hashes.len(),
+            SupportedTypeVec::Binary(row) => row.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The parsed sequence as bytes, with nucleotides as IUPAC codes and '?' for characters
+    /// that are missing in the fasta-all input format. None for rows that are not sequences.
+    pub fn to_sequence(&self) -> Option<Vec<u8>> {
+        match self {
+            SupportedTypeVec::Nucleotide(row) => Some(row.iter().map(|n| n.to_iupac()).collect()),
+            SupportedTypeVec::NucleotideAll(row) => Some(
+                row.iter()
+                    .map(|n| if n.is_missing() { b'?' } else { n.0 })
+                    .collect(),
+            ),
+            SupportedTypeVec::AminoAcid(row) => Some(row.iter().map(|a| a.0).collect()),
+            SupportedTypeVec::Cgmlst(_)
+            | SupportedTypeVec::SHA1Hash(_)
+            | SupportedTypeVec::SHA256Hash(_)
+            | SupportedTypeVec::Sketch(_)
+            | SupportedTypeVec::Binary(_) => None,
+        }
+    }
+
+    /// The value at the position as text: a character for sequences, the allele number or hash
+    /// of a locus, or 0 and 1 for presence and absence. Missing alleles and hashes are '-', and
+    /// characters that are missing in the fasta-all input format '?'. None for sketches and
+    /// positions past the end of the row.
+    pub fn value_at(&self, index: usize) -> Option<String> {
+        if index >= self.len() {
+            return None;
+        }
+        fn hex<const N: usize>(hash: &Hash<N>) -> String {
+            if hash.is_missing() {
+                return "-".into();
+            }
+            hash.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+        }
+        Some(match self {
+            SupportedTypeVec::Nucleotide(row) => char::from(row.get(index).to_iupac()).into(),
+            SupportedTypeVec::NucleotideAll(row) if row[index].is_missing() => "?".into(),
+            SupportedTypeVec::NucleotideAll(row) => char::from(row[index].0).into(),
+            SupportedTypeVec::AminoAcid(row) => char::from(row[index].0).into(),
+            SupportedTypeVec::Cgmlst(row) => {
+                let allele = row[index];
+                if allele.is_missing() {
+                    "-".into()
+                } else if allele.0 & ChewBBACAinteger::INFERRED != 0 {
+                    format!("INF-{}", allele.allele())
+                } else {
+                    allele.allele().to_string()
+                }
+            }
+            SupportedTypeVec::SHA1Hash(row) => hex(&row[index]),
+            SupportedTypeVec::SHA256Hash(row) => hex(&row[index]),
+            SupportedTypeVec::Binary(row) => u8::from(row.get(index).0).to_string(),
+            SupportedTypeVec::Sketch(_) => return None,
+        })
+    }
+
+    /// Whether the position is missing. Positions past the end of the row are missing.
+    pub fn is_missing(&self, index: usize) -> bool {
+        if index >= self.len() {
+            return true;
+        }
+        match self {
+            SupportedTypeVec::Nucleotide(row) => row.get(index).is_missing(),
+            SupportedTypeVec::NucleotideAll(row) => row[index].is_missing(),
+            SupportedTypeVec::Cgmlst(row) => row[index].is_missing(),
+            SupportedTypeVec::SHA1Hash(row) => row[index].is_missing(),
+            SupportedTypeVec::SHA256Hash(row) => row[index].is_missing(),
+            SupportedTypeVec::AminoAcid(row) => row[index].is_missing(),
+            SupportedTypeVec::Sketch(_) | SupportedTypeVec::Binary(_) => false,
+        }
+    }
+
+    /// Which positions of the row are missing under the policy. With `ignore_terminal_gaps` the
+    /// leading and trailing gaps of a nucleotide row are missing too. Sketches have no missing
+    /// positions.
+    pub fn missing_under(&self, policy: &ComparisonPolicy) -> Vec<bool> {
+        fn mask<T: Site>(sites: impl Iterator<Item = T>, policy: &ComparisonPolicy) -> Vec<bool> {
+            sites.map(|site| site.is_missing_under(policy)).collect()
+        }
+        match self {
+            SupportedTypeVec::Nucleotide(row) => {
+                let mut missing = mask(row.iter(), policy);
+                if policy.ignore_terminal_gaps {
+                    let start = (0..row.len())
+                        .find(|&i| !row.get(i).is_gap())
+                        .unwrap_or(row.len());
+                    let end = (start..row.len())
+                        .rfind(|&i| !row.get(i).is_gap())
+                        .map_or(start, |i| i + 1);
+                    missing[..start].fill(true);
+                    missing[end..].fill(true);
+                }
+                missing
+            }
+            SupportedTypeVec::NucleotideAll(row) => mask(row.iter().copied(), policy),
+            SupportedTypeVec::Cgmlst(row) => mask(row.iter().copied(), policy),
+            SupportedTypeVec::SHA1Hash(row) => mask(row.iter().copied(), policy),
+            SupportedTypeVec::SHA256Hash(row) => mask(row.iter().copied(), policy),
+            SupportedTypeVec::AminoAcid(row) => mask(row.iter().copied(), policy),
+            SupportedTypeVec::Sketch(_) | SupportedTypeVec::Binary(_) => vec![false; self.len()],
+        }
+    }
+
+    /// The row with only the positions whose entry in `keep` is true. Positions past the end of
+    /// `keep` are left out.
+    pub fn select_columns(&self, keep: &[bool]) -> Result<Self, &'static str> {
+        fn select<T: Clone>(row: &[T], keep: &[bool]) -> Vec<T> {
+            row.iter()
+                .zip(keep)
+                .filter(|(_, &keep)| keep)
+                .map(|(site, _)| site.clone())
+                .collect()
+        }
+        let kept = |index: &usize| keep.get(*index).copied().unwrap_or(false);
+        Ok(match self {
+            SupportedTypeVec::Nucleotide(row) => SupportedTypeVec::Nucleotide(
+                (0..row.len()).filter(kept).map(|i| row.get(i)).collect(),
+            ),
+            SupportedTypeVec::NucleotideAll(row) => {
+                SupportedTypeVec::NucleotideAll(select(row, keep))
+            }
+            SupportedTypeVec::Cgmlst(row) => SupportedTypeVec::Cgmlst(select(row, keep)),
+            SupportedTypeVec::SHA1Hash(row) => SupportedTypeVec::SHA1Hash(select(row, keep)),
+            SupportedTypeVec::SHA256Hash(row) => SupportedTypeVec::SHA256Hash(select(row, keep)),
+            SupportedTypeVec::AminoAcid(row) => SupportedTypeVec::AminoAcid(select(row, keep)),
+            SupportedTypeVec::Binary(row) => {
+                SupportedTypeVec::Binary((0..row.len()).filter(kept).map(|i| row.get(i)).collect())
+            }
+            SupportedTypeVec::Sketch(_) => {
+                return Err("The positions of sketches cannot be selected")
+            }
+        })
+    }
+
+    /// Whether both rows have exactly the same value at the position, unlike the comparison of
+    /// sites where missing values match anything. Always false for sketches.
+    pub fn identical_at(&self, other: &Self, index: usize) -> bool {
+        match (self, other) {
+            (SupportedTypeVec::Nucleotide(x), SupportedTypeVec::Nucleotide(y)) => {
+                index < x.len() && index < y.len() && x.get(index).0 == y.get(index).0
+            }
+            (SupportedTypeVec::NucleotideAll(x), SupportedTypeVec::NucleotideAll(y)) => x
+                .get(index)
+                .zip(y.get(index))
+                .is_some_and(|(x, y)| x.0 == y.0),
+            (SupportedTypeVec::Cgmlst(x), SupportedTypeVec::Cgmlst(y)) => x
+                .get(index)
+                .zip(y.get(index))
+                .is_some_and(|(x, y)| x.0 == y.0),
+            (SupportedTypeVec::SHA1Hash(x), SupportedTypeVec::SHA1Hash(y)) => x
+                .get(index)
+                .zip(y.get(index))
+                .is_some_and(|(x, y)| x.0 == y.0),
+            (SupportedTypeVec::SHA256Hash(x), SupportedTypeVec::SHA256Hash(y)) => x
+                .get(index)
+                .zip(y.get(index))
+                .is_some_and(|(x, y)| x.0 == y.0),
+            (SupportedTypeVec::AminoAcid(x), SupportedTypeVec::AminoAcid(y)) => x
+                .get(index)
+                .zip(y.get(index))
+                .is_some_and(|(x, y)| x.0 == y.0),
+            (SupportedTypeVec::Binary(x), SupportedTypeVec::Binary(y)) => {
+                index < x.len() && index < y.len() && x.get(index) == y.get(index)
+            }
+            _ => false,
+        }
+    }
+
+    /// Pads a row of loci with missing alleles up to the given number of loci. Longer rows are
+    /// kept as they are.
+    pub fn pad_missing(&mut self, len: usize) -> Result<(), &'static str> {
+        match self {
+            SupportedTypeVec::Cgmlst(row) => row.resize(len.max(row.len()), ChewBBACAinteger(0)),
+            SupportedTypeVec::SHA1Hash(row) => row.resize(len.max(row.len()), Hash([0; 20])),
+            SupportedTypeVec::SHA256Hash(row) => row.resize(len.max(row.len()), Hash([0; 32])),
+            _ => return Err("Only the rows of the cgmlst input formats can be padded"),
+        }
+        Ok(())
+    }
+}
+
+/// A value at a single position of a sample that knows whether it is missing
+pub trait Site: PartialEq {
+    fn is_missing(&self) -> bool;
+
+    /// Whether this site is missing under the policy, which only differs from
+    /// [`Site::is_missing`] for the policies that treat more sites as missing
+    #[inline]
+    fn is_missing_under(&self, _policy: &ComparisonPolicy) -> bool {
+        self.is_missing()
+    }
+
+    /// Whether this site counts as a difference with the other site under the policy
+    #[inline]
+    fn differs(&self, other: &Self, policy: &ComparisonPolicy) -> bool {
+        differs_by_missing_policy(self, other, policy.missing)
+    }
+}
+
+#[inline]
+fn differs_by_missing_policy<T: Site + ?Sized>(x: &T, y: &T, policy: MissingPolicy) -> bool {
+    match policy {
+        MissingPolicy::Skip => x != y,
+        MissingPolicy::Count => match (x.is_missing(), y.is_missing()) {
+            (false, false) => x != y,
+            (missing1, missing2) => missing1 != missing2,
+        },
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct ChewBBACAinteger(u16);
 
+impl ChewBBACAinteger {
+    /// Marks an inferred allele for the mismatch policy
+    const INFERRED: u16 = 1 << 15;
+
+    /// Parses an allele like [`FromStr`], and handles inferred alleles by the policy. With the
+    /// mismatch policy inferred alleles are marked by the highest bit, so the allele numbers have
+    /// to be below 32768.
+    pub fn parse_inferred(s: &str, policy: InferredPolicy) -> Result<Self, String> {
+        // Some exports leave the cells of missing loci empty
+        if s.trim().is_empty() {
+            return Ok(ChewBBACAinteger(0));
+        }
+        let (inferred, number) = match s.strip_prefix("INF-") {
+            Some(number) => (true, number),
+            None => (false, s),
+        };
+        let value = number.parse::<u16>().unwrap_or(0);
+        match policy {
+            InferredPolicy::Match => Ok(ChewBBACAinteger(value)),
+            InferredPolicy::Missing if inferred => Ok(ChewBBACAinteger(0)),
+            InferredPolicy::Missing => Ok(ChewBBACAinteger(value)),
+            InferredPolicy::Mismatch if value >= Self::INFERRED => Err(format!(
+                "Allele {} is too large to tell inferred alleles apart, the largest is {}",
+                s,
+                Self::INFERRED - 1
+            )),
+            InferredPolicy::Mismatch if inferred && value != 0 => {
+                Ok(ChewBBACAinteger(value | Self::INFERRED))
+            }
+            InferredPolicy::Mismatch => Ok(ChewBBACAinteger(value)),
+        }
+    }
+
+    /// The allele number without the mark of an inferred allele, where 0 is missing
+    pub fn allele(&self) -> u16 {
+        self.0 & !Self::INFERRED
+    }
+}
+
 impl std::str::FromStr for ChewBBACAinteger {
     type Err = std::num::ParseIntError;
 
+    /// Parses an allele number. Inferred alleles match the allele with their number, and
+    /// anything that is not a number is missing.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.strip_prefix("INF-").unwrap_or(s);
         let value = u16::from_str(s).unwrap_or(0);
@@ -42,36 +422,135 @@ impl PartialEq for ChewBBACAinteger {
     }
 }
 
-// A type that can be used to represent a fixed byte array
-// and that can be parsed from a string of hex digits
+impl Site for ChewBBACAinteger {
+    fn is_missing(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// A hash of `N` bytes that is parsed from a string of hex digits. Shorter strings are padded
+/// with zeros, and the hash of only zeros is missing.
 #[derive(Debug, Clone, Copy)]
-pub struct SHA1Hash([u8; 20]);
+pub struct Hash<const N: usize>([u8; N]);
 
-impl std::str::FromStr for SHA1Hash {
-    type Err = std::num::ParseIntError;
+/// The SHA-1 hashes of the alleles, as written by chewBBACA
+pub type SHA1Hash = Hash<20>;
+/// The SHA-256 hashes of the alleles
+pub type SHA256Hash = Hash<32>;
+
+impl Hash<20> {
+    /// Pads the hash with zeros to the width of SHA-256, which gives the same hash as parsing its
+    /// hex digits as SHA-256
+    fn widen(&self) -> SHA256Hash {
+        let mut bytes = [0u8; 32];
+        bytes[..20].copy_from_slice(&self.0);
+        Hash(bytes)
+    }
+}
+
+/// The hex digits of a hash don't fit its width
+#[derive(Debug)]
+pub struct HashLengthError {
+    digits: usize,
+    width: usize,
+}
+
+impl std::fmt::Display for HashLengthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A hash with {} hex digits is longer than the {} digits of a {} byte hash",
+            self.digits,
+            self.width * 2,
+            self.width
+        )
+    }
+}
+
+impl std::error::Error for HashLengthError {}
+
+impl<const N: usize> std::str::FromStr for Hash<N> {
+    type Err = HashLengthError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut bytes = [0u8; 20];
+        if s.len() > 2 * N {
+            return Err(HashLengthError {
+                digits: s.len(),
+                width: N,
+            });
+        }
+        let mut bytes = [0u8; N];
         let len = s.len() / 2;
         for i in 0..len {
             bytes[i] = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap_or_default();
         }
-        Ok(SHA1Hash(bytes))
+        Ok(Hash(bytes))
+    }
+}
+
+impl<const N: usize> Hash<N> {
+    #[cfg(feature = "simd")]
+    pub(crate) fn bytes(&self) -> &[u8; N] {
+        &self.0
     }
 }
 
-impl PartialEq for SHA1Hash {
+impl<const N: usize> PartialEq for Hash<N> {
     fn eq(&self, other: &Self) -> bool {
-        if self.0 == [0; 20] || other.0 == [0; 20] {
+        if self.0 == [0; N] || other.0 == [0; N] {
             return true;
         }
         self.0 == other.0
     }
 }
 
+impl<const N: usize> Site for Hash<N> {
+    fn is_missing(&self) -> bool {
+        self.0 == [0; N]
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Nucleotide(u8);
 
+impl Nucleotide {
+    /// A gap matches everything like N, but has an extra bit so it can be told apart
+    const GAP: u8 = 31;
+    const N: u8 = 15;
+
+    fn is_gap(&self) -> bool {
+        self.0 == Self::GAP
+    }
+
+    /// Whether this is one of A, C, G or T
+    pub fn is_base(&self) -> bool {
+        self.0.count_ones() == 1
+    }
+
+    /// Whether the change between two different bases is a transition (A<->G or C<->T)
+    pub fn is_transition(&self, other: &Self) -> bool {
+        matches!(self.0 | other.0, 5 | 10)
+    }
+
+    /// Whether this is A or G
+    pub fn is_purine(&self) -> bool {
+        matches!(self.0, 1 | 4)
+    }
+
+    /// The index of the base in the order A, C, G, T, or None if this is not a single base
+    pub fn base_index(&self) -> Option<usize> {
+        self.is_base().then(|| self.0.trailing_zeros() as usize)
+    }
+
+    /// The IUPAC code of the mask, or '-' for a gap
+    pub fn to_iupac(&self) -> u8 {
+        if self.is_gap() {
+            return b'-';
+        }
+        b"-ACMGRSVTWYHKDBN"[(self.0 & Self::N) as usize]
+    }
+}
+
 impl std::str::FromStr for Nucleotide {
     type Err = std::num::ParseIntError;
 
@@ -81,7 +560,8 @@ impl std::str::FromStr for Nucleotide {
             "C" | "c" => Ok(Nucleotide(2)),
             "G" | "g" => Ok(Nucleotide(4)),
             "T" | "t" => Ok(Nucleotide(8)),
-            _ => Ok(Nucleotide(15)),
+            "-" => Ok(Nucleotide(Nucleotide::GAP)),
+            _ => Ok(Nucleotide(Nucleotide::N)),
         }
     }
 }
@@ -93,6 +573,35 @@ impl PartialEq for Nucleotide {
     }
 }
 
+impl Site for Nucleotide {
+    fn is_missing(&self) -> bool {
+        self.0 & Self::N == Self::N
+    }
+
+    #[inline]
+    fn is_missing_under(&self, policy: &ComparisonPolicy) -> bool {
+        if policy.strict_acgt {
+            return !self.is_base();
+        }
+        self.is_missing()
+    }
+
+    #[inline]
+    fn differs(&self, other: &Self, policy: &ComparisonPolicy) -> bool {
+        if policy.strict_acgt {
+            return self.is_base() && other.is_base() && self.0 != other.0;
+        }
+        if policy.gaps_as_diff && (self.is_gap() || other.is_gap()) {
+            // A gap is a real state that only matches another gap, or N when missing is skipped
+            return match policy.missing {
+                MissingPolicy::Skip => self.0 != other.0 && self.0 != Self::N && other.0 != Self::N,
+                MissingPolicy::Count => self.0 != other.0,
+            };
+        }
+        differs_by_missing_policy(self, other, policy.missing)
+    }
+}
+
 impl From<u8> for Nucleotide {
     fn from(value: u8) -> Self {
         // Static lookup table for nucleotide values
@@ -106,6 +615,7 @@ impl From<u8> for Nucleotide {
             lut[b'C' as usize] = Nucleotide(2);
             lut[b'G' as usize] = Nucleotide(4);
             lut[b'T' as usize] = Nucleotide(8);
+            lut[b'-' as usize] = Nucleotide(Nucleotide::GAP);
             lut
         };
 
@@ -113,17 +623,426 @@ impl From<u8> for Nucleotide {
     }
 }
 
+/// An uppercase amino acid residue. X and any other non-letter are an unknown residue.
+#[derive(Debug, Clone, Copy)]
+pub struct AminoAcid(u8);
+
+impl AminoAcid {
+    const X: u8 = b'X';
+    const GAP: u8 = b'-';
+    const STOP: u8 = b'*';
+
+    fn is_gap_or_stop(&self) -> bool {
+        self.0 == Self::GAP || self.0 == Self::STOP
+    }
+}
+
+impl PartialEq for AminoAcid {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_missing() || other.is_missing() || self.0 == other.0
+    }
+}
+
+impl Site for AminoAcid {
+    fn is_missing(&self) -> bool {
+        self.0 == Self::X || self.is_gap_or_stop()
+    }
+
+    #[inline]
+    fn differs(&self, other: &Self, policy: &ComparisonPolicy) -> bool {
+        if policy.gaps_as_diff && (self.is_gap_or_stop() || other.is_gap_or_stop()) {
+            // Like nucleotide gaps, gaps and stops only match themselves, or X when missing is skipped
+            return match policy.missing {
+                MissingPolicy::Skip => self.0 != other.0 && self.0 != Self::X && other.0 != Self::X,
+                MissingPolicy::Count => self.0 != other.0,
+            };
+        }
+        differs_by_missing_policy(self, other, policy.missing)
+    }
+}
+
+impl From<u8> for AminoAcid {
+    fn from(value: u8) -> Self {
+        match value {
+            b'-' | b'*' => AminoAcid(value),
+            _ if value.is_ascii_alphabetic() => AminoAcid(value.to_ascii_uppercase()),
+            _ => AminoAcid(AminoAcid::X),
+        }
+    }
+}
+
+/// A bottom-k MinHash sketch: the smallest hashes of the canonical k-mers of a sequence.
+/// K-mers with other characters than ACGT are skipped.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MinHashSketch {
+    /// The sorted distinct hashes
+    hashes: Vec<u64>,
+    size: usize,
+}
+
+impl MinHashSketch {
+    /// Builds the sketch of at most `size` hashes from the k-mers of length `k`, which must be
+    /// between 1 and 32. The hashes only depend on the k-mers and the seed, so sketches with the
+    /// same seed are the same on every machine and can be compared.
+    pub fn from_sequence(seq: &[u8], k: usize, size: usize, seed: u64) -> Self {
+        assert!((1..=32).contains(&k), "k must be between 1 and 32");
+        let mask = if k == 32 {
+            u64::MAX
+        } else {
+            (1 << (2 * k)) - 1
+        };
+        let shift = 2 * (k as u64 - 1);
+        // The seed is mixed first so that nearby seeds give unrelated hashes. A seed of 0 keeps
+        // the plain hash of the k-mer.
+        let seed = mix64(seed);
+        let mut hashes = BTreeSet::new();
+        let (mut forward, mut reverse, mut valid) = (0u64, 0u64, 0);
+
+        for &base in seq {
+            let code = match base {
+                b'A' | b'a' => 0,
+                b'C' | b'c' => 1,
+                b'G' | b'g' => 2,
+                b'T' | b't' => 3,
+                _ => {
+                    valid = 0;
+                    continue;
+                }
+            };
+            forward = (forward << 2 | code) & mask;
+            reverse = reverse >> 2 | (3 - code) << shift;
+            valid += 1;
+            if valid < k {
+                continue;
+            }
+            hashes.insert(mix64(forward.min(reverse) ^ seed));
+            if hashes.len() > size {
+                hashes.pop_last();
+            }
+        }
+        MinHashSketch {
+            hashes: hashes.into_iter().collect(),
+            size,
+        }
+    }
+
+    /// Estimates the Jaccard index from the bottom hashes of the union of both sketches.
+    /// Returns `None` when both sketches are empty.
+    pub fn jaccard(&self, other: &Self) -> Option<f64> {
+        let size = self.size.min(other.size);
+        let (mut i, mut j) = (0, 0);
+        let (mut union, mut shared) = (0, 0);
+        while union < size && (i < self.hashes.len() || j < other.hashes.len()) {
+            match (self.hashes.get(i), other.hashes.get(j)) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    i += 1;
+                    j += 1;
+                }
+                (Some(x), Some(y)) if x < y => i += 1,
+                (Some(_), None) => i += 1,
+                _ => j += 1,
+            }
+            union += 1;
+        }
+        (union > 0).then(|| shared as f64 / union as f64)
+    }
+}
+
+/// The finalizer of MurmurHash3, which spreads the bits of the k-mer over the whole hash
+fn mix64(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^ x >> 33
+}
+
+/// Nucleotides packed as 4-bit masks, 16 per word. Gaps share the mask of N, so they are kept
+/// in a separate bitmap that is only allocated when the sequence contains gaps.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PackedNucleotides {
+    words: Vec<u64>,
+    gaps: Vec<u64>,
+    len: usize,
+}
+
+impl PackedNucleotides {
+    const PER_WORD: usize = 16;
+    /// The lowest bit of every nibble
+    const LOW_BITS: u64 = 0x1111_1111_1111_1111;
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Nucleotide {
+        if self
+            .gaps
+            .get(index / 64)
+            .is_some_and(|w| w >> (index % 64) & 1 == 1)
+        {
+            return Nucleotide(Nucleotide::GAP);
+        }
+        let shift = (index % Self::PER_WORD) * 4;
+        Nucleotide((self.words[index / Self::PER_WORD] >> shift & 0xF) as u8)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Nucleotide> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Pairs the nucleotides of both sequences like `zip`, but replaces the leading and trailing
+    /// positions where either sequence has a gap by N, so they never count as a difference.
+    pub fn zip_without_terminal_gaps<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> impl Iterator<Item = (Nucleotide, Nucleotide)> + 'a {
+        let len = self.len.min(other.len);
+        let has_gap = |i: usize| self.get(i).is_gap() || other.get(i).is_gap();
+        let (start, end) = if self.gaps.is_empty() && other.gaps.is_empty() {
+            (0, len)
+        } else {
+            let start = (0..len).find(|&i| !has_gap(i)).unwrap_or(len);
+            let end = (start..len)
+                .rfind(|&i| !has_gap(i))
+                .map_or(start, |i| i + 1);
+            (start, end)
+        };
+        let n = Nucleotide(Nucleotide::N);
+        self.iter()
+            .zip(other.iter())
+            .enumerate()
+            .map(move |(i, pair)| {
+                if (start..end).contains(&i) {
+                    pair
+                } else {
+                    (n, n)
+                }
+            })
+    }
+
+    /// Counts the positions where the nucleotide masks don't overlap, which is the same as
+    /// counting `!=` position by position. Like `zip`, only the overlapping length is compared.
+    /// Counting stops once `maxdist` is reached, in which case `maxdist` is returned.
+    pub fn count_mismatches(&self, other: &Self, maxdist: usize) -> usize {
+        // Check the early stop every 64 words so the inner loop stays branch free
+        const WORDS_PER_CHUNK: usize = 64;
+        let mut count = 0;
+        for (chunk1, chunk2) in self
+            .words
+            .chunks(WORDS_PER_CHUNK)
+            .zip(other.words.chunks(WORDS_PER_CHUNK))
+        {
+            count += chunk1
+                .iter()
+                .zip(chunk2)
+                .map(|(x, y)| {
+                    let overlap = x & y;
+                    let matches =
+                        (overlap | overlap >> 1 | overlap >> 2 | overlap >> 3) & Self::LOW_BITS;
+                    Self::PER_WORD - matches.count_ones() as usize
+                })
+                .sum::<usize>();
+            if count >= maxdist {
+                return maxdist;
+            }
+        }
+        count
+    }
+}
+
+impl FromIterator<Nucleotide> for PackedNucleotides {
+    fn from_iter<I: IntoIterator<Item = Nucleotide>>(iter: I) -> Self {
+        let mut packed = PackedNucleotides::default();
+        let mut word = 0u64;
+        for nucleotide in iter {
+            let offset = packed.len % Self::PER_WORD;
+            if nucleotide.is_gap() {
+                let gap_word = packed.len / 64;
+                packed.gaps.resize(gap_word + 1, 0);
+                packed.gaps[gap_word] |= 1 << (packed.len % 64);
+            }
+            word |= u64::from(nucleotide.0 & Nucleotide::N) << (offset * 4);
+            packed.len += 1;
+            if offset == Self::PER_WORD - 1 {
+                packed.words.push(word);
+                word = 0;
+            }
+        }
+        let used = packed.len % Self::PER_WORD;
+        if used != 0 {
+            // Pad with N, which matches everything, so the padding is never counted
+            word |= u64::MAX << (used * 4);
+            packed.words.push(word);
+        }
+        packed
+    }
+}
+
+/// Whether a gene or other feature is present in a sample. It is never missing.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Presence(bool);
+
+impl Site for Presence {
+    fn is_missing(&self) -> bool {
+        false
+    }
+}
+
+impl std::str::FromStr for Presence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Presence(false)),
+            "1" => Ok(Presence(true)),
+            _ => Err(format!(
+                "Expected 0 or 1 for presence or absence, found '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Presence and absence packed as bits, 64 per word
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PackedPresence {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl PackedPresence {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Presence {
+        Presence(self.words[index / 64] >> (index % 64) & 1 == 1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Presence> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Counts the positions that differ, like `count_mismatches` of [`PackedNucleotides`]. Only
+    /// the overlapping length is compared, and counting stops once `maxdist` is reached.
+    pub fn count_mismatches(&self, other: &Self, maxdist: usize) -> usize {
+        const WORDS_PER_CHUNK: usize = 64;
+        let len = self.len.min(other.len);
+        let full_words = len / 64;
+        let mut count = 0;
+        for (chunk1, chunk2) in self.words[..full_words]
+            .chunks(WORDS_PER_CHUNK)
+            .zip(other.words[..full_words].chunks(WORDS_PER_CHUNK))
+        {
+            count += chunk1
+                .iter()
+                .zip(chunk2)
+                .map(|(x, y)| (x ^ y).count_ones() as usize)
+                .sum::<usize>();
+            if count >= maxdist {
+                return maxdist;
+            }
+        }
+        let rest = len % 64;
+        if rest != 0 {
+            // The longer row may have bits past the end of the shorter one in the last word
+            let mask = (1u64 << rest) - 1;
+            count +=
+                ((self.words[full_words] ^ other.words[full_words]) & mask).count_ones() as usize;
+        }
+        count.min(maxdist)
+    }
+
+    /// Counts the positions that are present in both rows and in either row, over the
+    /// overlapping length
+    pub fn intersection_and_union(&self, other: &Self) -> (usize, usize) {
+        let len = self.len.min(other.len);
+        let mut intersection = 0;
+        let mut union = 0;
+        for (i, (x, y)) in self.words.iter().zip(&other.words).enumerate() {
+            let used = len.saturating_sub(i * 64).min(64);
+            let mask = if used == 64 {
+                u64::MAX
+            } else {
+                (1u64 << used) - 1
+            };
+            intersection += (x & y & mask).count_ones() as usize;
+            union += ((x | y) & mask).count_ones() as usize;
+        }
+        (intersection, union)
+    }
+}
+
+impl FromIterator<Presence> for PackedPresence {
+    fn from_iter<I: IntoIterator<Item = Presence>>(iter: I) -> Self {
+        let mut packed = PackedPresence::default();
+        for presence in iter {
+            if packed.len % 64 == 0 {
+                packed.words.push(0);
+            }
+            if presence.0 {
+                packed.words[packed.len / 64] |= 1 << (packed.len % 64);
+            }
+            packed.len += 1;
+        }
+        packed
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct NucleotideAll(u8);
 
+impl NucleotideAll {
+    /// Sentinel for characters that are configured as gap or missing characters
+    pub const MISSING: NucleotideAll = NucleotideAll(0);
+
+    /// The byte of the character, or 0 if the position is missing
+    #[cfg(feature = "simd")]
+    pub(crate) fn byte(&self) -> u8 {
+        self.0
+    }
+
+    /// Builds a lookup table from bytes to nucleotides where the given characters are missing.
+    /// By default case is ignored, so soft-masked bases match their uppercase versions. When
+    /// case sensitive, `a` differs from `A` and only the missing characters as given are missing.
+    pub fn lookup_table(missing_chars: &[u8], case_sensitive: bool) -> [NucleotideAll; 256] {
+        let mut lut = [NucleotideAll::MISSING; 256];
+        for (value, entry) in lut.iter_mut().enumerate() {
+            *entry = if case_sensitive {
+                NucleotideAll(value as u8)
+            } else {
+                NucleotideAll::from(value as u8)
+            };
+        }
+        for &c in missing_chars {
+            lut[c as usize] = NucleotideAll::MISSING;
+            if !case_sensitive {
+                lut[c.to_ascii_lowercase() as usize] = NucleotideAll::MISSING;
+                lut[c.to_ascii_uppercase() as usize] = NucleotideAll::MISSING;
+            }
+        }
+        lut
+    }
+}
+
 impl std::str::FromStr for NucleotideAll {
     type Err = &'static str;
 
+    /// Parses the first byte of the string like [`From<u8>`], so case is ignored
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // get first char from str and put it in
-        s.chars()
+        s.bytes()
             .next()
-            .map(|c| Self(c.to_ascii_lowercase() as u8))
+            .map(Self::from)
             .ok_or("NucleotideAll::from_str: could not parse first char from string")
     }
 }
@@ -137,7 +1056,13 @@ impl From<u8> for NucleotideAll {
 
 impl PartialEq for NucleotideAll {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.0 == other.0 || self.0 == 0 || other.0 == 0
+    }
+}
+
+impl Site for NucleotideAll {
+    fn is_missing(&self) -> bool {
+        self.0 == 0
     }
 }
 
@@ -158,18 +1083,50 @@ mod tests {
         assert_eq!(x, ChewBBACAinteger(0));
     }
 
+    #[test]
+    fn test_chewbbaca_inferred_policy() {
+        let parse = |s, policy| ChewBBACAinteger::parse_inferred(s, policy).unwrap();
+        for policy in [
+            InferredPolicy::Match,
+            InferredPolicy::Mismatch,
+            InferredPolicy::Missing,
+        ] {
+            assert_eq!(parse("5", policy).0, 5);
+            assert_eq!(parse("-", policy).0, 0);
+        }
+        assert_eq!(parse("INF-5", InferredPolicy::Match).0, 5);
+        assert!(parse("INF-5", InferredPolicy::Missing).is_missing());
+
+        let inferred = parse("INF-5", InferredPolicy::Mismatch);
+        assert_ne!(inferred, parse("5", InferredPolicy::Mismatch));
+        assert_eq!(inferred, parse("INF-5", InferredPolicy::Mismatch));
+        assert!(!inferred.is_missing());
+        assert!(ChewBBACAinteger::parse_inferred("40000", InferredPolicy::Mismatch).is_err());
+        assert_eq!(parse("40000", InferredPolicy::Match).0, 40000);
+    }
+
     #[test]
     fn test_sha1_hash() {
         let x = SHA1Hash::from_str("6bc8d04609de559621859873ef301f221cf5d991").unwrap();
         assert_eq!(
             x,
-            SHA1Hash([
+            Hash([
                 0x6b, 0xc8, 0xd0, 0x46, 0x09, 0xde, 0x55, 0x96, 0x21, 0x85, 0x98, 0x73, 0xef, 0x30,
                 0x1f, 0x22, 0x1c, 0xf5, 0xd9, 0x91
             ])
         );
     }
 
+    #[test]
+    fn test_sha256_hash() {
+        let short = SHA1Hash::from_str("6bc8d04609de559621859873ef301f221cf5d991").unwrap();
+        let wide = SHA256Hash::from_str("6bc8d04609de559621859873ef301f221cf5d991").unwrap();
+        assert_eq!(short.widen().0, wide.0);
+        assert!(SHA256Hash::from_str("-").unwrap().is_missing());
+        assert!(SHA1Hash::from_str(&"a".repeat(64)).is_err());
+        assert!(SHA256Hash::from_str(&"a".repeat(64)).is_ok());
+    }
+
     #[test]
     fn test_nucleotide() {
         let x = Nucleotide::from_str("A").unwrap();
@@ -197,6 +1154,94 @@ mod tests {
         assert_eq!(x, Nucleotide::from(b't'));
     }
 
+    #[test]
+    fn test_nucleotide_transition() {
+        let a = Nucleotide::from(b'A');
+        let c = Nucleotide::from(b'C');
+        let g = Nucleotide::from(b'G');
+        let t = Nucleotide::from(b'T');
+        assert!(a.is_base() && c.is_base() && g.is_base() && t.is_base());
+        assert!(!Nucleotide::from(b'N').is_base());
+        assert!(!Nucleotide::from(b'-').is_base());
+        assert!(a.is_transition(&g));
+        assert!(c.is_transition(&t));
+        assert!(!a.is_transition(&c));
+        assert!(!a.is_transition(&t));
+        assert!(!g.is_transition(&c));
+        assert!(!g.is_transition(&t));
+    }
+
+    #[test]
+    fn test_nucleotide_gaps_as_diff() {
+        let a = Nucleotide::from(b'A');
+        let n = Nucleotide::from(b'N');
+        let gap = Nucleotide::from_str("-").unwrap();
+        assert_eq!(gap, Nucleotide::from(b'-'));
+
+        let default = ComparisonPolicy::default();
+        assert!(!gap.differs(&a, &default));
+        assert!(!gap.differs(&gap, &default));
+        assert!(!gap.differs(&n, &default));
+
+        let gaps_as_diff = ComparisonPolicy {
+            gaps_as_diff: true,
+            ..Default::default()
+        };
+        assert!(gap.differs(&a, &gaps_as_diff));
+        assert!(a.differs(&gap, &gaps_as_diff));
+        assert!(!gap.differs(&gap, &gaps_as_diff));
+        assert!(!gap.differs(&n, &gaps_as_diff));
+        assert!(!n.differs(&a, &gaps_as_diff));
+    }
+
+    #[test]
+    fn test_packed_nucleotides() {
+        let seq1 = b"ACGTNRYACGT-ACGTTTGACA-NNACGTACGAT";
+        let seq2 = b"ACGAN-YACCTGACGTTTGACAANNACGTACGTTACG";
+        let packed1: PackedNucleotides = seq1.iter().map(|&b| Nucleotide::from(b)).collect();
+        let packed2: PackedNucleotides = seq2.iter().map(|&b| Nucleotide::from(b)).collect();
+        assert_eq!(packed1.len(), seq1.len());
+
+        for (i, &b) in seq1.iter().enumerate() {
+            let nucleotide = packed1.get(i);
+            assert_eq!(nucleotide.0, Nucleotide::from(b).0);
+        }
+
+        let expected = seq1
+            .iter()
+            .zip(seq2.iter())
+            .filter(|(&x, &y)| Nucleotide::from(x) != Nucleotide::from(y))
+            .count();
+        assert_eq!(expected, 3);
+        assert_eq!(packed1.count_mismatches(&packed2, usize::MAX), expected);
+        assert_eq!(packed2.count_mismatches(&packed1, usize::MAX), expected);
+        assert_eq!(packed1.count_mismatches(&packed2, 2), 2);
+    }
+
+    #[test]
+    fn test_packed_presence() {
+        let parse = |s: &str| -> PackedPresence {
+            s.chars()
+                .map(|c| Presence::from_str(&c.to_string()).unwrap())
+                .collect()
+        };
+        let row1 = parse(&"0110".repeat(40));
+        let row2 = parse(&"0101".repeat(40));
+        assert_eq!(row1.len(), 160);
+        assert_eq!(row1.get(1), Presence(true));
+        assert_eq!(row1.get(3), Presence(false));
+        assert_eq!(row1.count_mismatches(&row2, usize::MAX), 80);
+        assert_eq!(row1.count_mismatches(&row2, 10), 10);
+
+        // Only the overlapping positions are compared
+        let short = parse(&"0101".repeat(20)[..70]);
+        assert_eq!(row1.count_mismatches(&short, usize::MAX), 34);
+        assert_eq!(short.count_mismatches(&row1, usize::MAX), 34);
+        assert_eq!(row1.intersection_and_union(&row2), (40, 120));
+        assert_eq!(row1.intersection_and_union(&short), (18, 52));
+        assert!(Presence::from_str("2").is_err());
+    }
+
     #[test]
     fn test_nucleotide_all() {
         let x = NucleotideAll::from_str("A").unwrap();
@@ -213,4 +1258,142 @@ mod tests {
         assert_ne!(x, NucleotideAll::from_str("t").unwrap());
         assert_ne!(x, NucleotideAll::from_str("g").unwrap());
     }
+
+    #[test]
+    fn test_nucleotide_all_case() {
+        // Both parsing paths ignore case
+        assert_eq!(
+            NucleotideAll::from_str("a").unwrap(),
+            NucleotideAll::from(b'A')
+        );
+        assert_eq!(
+            NucleotideAll::from_str("Acgt").unwrap(),
+            NucleotideAll::from(b'a')
+        );
+        assert!(NucleotideAll::from_str("").is_err());
+
+        let policy = ComparisonPolicy::default();
+        let lut = NucleotideAll::lookup_table(b"n", false);
+        assert!(!lut[b'a' as usize].differs(&lut[b'A' as usize], &policy));
+        assert!(lut[b'N' as usize].is_missing());
+
+        let lut = NucleotideAll::lookup_table(b"n", true);
+        assert!(lut[b'a' as usize].differs(&lut[b'A' as usize], &policy));
+        assert!(!lut[b'a' as usize].differs(&lut[b'a' as usize], &policy));
+        assert!(lut[b'n' as usize].is_missing());
+        assert!(!lut[b'N' as usize].is_missing());
+    }
+
+    #[test]
+    fn test_nucleotide_all_missing() {
+        let lut = NucleotideAll::lookup_table(b"-.n", false);
+        assert!(lut[b'-' as usize].is_missing());
+        assert!(lut[b'.' as usize].is_missing());
+        assert!(lut[b'N' as usize].is_missing());
+        assert!(!lut[b'a' as usize].is_missing());
+        assert_eq!(lut[b'A' as usize], NucleotideAll::from(b'a'));
+        assert_eq!(lut[b'-' as usize], NucleotideAll::from(b'a'));
+
+        let a = NucleotideAll::from(b'a');
+        let c = NucleotideAll::from(b'c');
+        let m = NucleotideAll::MISSING;
+        let skip = ComparisonPolicy::default();
+        let count = ComparisonPolicy {
+            missing: MissingPolicy::Count,
+            ..Default::default()
+        };
+        assert!(a.differs(&c, &skip));
+        assert!(!a.differs(&m, &skip));
+        assert!(a.differs(&m, &count));
+        assert!(!m.differs(&m, &count));
+    }
+
+    #[test]
+    fn test_homogeneous_matrix() {
+        let cgmlst = SupportedTypeVec::Cgmlst(vec![ChewBBACAinteger(1)]);
+        let hash = SupportedTypeVec::SHA1Hash(vec![Hash([1; 20])]);
+
+        let matrix = HomogeneousMatrix::try_from(vec![("S1".to_string(), cgmlst.clone())]).unwrap();
+        assert_eq!(matrix.len(), 1);
+        assert!(HomogeneousMatrix::try_from(InputMatrix::new()).is_ok());
+
+        let err = HomogeneousMatrix::try_from(vec![
+            ("S1".to_string(), cgmlst.clone()),
+            ("S2".to_string(), cgmlst),
+            ("S3".to_string(), hash),
+        ])
+        .unwrap_err();
+        assert_eq!(err, "Sample S3 has a different type than sample S1");
+    }
+
+    #[test]
+    fn test_amino_acid() {
+        let skip = ComparisonPolicy::default();
+        let gaps_as_diff = ComparisonPolicy {
+            gaps_as_diff: true,
+            ..Default::default()
+        };
+        let count = ComparisonPolicy {
+            missing: MissingPolicy::Count,
+            gaps_as_diff: true,
+            ..Default::default()
+        };
+
+        assert_eq!(AminoAcid::from(b'm'), AminoAcid::from(b'M'));
+        assert_ne!(AminoAcid::from(b'M'), AminoAcid::from(b'L'));
+        for unknown in [b'X', b'x', b'?', b'-', b'*'] {
+            assert!(AminoAcid::from(unknown).is_missing());
+            assert!(!AminoAcid::from(unknown).differs(&AminoAcid::from(b'L'), &skip));
+        }
+
+        let gap = AminoAcid::from(b'-');
+        let stop = AminoAcid::from(b'*');
+        let x = AminoAcid::from(b'X');
+        let leucine = AminoAcid::from(b'L');
+        assert!(gap.differs(&leucine, &gaps_as_diff));
+        assert!(stop.differs(&leucine, &gaps_as_diff));
+        assert!(gap.differs(&stop, &gaps_as_diff));
+        assert!(!gap.differs(&gap, &gaps_as_diff));
+        assert!(!gap.differs(&x, &gaps_as_diff));
+        assert!(gap.differs(&x, &count));
+        assert!(!x.differs(&leucine, &gaps_as_diff));
+    }
+
+    #[test]
+    fn test_minhash_sketch() {
+        let seq = b"ACGTTGCAAGGCTTAACGGA";
+        let sketch = MinHashSketch::from_sequence(seq, 5, 100, 0);
+        assert_eq!(sketch.jaccard(&sketch), Some(1.0));
+
+        // The reverse complement has the same canonical k-mers
+        let reverse_complement: Vec<u8> = seq
+            .iter()
+            .rev()
+            .map(|b| match b {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect();
+        let other = MinHashSketch::from_sequence(&reverse_complement, 5, 100, 0);
+        assert_eq!(sketch, other);
+
+        // N breaks the k-mers and the sketch is limited to the smallest hashes
+        assert!(MinHashSketch::from_sequence(b"ACGNTGCA", 5, 100, 0)
+            .hashes
+            .is_empty());
+        assert_eq!(MinHashSketch::from_sequence(seq, 5, 3, 0).hashes.len(), 3);
+
+        let unrelated = MinHashSketch::from_sequence(b"TTTTTTTTTT", 5, 100, 0);
+        assert_eq!(sketch.jaccard(&unrelated), Some(0.0));
+        let empty = MinHashSketch::from_sequence(b"", 5, 100, 0);
+        assert_eq!(empty.jaccard(&empty), None);
+
+        // The seed changes the hashes, but the same seed gives the same sketch
+        let seeded = MinHashSketch::from_sequence(seq, 5, 100, 7);
+        assert_eq!(seeded, MinHashSketch::from_sequence(seq, 5, 100, 7));
+        assert_ne!(seeded.hashes, sketch.hashes);
+        assert_eq!(seeded.hashes.len(), sketch.hashes.len());
+    }
 }
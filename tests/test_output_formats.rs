@@ -2,10 +2,12 @@ use std::fs::File;
 use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 
 use distle::processing::{
-    compute_distances, read_and_parse_fasta, read_and_parse_tabular, write_distances_to_file,
-    OutputFormat, OutputMode,
+    compute_distances, read_and_parse_fasta, read_and_parse_tabular, remove_identical_columns,
+    sample_ids, write_distances_to_file, DistanceOptions, OutputFormat, OutputMode, OutputOptions,
+    ParseOptions,
 };
-use distle::types::InputFormat;
+use distle::types::{ComparisonPolicy, InputFormat};
+use rayon::ThreadPoolBuilder;
 
 #[test]
 pub fn test_output_long() {
@@ -13,19 +15,23 @@ pub fn test_output_long() {
     let mut output = Cursor::new(Vec::new());
     let input_format = InputFormat::FastaAll;
     let output_format = OutputFormat::Tabular;
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::LowerTriangle;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_fasta(input, input_format).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let data_map = read_and_parse_fasta(input, input_format, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
     let expected = include_bytes!("data/output.tsv").to_vec();
@@ -42,19 +48,23 @@ pub fn test_output_long_all() {
     let mut output = Cursor::new(Vec::new());
     let input_format = InputFormat::FastaAll;
     let output_format = OutputFormat::Tabular;
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::Full;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_fasta(input, input_format).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let data_map = read_and_parse_fasta(input, input_format, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
     let expected = include_bytes!("data/output_full.tsv").to_vec();
@@ -71,19 +81,23 @@ pub fn test_output_phylip() {
     let mut output = Cursor::new(Vec::new());
     let input_format = InputFormat::FastaAll;
     let output_format = OutputFormat::Phylip;
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::LowerTriangle;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_fasta(input, input_format).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let data_map = read_and_parse_fasta(input, input_format, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
     let expected = include_bytes!("data/output.phylip").to_vec();
@@ -100,19 +114,23 @@ pub fn test_output_phylip_full() {
     let mut output = Cursor::new(Vec::new());
     let input_format = InputFormat::FastaAll;
     let output_format = OutputFormat::Phylip;
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::Full;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_fasta(input, input_format).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let data_map = read_and_parse_fasta(input, input_format, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
     let expected = include_bytes!("data/output_full.phylip").to_vec();
@@ -130,19 +148,27 @@ pub fn test_input_cgmlst_hash() {
     let input_format = InputFormat::CgmlstHash;
     let output_format = OutputFormat::Phylip;
     let input_sep = '\t';
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::LowerTriangle;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_tabular(input, input_format, input_sep, false).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let parse_options = ParseOptions {
+        separator: input_sep,
+        ..Default::default()
+    };
+    let data_map = read_and_parse_tabular(input, input_format, &parse_options).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
     let expected = include_bytes!("data/output_cgmlst_hash.phylip").to_vec();
@@ -160,19 +186,27 @@ pub fn test_input_cgmlst_hash_full() {
     let input_format = InputFormat::CgmlstHash;
     let output_format = OutputFormat::Phylip;
     let input_sep = '\t';
-    let output_sep = '\t';
+    let output_sep = "\t".to_string();
     let output_mode = OutputMode::Full;
-    let maxdist = None;
+    let options = DistanceOptions::default();
 
-    let data_map = read_and_parse_tabular(input, input_format, input_sep, false).unwrap();
-    // remove_identical_columns(&mut data_map);
-    let distances = compute_distances(&data_map, maxdist, output_mode, None);
+    let parse_options = ParseOptions {
+        separator: input_sep,
+        ..Default::default()
+    };
+    let data_map = read_and_parse_tabular(input, input_format, &parse_options).unwrap();
+    let distances = compute_distances(&data_map, &options, output_mode, None, None);
+    let output_options = OutputOptions {
+        output_format,
+        output_mode,
+        output_sep,
+        ..Default::default()
+    };
     write_distances_to_file(
         distances,
         &mut output,
-        output_sep,
-        output_format,
-        data_map.len(),
+        &sample_ids(&data_map),
+        &output_options,
     )
     .unwrap();
 
@@ -186,26 +220,495 @@ pub fn test_input_cgmlst_hash_full() {
     assert_eq!(expected, result);
 }
 
+#[test]
+pub fn test_input_cgmlst_hash_transposed() {
+    let input = BufReader::new(File::open("tests/data/cgmlst_hash_transposed.tsv").unwrap());
+    let mut output = Vec::new();
+    let parse_options = ParseOptions {
+        transpose: true,
+        ..Default::default()
+    };
+
+    let data_map = read_and_parse_tabular(input, InputFormat::CgmlstHash, &parse_options).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Phylip,
+        ..Default::default()
+    };
+    write_distances_to_file(
+        distances,
+        &mut output,
+        &sample_ids(&data_map),
+        &output_options,
+    )
+    .unwrap();
+
+    let expected = include_bytes!("data/output_cgmlst_hash.phylip").to_vec();
+    assert_eq!(expected, output);
+}
+
 #[test]
 pub fn test_remove_identical() {
     let input = BufReader::new(File::open("tests/data/test_remove_identical.fasta").unwrap());
-    let data_map = read_and_parse_fasta(input, InputFormat::Fasta).unwrap();
-    let data_map_with_removed_columns = data_map.clone();
-    // let _n_removed = remove_identical_columns(&mut data_map_with_removed_columns);
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    let mut data_map_with_removed_columns = data_map.clone();
+    let policy = ComparisonPolicy::default();
+    // The last position is past the end of the shorter samples and N in sampleB
+    let n_removed = remove_identical_columns(&mut data_map_with_removed_columns, &policy).unwrap();
+    assert_eq!(n_removed, 1);
+    assert_ne!(data_map, data_map_with_removed_columns);
 
-    // assert_eq!(data_map, data_map_with_removed_columns);
-
-    let dist_original: Vec<_> =
-        compute_distances(&data_map, None, OutputMode::LowerTriangle, None).collect();
+    let dist_original: Vec<_> = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    )
+    .collect();
     let dist_removed: Vec<_> = compute_distances(
         &data_map_with_removed_columns,
-        None,
+        &DistanceOptions::default(),
         OutputMode::LowerTriangle,
         None,
+        None,
     )
     .collect();
 
     assert_eq!(dist_original, dist_removed);
 
-    // let n_removed = remove_identical_columns(&mut data_map_with_removed_columns);
+    let n_removed = remove_identical_columns(&mut data_map_with_removed_columns, &policy).unwrap();
+    assert_eq!(n_removed, 0);
+}
+
+#[test]
+pub fn test_output_long_header() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let mut output = Cursor::new(Vec::new());
+    let header = vec!["a".to_string(), "b".to_string(), "dist".to_string()];
+
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::FastaAll, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let output_options = OutputOptions {
+        header: Some(header),
+        ..Default::default()
+    };
+    write_distances_to_file(
+        distances,
+        &mut output,
+        &sample_ids(&data_map),
+        &output_options,
+    )
+    .unwrap();
+    let mut expected = b"a\tb\tdist\n".to_vec();
+    expected.extend_from_slice(include_bytes!("data/output.tsv"));
+    let result = output.into_inner();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+pub fn test_output_matrix() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let mut output = Cursor::new(Vec::new());
+
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::FastaAll, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::Full,
+        None,
+        None,
+    );
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Matrix,
+        output_mode: OutputMode::Full,
+        ..Default::default()
+    };
+    write_distances_to_file(
+        distances,
+        &mut output,
+        &sample_ids(&data_map),
+        &output_options,
+    )
+    .unwrap();
+    let expected = include_bytes!("data/output_matrix.tsv").to_vec();
+    let result = output.into_inner();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+pub fn test_output_matrix_requires_full() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let mut output = Cursor::new(Vec::new());
+
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::FastaAll, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Matrix,
+        ..Default::default()
+    };
+    let result = write_distances_to_file(
+        distances,
+        &mut output,
+        &sample_ids(&data_map),
+        &output_options,
+    );
+
+    assert!(result.is_err());
+}
+
+#[test]
+pub fn test_output_phylip_similar_names() {
+    let input = b">S1\nACGT\n>S1_b\nACGA\n";
+    let options = OutputOptions {
+        output_format: OutputFormat::Phylip,
+        ..Default::default()
+    };
+
+    let data_map =
+        read_and_parse_fasta(&input[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &sample_ids(&data_map), &options).unwrap();
+    assert_eq!(output, b"2\nS1\nS1_b\t1\n");
+
+    // Identical names and a single sample still produce every row
+    let input = b">S1\nACGT\n>S1\nACGA\n";
+    let parse_options = ParseOptions {
+        allow_duplicate_ids: true,
+        ..Default::default()
+    };
+    let data_map = read_and_parse_fasta(&input[..], InputFormat::Fasta, &parse_options).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &sample_ids(&data_map), &options).unwrap();
+    assert_eq!(output, b"2\nS1\nS1\t1\n");
+
+    let distances = std::iter::empty();
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &["S1"], &options).unwrap();
+    assert_eq!(output, b"1\nS1\n");
+}
+
+#[test]
+pub fn test_output_nexus() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let mut output = Cursor::new(Vec::new());
+
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::FastaAll, &ParseOptions::default()).unwrap();
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Nexus,
+        ..Default::default()
+    };
+    write_distances_to_file(
+        distances,
+        &mut output,
+        &sample_ids(&data_map),
+        &output_options,
+    )
+    .unwrap();
+    let expected = include_bytes!("data/output.nexus").to_vec();
+    let result = output.into_inner();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+pub fn test_output_independent_of_threads() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::FastaAll, &ParseOptions::default()).unwrap();
+
+    let outputs: Vec<Vec<u8>> = [1, 2, 8]
+        .iter()
+        .map(|&threads| {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let distances = compute_distances(
+                    &data_map,
+                    &DistanceOptions::default(),
+                    OutputMode::Full,
+                    None,
+                    None,
+                );
+                let mut output = Vec::new();
+                write_distances_to_file(
+                    distances,
+                    &mut output,
+                    &sample_ids(&data_map),
+                    &OutputOptions::default(),
+                )
+                .unwrap();
+                output
+            })
+        })
+        .collect();
+
+    assert_eq!(outputs[0], include_bytes!("data/output_full.tsv").to_vec());
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[test]
+pub fn test_sketch_output_reproducible() {
+    let input = std::fs::read("tests/data/input.fasta").unwrap();
+    let options = ParseOptions {
+        sketch_k: 3,
+        sketch_size: 3,
+        sketch_seed: 42,
+        ..Default::default()
+    };
+
+    let outputs: Vec<Vec<u8>> = [1, 4, 1]
+        .iter()
+        .map(|&threads| {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let data_map =
+                    read_and_parse_fasta(&input[..], InputFormat::FastaSketch, &options).unwrap();
+                let distances = compute_distances(
+                    &data_map,
+                    &DistanceOptions::default(),
+                    OutputMode::Full,
+                    None,
+                    None,
+                );
+                let mut output = Vec::new();
+                write_distances_to_file(
+                    distances,
+                    &mut output,
+                    &sample_ids(&data_map),
+                    &OutputOptions::default(),
+                )
+                .unwrap();
+                output
+            })
+        })
+        .collect();
+
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[test]
+pub fn test_output_long_diagonal() {
+    let input = BufReader::new(File::open("tests/data/input.fasta").unwrap());
+    let data_map =
+        read_and_parse_fasta(input, InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    let ids = sample_ids(&data_map);
+
+    let outputs: Vec<String> = [OutputMode::LowerTriangle, OutputMode::LowerTriangleDiag]
+        .into_iter()
+        .map(|output_mode| {
+            let distances = compute_distances(
+                &data_map,
+                &DistanceOptions::default(),
+                output_mode,
+                None,
+                None,
+            );
+            let output_options = OutputOptions {
+                output_mode,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write_distances_to_file(distances, &mut output, &ids, &output_options).unwrap();
+            String::from_utf8(output).unwrap()
+        })
+        .collect();
+
+    let lines: Vec<&str> = outputs[1].lines().collect();
+    assert_eq!(lines.len(), outputs[0].lines().count() + ids.len());
+    for id in &ids {
+        assert!(lines.contains(&format!("{}\t{}\t0", id, id).as_str()));
+    }
+    let without_diagonal: Vec<&str> = lines
+        .into_iter()
+        .filter(|line| {
+            let mut fields = line.split('\t');
+            fields.next() != fields.next()
+        })
+        .collect();
+    assert_eq!(without_diagonal, outputs[0].lines().collect::<Vec<_>>());
+}
+
+#[test]
+pub fn test_output_empty_input() {
+    let data_map =
+        read_and_parse_fasta(&b""[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    assert!(data_map.is_empty());
+
+    let expected: [(OutputFormat, &[u8]); 5] = [
+        (OutputFormat::Tabular, b""),
+        (OutputFormat::Phylip, b"0\n"),
+        (OutputFormat::Matrix, b""),
+        (OutputFormat::Condensed, b""),
+        (OutputFormat::Binary, b"DSTL\x01\0\0\0\0\0\0\0"),
+    ];
+    for (output_format, expected) in expected {
+        let output_mode = match output_format {
+            OutputFormat::Matrix => OutputMode::Full,
+            _ => OutputMode::LowerTriangle,
+        };
+        let distances = compute_distances(
+            &data_map,
+            &DistanceOptions::default(),
+            output_mode,
+            None,
+            None,
+        );
+        let output_options = OutputOptions {
+            output_format,
+            output_mode,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(distances, &mut output, &[], &output_options).unwrap();
+        assert_eq!(output, expected, "{:?}", output_format);
+    }
+
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Nexus,
+        ..Default::default()
+    };
+    let distances = compute_distances(
+        &data_map,
+        &DistanceOptions::default(),
+        OutputMode::LowerTriangle,
+        None,
+        None,
+    );
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &[], &output_options).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("DIMENSIONS ntax=0;").count(), 2);
+    assert!(output.trim_end().ends_with("END;"));
+}
+
+#[test]
+pub fn test_output_ndjson() {
+    let data = b">S1\nACGT\n>S\"2\nACGA\n>S3\nNNNN\n";
+    let data_map =
+        read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    let options = DistanceOptions {
+        min_shared: Some(1),
+        ..Default::default()
+    };
+    let distances = compute_distances(&data_map, &options, OutputMode::LowerTriangle, None, None);
+    let output_options = OutputOptions {
+        output_format: OutputFormat::Ndjson,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &[], &output_options).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "{\"id1\":\"S\\\"2\",\"id2\":\"S1\",\"distance\":1}\n\
+         {\"id1\":\"S3\",\"id2\":\"S1\",\"distance\":null}\n\
+         {\"id1\":\"S3\",\"id2\":\"S\\\"2\",\"distance\":null}\n"
+    );
+}
+
+#[test]
+pub fn test_output_transposed() {
+    let data = b">S1\nACGT\n>S2\nACGA\n>S3\nTCGA\n";
+    let data_map =
+        read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    for (output_format, expected) in [
+        (OutputFormat::Tabular, "S1\tS2\t1\nS1\tS3\t2\nS2\tS3\t1\n"),
+        (
+            OutputFormat::Ndjson,
+            "{\"id1\":\"S1\",\"id2\":\"S2\",\"distance\":1}\n\
+             {\"id1\":\"S1\",\"id2\":\"S3\",\"distance\":2}\n\
+             {\"id1\":\"S2\",\"id2\":\"S3\",\"distance\":1}\n",
+        ),
+    ] {
+        let distances = compute_distances(
+            &data_map,
+            &DistanceOptions::default(),
+            OutputMode::LowerTriangle,
+            None,
+            None,
+        );
+        let output_options = OutputOptions {
+            output_format,
+            transpose: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_distances_to_file(distances, &mut output, &[], &output_options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
+
+#[test]
+pub fn test_output_snp_distance() {
+    // s2 has an ambiguous base, an N and a gap, which are never counted
+    let data = b">ref\nACGTACGTAC\n>s1\nACGAACGTTC\n>s2\nACRTNCGT-C\n>s3\nACGTACCTAC\n";
+    let data_map =
+        read_and_parse_fasta(&data[..], InputFormat::Fasta, &ParseOptions::default()).unwrap();
+    let options = DistanceOptions {
+        policy: ComparisonPolicy {
+            strict_acgt: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let distances = compute_distances(&data_map, &options, OutputMode::LowerTriangle, None, None);
+    let mut output = Vec::new();
+    write_distances_to_file(distances, &mut output, &[], &OutputOptions::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "s1\tref\t2\ns2\tref\t0\ns2\ts1\t1\ns3\tref\t1\ns3\ts1\t3\ns3\ts2\t1\n"
+    );
 }